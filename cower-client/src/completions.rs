@@ -0,0 +1,181 @@
+//! Shell completions. `cower-client completions <shell>` prints a script that calls back into the
+//! client whenever the shell completes its command line, so that besides subcommands and flags,
+//! targets are completed from the configuration file and resources are listed by the target
+//! itself.
+
+use std::{env, ffi::OsString, io, path::PathBuf, time::Duration};
+
+use anyhow::anyhow;
+use clap::{CommandFactory, Parser, ValueEnum};
+use clap_complete::{CompleteEnv, CompletionCandidate, env::Shells};
+use cower_common::Timeouts;
+
+use crate::{Args, load_config, open};
+
+/// The environment variable the scripts set when calling back into the client
+const VAR: &str = "COMPLETE";
+
+/// Completing has to be quick, so targets that don't answer right away are given up on
+const TIMEOUTS: Timeouts = Timeouts {
+    handshake: Some(Duration::from_secs(2)),
+    read: Some(Duration::from_secs(2)),
+    write: Some(Duration::from_secs(2)),
+    idle: None,
+};
+
+/// Shells completions can be set up for
+#[derive(ValueEnum, Clone, Copy, Debug)]
+pub enum Shell {
+    Bash,
+    Elvish,
+    Fish,
+    Powershell,
+    Zsh,
+}
+
+impl Shell {
+    fn name(self) -> &'static str {
+        match self {
+            Self::Bash => "bash",
+            Self::Elvish => "elvish",
+            Self::Fish => "fish",
+            Self::Powershell => "powershell",
+            Self::Zsh => "zsh",
+        }
+    }
+}
+
+/// Answers the shell and exits if the client was called back to complete something
+pub fn complete() {
+    CompleteEnv::with_factory(Args::command).var(VAR).complete();
+}
+
+/// Prints the script setting completions up for `shell`
+pub fn write_registration(shell: Shell) -> anyhow::Result<()> {
+    let shells = Shells::builtins();
+    let completer = shells
+        .completer(shell.name())
+        .ok_or(anyhow!("Completions for {} aren't supported", shell.name()))?;
+    let bin = Args::command().get_name().to_owned();
+    let executable = env::current_exe()?;
+
+    completer.write_registration(
+        VAR,
+        &bin,
+        &bin,
+        &executable.to_string_lossy(),
+        &mut io::stdout(),
+    )?;
+
+    Ok(())
+}
+
+/// The words of the command line being completed, up to the one under the cursor
+fn words_before_cursor() -> Vec<OsString> {
+    let mut words: Vec<OsString> = env::args_os()
+        .skip_while(|arg| arg != "--")
+        .skip(1)
+        .collect();
+    // fish doesn't say where the cursor is, but always completes the last word
+    let index = env::var("_CLAP_COMPLETE_INDEX")
+        .ok()
+        .and_then(|index| index.parse().ok())
+        .unwrap_or(words.len().saturating_sub(1));
+    words.truncate(index);
+
+    words
+}
+
+/// Names of the configured targets, and aliases of the default target's resources
+pub fn targets() -> Vec<CompletionCandidate> {
+    // the target is still missing, so only the flags can be looked at
+    let matches = Args::command()
+        .ignore_errors(true)
+        .try_get_matches_from(words_before_cursor());
+    let config_path = matches
+        .ok()
+        .and_then(|matches| matches.get_one::<PathBuf>("config").cloned());
+    let Ok(config) = load_config(config_path) else {
+        return vec![];
+    };
+
+    let mut candidates: Vec<CompletionCandidate> = config
+        .targets
+        .iter()
+        .map(|(name, settings)| {
+            CompletionCandidate::new(name).help(Some(settings.address.clone().into()))
+        })
+        .collect();
+
+    if let Some(default) = &config.default_target
+        && let Some(settings) = config.targets.get(default)
+    {
+        candidates.extend(settings.aliases.iter().map(|(alias, resource)| {
+            CompletionCandidate::new(alias).help(Some(format!("{resource} on {default}").into()))
+        }));
+    }
+
+    candidates.sort_by(|a, b| a.get_value().cmp(b.get_value()));
+
+    candidates
+}
+
+/// Aliases from the configuration file and the resources the target lists
+pub fn resources() -> Vec<CompletionCandidate> {
+    let Ok(args) = Args::try_parse_from(words_before_cursor()) else {
+        return vec![];
+    };
+    let Ok(config) = load_config(args.config.clone()) else {
+        return vec![];
+    };
+
+    let mut candidates = vec![];
+
+    let settings = args
+        .command
+        .target()
+        .and_then(|target| config.resolve(target.target.as_deref()?).ok())
+        .and_then(|target| target.settings);
+    if let Some(settings) = settings {
+        let mut aliases: Vec<_> = settings.aliases.iter().collect();
+        aliases.sort();
+        candidates.extend(aliases.into_iter().map(|(alias, resource)| {
+            CompletionCandidate::new(alias).help(Some(format!("alias of {resource}").into()))
+        }));
+    }
+
+    if let Ok((_, mut client)) = open(&args, &config, TIMEOUTS, true) {
+        let resources = client.list().unwrap_or_default();
+        candidates.extend(resources.into_iter().map(|resource| {
+            let help = resource
+                .description
+                .or(resource.state.map(|state| format!("{state:?}")));
+            CompletionCandidate::new(resource.name).help(help.map(Into::into))
+        }));
+
+        _ = client.close();
+    }
+
+    candidates
+}
+
+#[cfg(test)]
+mod completions_tests {
+    use clap::{CommandFactory, ValueEnum};
+    use clap_complete::env::Shells;
+
+    use super::Shell;
+    use crate::Args;
+
+    #[test]
+    fn supported_shells() {
+        Args::command().debug_assert();
+
+        for shell in Shell::value_variants() {
+            assert!(
+                Shells::builtins().completer(shell.name()).is_some(),
+                "{shell:?} isn't supported"
+            );
+        }
+    }
+}
@@ -0,0 +1,352 @@
+//! The client's configuration file, `$XDG_CONFIG_HOME/cower/config.toml` by default. It holds
+//! named targets, so that they don't have to be spelled out on every run:
+//!
+//! ```toml
+//! default_target = "home"
+//!
+//! [targets.home]
+//! address = "example.com:9989"
+//! domain = "cower.example.com"
+//! pin_spki = "3f0a..."
+//! aliases = { mc = "minecraft" }
+//! ```
+//!
+//! With this, `cower-client start home minecraft` and `cower-client start mc` do the same thing.
+//!
+//! Settings are layered: flags on the command line win over environment variables, which win over
+//! the file.
+
+use std::{
+    collections::HashMap,
+    env, fs, io,
+    path::{Path, PathBuf},
+};
+
+use anyhow::anyhow;
+use cower_common::tls::Pin;
+use serde::{
+    Deserialize, Deserializer,
+    de::{Error as _, IgnoredAny},
+};
+
+use crate::target::Target;
+
+/// `$XDG_CONFIG_HOME/cower`, falling back to `~/.config/cower`
+pub fn config_dir() -> Option<PathBuf> {
+    let config_dir = env::var_os("XDG_CONFIG_HOME")
+        .filter(|dir| !dir.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| env::var_os("HOME").map(|home| PathBuf::from(home).join(".config")))?;
+
+    Some(config_dir.join("cower"))
+}
+
+/// `$XDG_CONFIG_HOME/cower/config.toml`
+pub fn default_path() -> Option<PathBuf> {
+    config_dir().map(|dir| dir.join("config.toml"))
+}
+
+/// Settings of a single named target
+#[derive(Deserialize, Clone, Debug, Default)]
+#[serde(deny_unknown_fields)]
+pub struct TargetConfig {
+    /// Anything that can be given as a target on the command line, except for names of other
+    /// targets
+    pub address: String,
+    /// Server name to check the target's certificate against, like `--sni`
+    pub domain: Option<String>,
+    /// Path to a custom certificate, like `--cert-path`. Relative paths are relative to the
+    /// configuration file
+    pub cert_path: Option<PathBuf>,
+    /// Fingerprint of the target's certificate, like `--pin-cert`
+    pub pin_cert: Option<String>,
+    /// Fingerprint of the target's public key, like `--pin-spki`
+    pub pin_spki: Option<String>,
+    /// Trust the target on first use, like `--tofu`
+    #[serde(default)]
+    pub tofu: bool,
+    /// Short names of resources, mapped to their actual names
+    #[serde(default)]
+    pub aliases: HashMap<String, String>,
+    // rejected rather than ignored, so that nobody relies on them
+    #[serde(default, rename = "identity", deserialize_with = "client_auth")]
+    _identity: Option<IgnoredAny>,
+    #[serde(default, rename = "token", deserialize_with = "client_auth")]
+    _token: Option<IgnoredAny>,
+}
+
+/// Fails for the settings of client authentication, which targets don't support yet
+fn client_auth<'de, D: Deserializer<'de>>(_: D) -> Result<Option<IgnoredAny>, D::Error> {
+    Err(D::Error::custom(
+        "targets don't support client authentication yet",
+    ))
+}
+
+/// How a target's certificate is checked, before any certificate is loaded
+#[derive(Clone, Debug)]
+pub enum Verification {
+    /// Validation against the root certificates, with an optional extra one
+    CertificateAuthority(Option<PathBuf>),
+    /// The certificate has to match the pin
+    Pinned(Pin),
+    /// Trust the target on first use, then pin its public key
+    TrustOnFirstUse,
+}
+
+impl TargetConfig {
+    /// How the file says the target's certificate should be checked, if it says anything
+    pub fn verification(&self) -> anyhow::Result<Option<Verification>> {
+        let verification = match (&self.pin_cert, &self.pin_spki) {
+            (Some(_), Some(_)) => {
+                return Err(anyhow!("Only one of pin_cert and pin_spki can be set"));
+            }
+            (Some(pin), None) => Some(Verification::Pinned(Pin::Certificate(pin.parse()?))),
+            (None, Some(pin)) => Some(Verification::Pinned(Pin::Spki(pin.parse()?))),
+            (None, None) if self.tofu => Some(Verification::TrustOnFirstUse),
+            (None, None) => None,
+        };
+
+        if verification.is_some() && self.cert_path.is_some() {
+            return Err(anyhow!("cert_path can't be combined with pins or tofu"));
+        }
+
+        Ok(verification.or_else(|| {
+            self.cert_path
+                .clone()
+                .map(|path| Verification::CertificateAuthority(Some(path)))
+        }))
+    }
+}
+
+/// Picks how the target's certificate is checked. The command line wins over the certificate in
+/// `COWER_CERT`, which wins over the configuration file
+pub fn layer_verification(
+    command_line: Option<Verification>,
+    env_cert: Option<PathBuf>,
+    settings: Option<&TargetConfig>,
+) -> anyhow::Result<Verification> {
+    if let Some(verification) = command_line {
+        return Ok(verification);
+    }
+    if let Some(cert_path) = env_cert {
+        return Ok(Verification::CertificateAuthority(Some(cert_path)));
+    }
+
+    let from_file = settings.map(TargetConfig::verification).transpose()?;
+
+    Ok(from_file
+        .flatten()
+        .unwrap_or(Verification::CertificateAuthority(None)))
+}
+
+/// The whole client configuration
+#[derive(Deserialize, Clone, Debug, Default)]
+#[serde(deny_unknown_fields)]
+pub struct Config {
+    /// Target whose aliases can be used in place of a target
+    pub default_target: Option<String>,
+    /// Targets by name
+    #[serde(default)]
+    pub targets: HashMap<String, TargetConfig>,
+}
+
+/// A target given on the command line, together with its settings if it's a named one
+#[derive(Debug)]
+pub struct ResolvedTarget<'a> {
+    /// Where to connect to, with the resource an alias stands for
+    pub target: Target,
+    /// Settings of the named target, if it's one
+    pub settings: Option<&'a TargetConfig>,
+}
+
+impl ResolvedTarget<'_> {
+    /// The actual name of `resource`, which may be an alias
+    pub fn resource_name(&self, resource: String) -> String {
+        self.settings
+            .and_then(|settings| settings.aliases.get(&resource))
+            .cloned()
+            .unwrap_or(resource)
+    }
+
+    /// The resource to act on, given either with the target or as an argument
+    pub fn resource(&self, resource: Option<String>) -> anyhow::Result<String> {
+        match (self.target.resource.clone(), resource) {
+            (Some(_), Some(_)) => Err(anyhow!("The resource is given both in the URL and apart")),
+            (Some(resource), None) => Ok(resource),
+            (None, Some(resource)) => Ok(self.resource_name(resource)),
+            (None, None) => Err(anyhow!("Missing the name of the resource")),
+        }
+    }
+}
+
+impl Config {
+    /// Loads the configuration from a TOML file. A missing file is the same as an empty one
+    pub fn load(path: &Path) -> anyhow::Result<Self> {
+        let contents = match fs::read_to_string(path) {
+            Ok(contents) => contents,
+            Err(why) if why.kind() == io::ErrorKind::NotFound => return Ok(Self::default()),
+            Err(why) => return Err(why.into()),
+        };
+
+        let mut config: Self =
+            toml::from_str(&contents).map_err(|why| anyhow!("{}: {why}", path.display()))?;
+        if let Some(dir) = path.parent() {
+            for target in config.targets.values_mut() {
+                target.cert_path = target.cert_path.take().map(|cert| dir.join(cert));
+            }
+        }
+
+        Ok(config)
+    }
+
+    fn parse_address(name: &str, settings: &TargetConfig) -> anyhow::Result<Target> {
+        settings
+            .address
+            .parse()
+            .map_err(|why| anyhow!("Invalid address of target {name}: {why}"))
+    }
+
+    /// Figures out what was given on the command line as a target: the name of a configured
+    /// target, an alias of a resource on the default target, or an address
+    pub fn resolve(&self, target: &str) -> anyhow::Result<ResolvedTarget<'_>> {
+        if let Some(settings) = self.targets.get(target) {
+            return Ok(ResolvedTarget {
+                target: Self::parse_address(target, settings)?,
+                settings: Some(settings),
+            });
+        }
+
+        if let Some(default) = &self.default_target {
+            let settings = self
+                .targets
+                .get(default)
+                .ok_or(anyhow!("The default target {default} isn't configured"))?;
+
+            if let Some(resource) = settings.aliases.get(target) {
+                let mut resolved = Self::parse_address(default, settings)?;
+                resolved.resource = Some(resource.clone());
+
+                return Ok(ResolvedTarget {
+                    target: resolved,
+                    settings: Some(settings),
+                });
+            }
+        }
+
+        Ok(ResolvedTarget {
+            target: target.parse()?,
+            settings: None,
+        })
+    }
+}
+
+#[cfg(test)]
+mod config_tests {
+    use std::path::{Path, PathBuf};
+
+    use cower_common::tls::{Fingerprint, Pin};
+
+    use super::{Config, Verification, layer_verification};
+    use crate::target::Address;
+
+    fn config() -> anyhow::Result<Config> {
+        let pin = Fingerprint::of(b"home");
+
+        Ok(toml::from_str(&format!(
+            r#"
+            default_target = "home"
+
+            [targets.home]
+            address = "example.com:1234"
+            domain = "cower.example.com"
+            pin_spki = "{pin}"
+            aliases = {{ mc = "minecraft" }}
+
+            [targets.work]
+            address = "cower://work.example.com/builder"
+            cert_path = "/etc/cower/work.crt"
+            "#
+        ))?)
+    }
+
+    #[test]
+    fn resolve_targets() -> anyhow::Result<()> {
+        let config = config()?;
+
+        let home = config.resolve("home")?;
+        assert_eq!(
+            home.target.address,
+            Address::Tcp {
+                host: "example.com".to_owned(),
+                port: 1234
+            }
+        );
+        assert_eq!(home.resource(Some("mc".to_owned()))?, "minecraft");
+        assert_eq!(home.resource(Some("web".to_owned()))?, "web");
+        assert!(home.resource(None).is_err());
+
+        // aliases of the default target work in place of a target
+        let mc = config.resolve("mc")?;
+        assert_eq!(mc.target.address, home.target.address);
+        assert_eq!(mc.resource(None)?, "minecraft");
+        assert!(mc.resource(Some("web".to_owned())).is_err());
+
+        let work = config.resolve("work")?;
+        assert_eq!(work.resource(None)?, "builder");
+
+        let other = config.resolve("other.example.com")?;
+        assert!(other.settings.is_none());
+        assert_eq!(other.resource(Some("mc".to_owned()))?, "mc");
+
+        Ok(())
+    }
+
+    #[test]
+    fn layered_verification() -> anyhow::Result<()> {
+        let config = config()?;
+        let home = config.targets.get("home");
+        let work = config.targets.get("work");
+        let env_cert = Some(PathBuf::from("env.crt"));
+
+        assert!(matches!(
+            layer_verification(Some(Verification::TrustOnFirstUse), env_cert.clone(), home)?,
+            Verification::TrustOnFirstUse
+        ));
+        assert!(matches!(
+            layer_verification(None, env_cert.clone(), home)?,
+            Verification::CertificateAuthority(Some(path)) if path == Path::new("env.crt")
+        ));
+        assert!(matches!(
+            layer_verification(None, None, home)?,
+            Verification::Pinned(Pin::Spki(_))
+        ));
+        assert!(matches!(
+            layer_verification(None, None, work)?,
+            Verification::CertificateAuthority(Some(path)) if path == Path::new("/etc/cower/work.crt")
+        ));
+        assert!(matches!(
+            layer_verification(None, None, None)?,
+            Verification::CertificateAuthority(None)
+        ));
+
+        Ok(())
+    }
+
+    #[test]
+    fn reject_unknown_settings() {
+        let typo = toml::from_str::<Config>("[targets.home]\naddress = \"home\"\npin_spk = \"\"");
+        assert!(typo.is_err());
+        let typo = toml::from_str::<Config>("default = \"home\"");
+        assert!(typo.is_err());
+
+        for setting in ["identity = \"client.p12\"", "token = \"secret\""] {
+            let why =
+                toml::from_str::<Config>(&format!("[targets.home]\naddress = \"home\"\n{setting}"))
+                    .expect_err("client authentication was accepted");
+            assert!(
+                why.to_string()
+                    .contains("targets don't support client authentication yet")
+            );
+        }
+    }
+}
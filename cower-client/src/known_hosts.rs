@@ -0,0 +1,99 @@
+//! A `known_hosts`-style file storing the fingerprints of targets the client trusted on first use,
+//! like SSH does.
+//!
+//! Every line holds one target: its address, the kind of fingerprint and the fingerprint itself,
+//! separated by whitespace. Empty lines and lines starting with `#` are ignored.
+//!
+//! ```text
+//! 127.0.0.1:9989 spki-sha256 3f0a...
+//! ```
+
+use std::{
+    fs::{self, OpenOptions},
+    io::{self, Write},
+    path::{Path, PathBuf},
+};
+
+use anyhow::anyhow;
+use cower_common::tls::Fingerprint;
+
+const SPKI_SHA256: &str = "spki-sha256";
+
+/// `$XDG_CONFIG_HOME/cower/known_hosts`, falling back to `~/.config/cower/known_hosts`
+pub fn default_path() -> Option<PathBuf> {
+    cower_client::config::config_dir().map(|dir| dir.join("known_hosts"))
+}
+
+/// Looks up the SPKI fingerprint recorded for `host`. A missing file means that no host is known
+pub fn lookup(path: &Path, host: &str) -> anyhow::Result<Option<Fingerprint>> {
+    let contents = match fs::read_to_string(path) {
+        Ok(contents) => contents,
+        Err(why) if why.kind() == io::ErrorKind::NotFound => return Ok(None),
+        Err(why) => return Err(why.into()),
+    };
+
+    for (number, line) in contents.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        let fields: Vec<&str> = line.split_whitespace().collect();
+        let [entry_host, kind, fingerprint] = fields[..] else {
+            return Err(anyhow!(
+                "{}:{}: malformed entry",
+                path.display(),
+                number + 1
+            ));
+        };
+
+        if entry_host == host && kind == SPKI_SHA256 {
+            return Ok(Some(fingerprint.parse()?));
+        }
+    }
+
+    Ok(None)
+}
+
+/// Records the SPKI fingerprint of `host`, creating the file and its directory if needed
+pub fn record(path: &Path, host: &str, spki: &Fingerprint) -> anyhow::Result<()> {
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+
+    let mut file = OpenOptions::new().create(true).append(true).open(path)?;
+    writeln!(file, "{host} {SPKI_SHA256} {spki}")?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod known_hosts_tests {
+    use std::{env, fs, process};
+
+    use cower_common::tls::Fingerprint;
+
+    use super::{lookup, record};
+
+    #[test]
+    fn record_and_lookup() -> anyhow::Result<()> {
+        let path = env::temp_dir()
+            .join(format!("cower-known-hosts-{}", process::id()))
+            .join("known_hosts");
+        let first = Fingerprint::of(b"first");
+        let second = Fingerprint::of(b"second");
+
+        assert_eq!(lookup(&path, "127.0.0.1:9989")?, None);
+
+        record(&path, "127.0.0.1:9989", &first)?;
+        record(&path, "example.com:9989", &second)?;
+
+        assert_eq!(lookup(&path, "127.0.0.1:9989")?, Some(first));
+        assert_eq!(lookup(&path, "example.com:9989")?, Some(second));
+        assert_eq!(lookup(&path, "example.org:9989")?, None);
+
+        fs::remove_dir_all(path.parent().expect("path has a parent"))?;
+
+        Ok(())
+    }
+}
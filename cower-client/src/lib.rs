@@ -0,0 +1,450 @@
+#![deny(missing_docs)]
+
+//! The client talks to targets on behalf of people and other programs. Besides the
+//! `cower-client` binary, it can be used as a library to manage resources without shelling out:
+//!
+//! ```no_run
+//! use cower_client::{CowerClient, target::Target};
+//! use cower_common::tls::ServerVerification;
+//!
+//! # fn main() -> anyhow::Result<()> {
+//! let target: Target = "example.com".parse()?;
+//! let verification = ServerVerification::CertificateAuthority(None);
+//!
+//! let mut client = CowerClient::connect(&target, None, &verification)?;
+//! println!("{:?}", client.start("minecraft")?);
+//! client.close()?;
+//! # Ok(())
+//! # }
+//! ```
+
+pub mod config;
+pub mod target;
+
+use std::{fmt, time::Duration};
+
+use anyhow::{Context, anyhow};
+use cower_common::{
+    Timeouts,
+    message::{CloseReason, ContainerState, ErrorCode},
+    prelude::*,
+    tls::{PeerIdentity, ServerVerification},
+};
+
+use target::{Address, Target};
+
+/// Gives up on targets that went away without closing the connection. Waiting for replies is
+/// fine as long as the target answers pings
+pub const DEFAULT_TIMEOUTS: Timeouts = Timeouts {
+    handshake: Some(Duration::from_secs(10)),
+    read: None,
+    write: Some(Duration::from_secs(60)),
+    idle: Some(Duration::from_secs(60)),
+};
+
+/// The error a target replied with to a request
+#[derive(Debug)]
+pub struct TargetError(pub ErrorCode);
+
+impl fmt::Display for TargetError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Target responded with {:?}", self.0)
+    }
+}
+
+impl std::error::Error for TargetError {}
+
+fn unexpected(message: Message) -> anyhow::Error {
+    anyhow!("Received unexpected message: {message:?}")
+}
+
+/// What happened to one of several resources acted on at once
+#[derive(Debug)]
+pub struct Outcome {
+    /// Name of the resource
+    pub resource: String,
+    /// The state the resource ended up in, or the error the target replied with
+    pub state: Result<ContainerState, ErrorCode>,
+}
+
+/// A resource or group the target lists
+#[derive(Debug)]
+pub struct ResourceInfo {
+    /// Name of the resource or group
+    pub name: String,
+    /// State of the resource. Groups don't have one
+    pub state: Option<ContainerState>,
+    /// Description from the target's configuration
+    pub description: Option<String>,
+}
+
+/// A connection to a target, with a method for every request the protocol has
+pub struct CowerClient {
+    conn: Connection<Client>,
+}
+
+impl CowerClient {
+    /// Wraps a connection that's already been set up
+    pub fn new(conn: Connection<Client>) -> Self {
+        Self { conn }
+    }
+
+    /// Connects to `target` with [`DEFAULT_TIMEOUTS`], checking its certificate according to
+    /// `verification`. `sni` is the name the certificate is checked against, defaulting to the
+    /// host of the target. Unix sockets don't use certificates, so both are ignored for them
+    pub fn connect(
+        target: &Target,
+        sni: Option<&str>,
+        verification: &ServerVerification,
+    ) -> anyhow::Result<Self> {
+        Self::connect_with_timeouts(target, sni, verification, DEFAULT_TIMEOUTS)
+    }
+
+    /// Same as [`CowerClient::connect`], but with custom timeouts
+    pub fn connect_with_timeouts(
+        target: &Target,
+        sni: Option<&str>,
+        verification: &ServerVerification,
+        timeouts: Timeouts,
+    ) -> anyhow::Result<Self> {
+        let conn = match &target.address {
+            Address::Tcp { host, port } => {
+                let domain = sni.unwrap_or(host);
+                Connection::connect_with_timeouts(
+                    (host.as_str(), *port),
+                    domain,
+                    verification,
+                    timeouts,
+                )?
+            }
+            Address::WebSocket(_) if sni.is_some() => {
+                return Err(anyhow!("--sni isn't supported with wss:// targets"));
+            }
+            #[cfg(feature = "websocket")]
+            Address::WebSocket(url) => Connection::connect_websocket(url, verification, timeouts)?,
+            #[cfg(not(feature = "websocket"))]
+            Address::WebSocket(_) => {
+                return Err(anyhow!("cower-client was built without WebSocket support"));
+            }
+            #[cfg(unix)]
+            Address::Unix(path) => {
+                let mut conn = Connection::connect_unix(path)
+                    .with_context(|| format!("Couldn't connect to {}", path.display()))?;
+                conn.set_timeouts(timeouts)?;
+
+                conn
+            }
+            #[cfg(not(unix))]
+            Address::Unix(_) => {
+                return Err(anyhow!("Unix sockets aren't supported on this platform"));
+            }
+        };
+
+        Ok(Self::new(conn))
+    }
+
+    /// Fingerprints of the certificate the target presented, unless connected over a Unix socket
+    pub fn peer_identity(&self) -> Option<&PeerIdentity> {
+        self.conn.peer_identity()
+    }
+
+    /// The underlying connection, e.g. for sending messages this type has no method for
+    pub fn connection(&mut self) -> &mut Connection<Client> {
+        &mut self.conn
+    }
+
+    /// Sends `message` and waits for the state it's answered with
+    fn request_state(&mut self, message: &Message) -> anyhow::Result<ContainerState> {
+        self.request_readiness(message).map(|(state, _)| state)
+    }
+
+    /// Same as [`CowerClient::request_state`], but also returns whether the resource is ready
+    fn request_readiness(
+        &mut self,
+        message: &Message,
+    ) -> anyhow::Result<(ContainerState, Option<bool>)> {
+        self.conn.send(message)?;
+
+        match self.conn.receive()? {
+            Message::StateMessage { state, ready } => Ok((state, ready)),
+            Message::ErrorMessage { code } => Err(TargetError(code).into()),
+
+            msg => Err(unexpected(msg)),
+        }
+    }
+
+    /// Starts a resource, returning its new state
+    pub fn start(&mut self, resource: &str) -> anyhow::Result<ContainerState> {
+        self.request_state(&Message::StartMessage {
+            resource_name: resource.to_owned(),
+        })
+    }
+
+    /// Starts several resources at once over this connection. The outcomes are returned in the
+    /// order the target replies in, and a resource failing doesn't stop the others
+    pub fn start_all(&mut self, resources: &[String]) -> anyhow::Result<Vec<Outcome>> {
+        // request IDs are the resources' indices + 1, as 0 means no ID
+        for (request_id, resource) in (1..).zip(resources) {
+            let message = Message::StartMessage {
+                resource_name: resource.clone(),
+            };
+            self.conn.send_request(request_id, &message)?;
+        }
+
+        let mut outcomes = Vec::with_capacity(resources.len());
+        for _ in resources {
+            let (request_id, reply) = self.conn.receive_request()?;
+            let resource = request_id
+                .checked_sub(1)
+                .and_then(|index| resources.get(usize::try_from(index).ok()?))
+                .ok_or(anyhow!("Received a reply to an unknown request"))?;
+
+            let state = match reply {
+                Message::StateMessage { state, .. } => Ok(state),
+                Message::ErrorMessage { code } => Err(code),
+
+                msg => return Err(unexpected(msg)),
+            };
+            outcomes.push(Outcome {
+                resource: resource.clone(),
+                state,
+            });
+        }
+
+        Ok(outcomes)
+    }
+
+    /// Stops a resource, waiting `timeout` seconds before killing it and sending `signal`
+    /// instead of the default stop signal if given
+    pub fn stop(
+        &mut self,
+        resource: &str,
+        timeout: Option<u32>,
+        signal: Option<String>,
+    ) -> anyhow::Result<ContainerState> {
+        self.request_state(&Message::StopMessage {
+            resource_name: resource.to_owned(),
+            timeout,
+            signal,
+        })
+    }
+
+    /// Restarts a resource, waiting `timeout` seconds for it to stop before killing it
+    pub fn restart(
+        &mut self,
+        resource: &str,
+        timeout: Option<u32>,
+    ) -> anyhow::Result<ContainerState> {
+        self.request_state(&Message::RestartMessage {
+            resource_name: resource.to_owned(),
+            timeout,
+        })
+    }
+
+    /// The current state of a resource
+    pub fn status(&mut self, resource: &str) -> anyhow::Result<ContainerState> {
+        self.request_state(&Message::StatusMessage {
+            resource_name: resource.to_owned(),
+        })
+    }
+
+    /// The current state of a resource, together with whether it's ready: running and passing
+    /// its healthcheck, if it has one. The readiness is `None` for targets that don't report it
+    pub fn readiness(&mut self, resource: &str) -> anyhow::Result<(ContainerState, Option<bool>)> {
+        self.request_readiness(&Message::StatusMessage {
+            resource_name: resource.to_owned(),
+        })
+    }
+
+    /// Pauses a resource, freezing it without losing its memory
+    pub fn pause(&mut self, resource: &str) -> anyhow::Result<ContainerState> {
+        self.request_state(&Message::PauseMessage {
+            resource_name: resource.to_owned(),
+        })
+    }
+
+    /// Resumes a paused resource
+    pub fn unpause(&mut self, resource: &str) -> anyhow::Result<ContainerState> {
+        self.request_state(&Message::UnpauseMessage {
+            resource_name: resource.to_owned(),
+        })
+    }
+
+    /// The resources and groups of the target whose state this client may see
+    pub fn list(&mut self) -> anyhow::Result<Vec<ResourceInfo>> {
+        self.conn.send(&Message::ListMessage)?;
+
+        let mut resources = vec![];
+        loop {
+            match self.conn.receive()? {
+                Message::ResourceMessage {
+                    resource_name,
+                    state,
+                    description,
+                } => resources.push(ResourceInfo {
+                    name: resource_name,
+                    state,
+                    description,
+                }),
+                Message::ListEndMessage => return Ok(resources),
+                Message::ErrorMessage { code } => return Err(TargetError(code).into()),
+
+                msg => return Err(unexpected(msg)),
+            }
+        }
+    }
+
+    /// Requests the logs of a resource, only the last `tail` lines if given. With `follow`, new
+    /// output keeps arriving until the connection is closed
+    pub fn logs(
+        &mut self,
+        resource: &str,
+        tail: Option<u32>,
+        follow: bool,
+    ) -> anyhow::Result<Logs<'_>> {
+        self.conn.send(&Message::LogsMessage {
+            resource_name: resource.to_owned(),
+            tail,
+            follow,
+        })?;
+
+        Ok(Logs {
+            conn: &mut self.conn,
+            done: false,
+        })
+    }
+
+    /// Closes the connection, telling the target the client is done
+    pub fn close(self) -> anyhow::Result<()> {
+        Ok(self.conn.close(CloseReason::Normal, None)?)
+    }
+}
+
+/// The logs of a resource, in chunks of raw bytes as the target sends them
+pub struct Logs<'a> {
+    conn: &'a mut Connection<Client>,
+    done: bool,
+}
+
+impl Iterator for Logs<'_> {
+    type Item = anyhow::Result<Vec<u8>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+
+        let item = match self.conn.receive() {
+            Ok(Message::LogDataMessage { data }) => return Some(Ok(data)),
+            Ok(Message::LogsEndMessage) => None,
+            Ok(Message::ErrorMessage { code }) => Some(Err(TargetError(code).into())),
+            Ok(msg) => Some(Err(unexpected(msg))),
+            Err(why) => Some(Err(why.into())),
+        };
+        self.done = true;
+
+        item
+    }
+}
+
+#[cfg(test)]
+mod client_tests {
+    use std::{net::TcpListener, thread};
+
+    use cower_common::{
+        Acceptor,
+        message::{ContainerState, ErrorCode, Message},
+        tls::{Certificate, Identity, ServerVerification},
+    };
+
+    use super::{CowerClient, TargetError};
+
+    const IDENT_FILE: &[u8] = include_bytes!("../../test-keys/identity.p12");
+    const IDENT_PASS: &str = include_str!("../../test-keys/creds.asc");
+    const CUSTOM_CERT: &[u8] = include_bytes!("../../test-keys/cert.crt");
+
+    /// Answers the requests the test makes, in order
+    fn serve(acceptor: &Acceptor, listener: &TcpListener) -> cower_common::Result<()> {
+        let (stream, _) = listener.accept()?;
+        let mut conn = acceptor.accept(stream)?;
+
+        let Message::StartMessage { .. } = conn.receive()? else {
+            panic!("expected a start message");
+        };
+        conn.send(&Message::StateMessage {
+            state: ContainerState::Running,
+            ready: None,
+        })?;
+
+        let Message::StatusMessage { .. } = conn.receive()? else {
+            panic!("expected a status message");
+        };
+        conn.send(&Message::ErrorMessage {
+            code: ErrorCode::ResourceNotFound,
+        })?;
+
+        let Message::ListMessage = conn.receive()? else {
+            panic!("expected a list message");
+        };
+        conn.send(&Message::ResourceMessage {
+            resource_name: "web".to_owned(),
+            state: Some(ContainerState::Exited),
+            description: Some("Web server".to_owned()),
+        })?;
+        conn.send(&Message::ListEndMessage)?;
+
+        let Message::LogsMessage { .. } = conn.receive()? else {
+            panic!("expected a logs message");
+        };
+        for data in [b"line 1\n", b"line 2\n"] {
+            conn.send(&Message::LogDataMessage {
+                data: data.to_vec(),
+            })?;
+        }
+        conn.send(&Message::LogsEndMessage)?;
+
+        assert!(matches!(
+            conn.receive(),
+            Err(cower_common::Error::Closed { .. })
+        ));
+
+        Ok(())
+    }
+
+    #[test]
+    fn client_requests() -> anyhow::Result<()> {
+        let cert = Certificate::from_pem(CUSTOM_CERT)?;
+        let acceptor = Acceptor::new(Identity::from_pkcs12(IDENT_FILE, IDENT_PASS.trim())?)?;
+
+        let listener = TcpListener::bind(("127.0.0.1", 0))?;
+        let target = format!("127.0.0.1:{}", listener.local_addr()?.port()).parse()?;
+        let handle = thread::spawn(move || serve(&acceptor, &listener));
+
+        let verification = ServerVerification::CertificateAuthority(Some(cert));
+        let mut client = CowerClient::connect(&target, Some("localhost"), &verification)?;
+
+        assert_eq!(client.start("web")?, ContainerState::Running);
+
+        let error = client.status("nothing").expect_err("status should fail");
+        assert!(matches!(
+            error.downcast_ref(),
+            Some(TargetError(ErrorCode::ResourceNotFound))
+        ));
+
+        let list = client.list()?;
+        assert_eq!(list.len(), 1);
+        assert!(list.iter().all(|resource| resource.name == "web"
+            && resource.state == Some(ContainerState::Exited)
+            && resource.description.as_deref() == Some("Web server")));
+
+        let logs = client
+            .logs("web", None, false)?
+            .collect::<anyhow::Result<Vec<_>>>()?;
+        assert_eq!(logs.concat(), b"line 1\nline 2\n");
+
+        client.close()?;
+        handle.join().expect("associated thread panicked")?;
+
+        Ok(())
+    }
+}
@@ -1,26 +1,248 @@
-use std::{env, fs, io::Read};
+mod completions;
+mod known_hosts;
+mod output;
+#[cfg(feature = "tui")]
+mod tui;
+mod wait;
 
-use clap::Parser;
+use std::{
+    env, fs,
+    io::Read,
+    path::PathBuf,
+    process::ExitCode,
+    time::{Duration, Instant},
+};
 
-use cower_common::prelude::*;
-use native_tls::Certificate;
+use anyhow::{Context, anyhow};
+use clap::{Parser, Subcommand};
+
+use clap_complete::ArgValueCandidates;
+use completions::Shell;
+use cower_client::{
+    CowerClient, DEFAULT_TIMEOUTS, TargetError,
+    config::{self, Config, ResolvedTarget, Verification, layer_verification},
+    target::{Address, Target},
+};
+use cower_common::{
+    Timeouts,
+    tls::{Certificate, Fingerprint, Pin, ServerVerification},
+};
+use output::{Format, Output};
+use wait::Waiter;
 
 #[derive(Parser, Debug)]
-#[command(version, about)]
+#[command(version, about, after_help = output::EXIT_CODES)]
 struct Args {
-    /// Force direct connection to target. Fails if provided address belongs to a server
-    #[arg(short, long, default_value_t = false)]
+    /// Only connect to the target itself. Fails for `wss://` URLs, as the WebSocket may be
+    /// relayed by a web server in front of the target
+    #[arg(short, long, global = true, default_value_t = false)]
     direct: bool,
 
+    /// Connect to a target on this host over its Unix socket, the same as giving the path as the
+    /// target. The target argument then names the resource, if the command takes one
+    #[arg(long, global = true, conflicts_with_all = ["cert_path", "pin_cert", "pin_spki", "tofu"])]
+    socket: Option<PathBuf>,
+
     /// Path to a custom certificate
-    #[arg(short, long)]
+    #[arg(short, long, global = true)]
     cert_path: Option<String>,
+
+    /// Only accept a target whose certificate has this SHA-256 fingerprint, skipping CA
+    /// validation
+    #[arg(long, global = true, conflicts_with_all = ["cert_path", "pin_spki", "tofu"])]
+    pin_cert: Option<Fingerprint>,
+
+    /// Only accept a target whose public key (SubjectPublicKeyInfo) has this SHA-256
+    /// fingerprint, skipping CA validation. Unlike `--pin-cert`, this survives certificate
+    /// renewals that keep the key
+    #[arg(long, global = true, conflicts_with_all = ["cert_path", "tofu"])]
+    pin_spki: Option<Fingerprint>,
+
+    /// Trust the target on first use and pin its public key afterwards, like SSH does
+    #[arg(
+        long,
+        global = true,
+        default_value_t = false,
+        conflicts_with = "cert_path"
+    )]
+    tofu: bool,
+
+    /// Path to the known hosts file used by `--tofu`. Defaults to
+    /// `$XDG_CONFIG_HOME/cower/known_hosts`
+    #[arg(long, global = true)]
+    known_hosts: Option<PathBuf>,
+
+    /// Path to the configuration file holding named targets. Defaults to `COWER_CLIENT_CONFIG`,
+    /// then `$XDG_CONFIG_HOME/cower/config.toml`
+    #[arg(long, global = true)]
+    config: Option<PathBuf>,
+
+    /// How to print results and errors. `json` prints one JSON object per line, errors included,
+    /// to standard output
+    #[arg(short, long, global = true, value_enum, default_value_t)]
+    output: Format,
+
+    #[command(subcommand)]
+    command: Command,
 }
 
-fn main() -> anyhow::Result<()> {
-    let args = Args::parse();
+/// Where to connect to
+#[derive(clap::Args, Debug)]
+struct TargetArgs {
+    /// The target: the name of a target in the configuration file, an alias of a resource on the
+    /// default target, `host[:port]`, `cower://host[:port]/resource`, a `wss://` URL, or the path
+    /// to the Unix socket of a target on this host (starting with `/` or `.`). Connections over
+    /// the Unix socket aren't encrypted and the target authorizes you by your user and group
+    #[arg(
+        add = ArgValueCandidates::new(completions::targets),
+        required_unless_present = "socket"
+    )]
+    target: Option<String>,
+
+    /// Server name to request and to check the target's certificate against. Defaults to the
+    /// target's `domain` in the configuration file, then to the host of the target
+    #[arg(long)]
+    sni: Option<String>,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Start one or more resources
+    Start {
+        #[command(flatten)]
+        target: TargetArgs,
+
+        /// Names of the resources. Several resources are started concurrently over a single
+        /// connection
+        #[arg(add = ArgValueCandidates::new(completions::resources))]
+        resources: Vec<String>,
+
+        /// Wait until the resources are running and healthy, retrying if the connection to the
+        /// target fails
+        #[arg(short, long, default_value_t = false)]
+        wait: bool,
+
+        /// Seconds after which to give up waiting
+        #[arg(long, default_value_t = 300, requires = "wait")]
+        wait_timeout: u64,
+    },
+    /// Stop a resource
+    Stop {
+        #[command(flatten)]
+        target: TargetArgs,
+
+        /// Seconds to wait for the resource to stop before killing it
+        #[arg(short, long)]
+        timeout: Option<u32>,
+
+        /// Signal to send instead of the default stop signal, e.g. SIGINT
+        #[arg(short, long)]
+        signal: Option<String>,
 
-    let cert_path = args.cert_path.or_else(|| env::var("COWER_CERT").ok());
+        /// Name of the resource, unless it's part of the target URL
+        #[arg(add = ArgValueCandidates::new(completions::resources))]
+        resource: Option<String>,
+    },
+    /// Restart a resource
+    Restart {
+        #[command(flatten)]
+        target: TargetArgs,
+
+        /// Seconds to wait for the resource to stop before killing it
+        #[arg(short, long)]
+        timeout: Option<u32>,
+
+        /// Name of the resource, unless it's part of the target URL
+        #[arg(add = ArgValueCandidates::new(completions::resources))]
+        resource: Option<String>,
+    },
+    /// Print the state of a resource
+    Status {
+        #[command(flatten)]
+        target: TargetArgs,
+
+        /// Name of the resource, unless it's part of the target URL
+        #[arg(add = ArgValueCandidates::new(completions::resources))]
+        resource: Option<String>,
+    },
+    /// List the resources of a target whose state you may see
+    List {
+        #[command(flatten)]
+        target: TargetArgs,
+    },
+    /// Pause a resource, freezing it without losing its memory
+    Pause {
+        #[command(flatten)]
+        target: TargetArgs,
+
+        /// Name of the resource, unless it's part of the target URL
+        #[arg(add = ArgValueCandidates::new(completions::resources))]
+        resource: Option<String>,
+    },
+    /// Resume a paused resource
+    Unpause {
+        #[command(flatten)]
+        target: TargetArgs,
+
+        /// Name of the resource, unless it's part of the target URL
+        #[arg(add = ArgValueCandidates::new(completions::resources))]
+        resource: Option<String>,
+    },
+    /// Print the logs of a resource
+    Logs {
+        #[command(flatten)]
+        target: TargetArgs,
+
+        /// Keep printing new output as it is produced
+        #[arg(short, long, default_value_t = false)]
+        follow: bool,
+
+        /// Only print the last N lines
+        #[arg(short = 'n', long)]
+        tail: Option<u32>,
+
+        /// Name of the resource, unless it's part of the target URL
+        #[arg(add = ArgValueCandidates::new(completions::resources))]
+        resource: Option<String>,
+    },
+    /// Show the resources of a target in an interactive interface, refreshed as they change
+    #[cfg(feature = "tui")]
+    Tui {
+        #[command(flatten)]
+        target: TargetArgs,
+    },
+    /// Print the script that sets up completions for a shell, e.g.
+    /// `source <(cower-client completions bash)` in `~/.bashrc`. Names of resources are
+    /// completed by asking the target for them
+    Completions {
+        /// The shell to set completions up for
+        shell: Shell,
+    },
+}
+
+impl Command {
+    /// The target to connect to. Only `completions` doesn't have one
+    fn target(&self) -> Option<&TargetArgs> {
+        match self {
+            Self::Start { target, .. }
+            | Self::Stop { target, .. }
+            | Self::Restart { target, .. }
+            | Self::Status { target, .. }
+            | Self::List { target }
+            | Self::Pause { target, .. }
+            | Self::Unpause { target, .. }
+            | Self::Logs { target, .. } => Some(target),
+            #[cfg(feature = "tui")]
+            Self::Tui { target } => Some(target),
+            Self::Completions { .. } => None,
+        }
+    }
+}
+
+fn load_certificate(
+    cert_path: Option<PathBuf>,
+    quiet: bool,
+) -> anyhow::Result<Option<Certificate>> {
     let cert: Option<Certificate> = if let Some(cert_path) = cert_path {
         let mut file = fs::File::open(&cert_path)?;
         let mut buf = vec![];
@@ -28,17 +250,301 @@ fn main() -> anyhow::Result<()> {
 
         Some(Certificate::from_pem(&buf)?)
     } else {
-        eprintln!("Running without custom certificate. This might cause you trouble!");
+        if !quiet {
+            eprintln!("Running without custom certificate. This might cause you trouble!");
+        }
 
         None
     };
 
-    let mut conn = Connection::connect("127.0.0.1:9989", "localhost", cert)?;
+    Ok(cert)
+}
+
+/// Connects to the target, pinning its public key in the known hosts file. Unknown targets are
+/// refused when `quiet`, as nobody would learn about them being trusted
+fn connect_tofu(
+    target: &Target,
+    sni: Option<&str>,
+    known_hosts: Option<PathBuf>,
+    timeouts: Timeouts,
+    quiet: bool,
+) -> anyhow::Result<CowerClient> {
+    let path = known_hosts
+        .or_else(known_hosts::default_path)
+        .ok_or(anyhow!(
+            "Couldn't determine the path to the known hosts file"
+        ))?;
+    let host = target.to_string();
+
+    let Some(spki) = known_hosts::lookup(&path, &host)? else {
+        if quiet {
+            return Err(anyhow!("{host} isn't a known host yet"));
+        }
+
+        let verification = ServerVerification::TrustOnFirstUse;
+        let client = CowerClient::connect_with_timeouts(target, sni, &verification, timeouts)?;
+        let peer = client
+            .peer_identity()
+            .ok_or(anyhow!("Target didn't present a certificate"))?;
+
+        known_hosts::record(&path, &host, &peer.spki)?;
+        eprintln!(
+            "Added {host} (SPKI SHA-256 {}) to {}",
+            peer.spki,
+            path.display()
+        );
 
-    let msg = Message::StartMessage {
-        resource_name: "my_resource".to_owned(),
+        return Ok(client);
     };
-    conn.send(&msg)?;
+
+    let verification = ServerVerification::Pinned(Pin::Spki(spki));
+    match CowerClient::connect_with_timeouts(target, sni, &verification, timeouts) {
+        Err(why) => match why.downcast_ref() {
+            Some(cower_common::Error::FingerprintMismatch(actual)) => {
+                let message = format!(
+                    "The identity of {host} has changed (SPKI SHA-256 {actual}, expected {spki}). \
+                     If this is expected, remove its entry from {}",
+                    path.display()
+                );
+
+                // keeps the mismatch as the cause, so that it's reported as a TLS failure
+                Err(why.context(message))
+            }
+            _ => Err(why),
+        },
+        result => result,
+    }
+}
+
+/// Starts several resources at once, printing their states in the order the target replies in
+fn start_all(
+    client: &mut CowerClient,
+    output: &Output,
+    resources: &[String],
+) -> anyhow::Result<()> {
+    let mut failures = vec![];
+    for outcome in client.start_all(resources)? {
+        match outcome.state {
+            Ok(state) => output.resource_state(&outcome.resource, state)?,
+            Err(code) => {
+                output.resource_error(&outcome.resource, code)?;
+                failures.push(code);
+            }
+        }
+    }
+
+    // the exit code is that of the first failure
+    if let Some(&code) = failures.first() {
+        return Err(TargetError(code)).context(format!(
+            "{} of {} requests failed",
+            failures.len(),
+            resources.len()
+        ));
+    }
 
     Ok(())
 }
+
+fn main() -> ExitCode {
+    completions::complete();
+
+    let args = Args::parse();
+    let mut output = Output::new(args.output);
+
+    match run(args, &mut output) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(why) => output.error(&why),
+    }
+}
+
+fn load_config(path: Option<PathBuf>) -> anyhow::Result<Config> {
+    let config = path
+        .or_else(|| env::var_os("COWER_CLIENT_CONFIG").map(PathBuf::from))
+        .or_else(config::default_path)
+        .map(|path| Config::load(&path))
+        .transpose()?
+        .unwrap_or_default();
+
+    Ok(config)
+}
+
+/// Resolves the target of the command and connects to it. With `quiet`, nothing is printed and
+/// unknown targets aren't trusted on first use
+fn open<'a>(
+    args: &Args,
+    config: &'a Config,
+    timeouts: Timeouts,
+    quiet: bool,
+) -> anyhow::Result<(ResolvedTarget<'a>, CowerClient)> {
+    let target_args = args
+        .command
+        .target()
+        .ok_or(anyhow!("The command doesn't connect to a target"))?;
+    let target = match (&args.socket, &target_args.target) {
+        (Some(socket), resource) => ResolvedTarget {
+            target: Target {
+                address: Address::Unix(socket.clone()),
+                resource: resource.clone(),
+            },
+            settings: None,
+        },
+        (None, Some(target)) => config.resolve(target)?,
+        (None, None) => return Err(anyhow!("Missing the target")),
+    };
+    if args.direct && matches!(target.target.address, Address::WebSocket(_)) {
+        return Err(anyhow!(
+            "{} may be relayed, so it can't be used with --direct",
+            target.target
+        ));
+    }
+    let sni = target_args
+        .sni
+        .clone()
+        .or_else(|| target.settings.and_then(|settings| settings.domain.clone()));
+
+    let command_line = if let Some(fingerprint) = args.pin_cert {
+        Some(Verification::Pinned(Pin::Certificate(fingerprint)))
+    } else if let Some(fingerprint) = args.pin_spki {
+        Some(Verification::Pinned(Pin::Spki(fingerprint)))
+    } else if args.tofu {
+        Some(Verification::TrustOnFirstUse)
+    } else {
+        args.cert_path
+            .as_ref()
+            .map(|path| Verification::CertificateAuthority(Some(path.into())))
+    };
+
+    let client = if let Address::Unix(_) = &target.target.address {
+        if command_line.is_some() {
+            return Err(anyhow!("Unix sockets don't use certificates"));
+        }
+
+        let verification = ServerVerification::CertificateAuthority(None);
+        CowerClient::connect_with_timeouts(&target.target, None, &verification, timeouts)?
+    } else {
+        let env_cert = env::var_os("COWER_CERT").map(PathBuf::from);
+        let (address, sni) = (&target.target, sni.as_deref());
+
+        let verification = match layer_verification(command_line, env_cert, target.settings)? {
+            Verification::Pinned(pin) => ServerVerification::Pinned(pin),
+            Verification::TrustOnFirstUse => {
+                let known_hosts = args.known_hosts.clone();
+                let client = connect_tofu(address, sni, known_hosts, timeouts, quiet)?;
+
+                return Ok((target, client));
+            }
+            Verification::CertificateAuthority(cert_path) => {
+                ServerVerification::CertificateAuthority(load_certificate(cert_path, quiet)?)
+            }
+        };
+
+        CowerClient::connect_with_timeouts(address, sni, &verification, timeouts)?
+    };
+
+    Ok((target, client))
+}
+
+fn run(args: Args, output: &mut Output) -> anyhow::Result<()> {
+    if let Command::Completions { shell } = args.command {
+        return completions::write_registration(shell);
+    }
+
+    let config = load_config(args.config.clone())?;
+    let connect = |timeouts| open(&args, &config, timeouts, false);
+    // only waiting is worth retrying for, as it comes with a timeout
+    let deadline = match &args.command {
+        Command::Start {
+            wait: true,
+            wait_timeout,
+            ..
+        } => Some(Instant::now() + Duration::from_secs(*wait_timeout)),
+        _ => None,
+    };
+    let (target, mut client) = match deadline {
+        Some(deadline) => wait::retry(deadline, output, || {
+            connect(wait::cap(DEFAULT_TIMEOUTS, deadline)?)
+        })?,
+        None => connect(DEFAULT_TIMEOUTS)?,
+    };
+
+    match &args.command {
+        Command::Start { resources, .. } => {
+            let resources: Vec<String> = target
+                .target
+                .resource
+                .iter()
+                .cloned()
+                .chain(
+                    resources
+                        .iter()
+                        .map(|name| target.resource_name(name.clone())),
+                )
+                .collect();
+
+            match (resources.as_slice(), deadline) {
+                ([], _) => return Err(anyhow!("Missing the name of the resource")),
+                (_, Some(deadline)) => {
+                    let reconnect = |timeouts| connect(timeouts).map(|(_, client)| client);
+                    let mut waiter = Waiter::new(&mut client, deadline, output, reconnect);
+                    waiter.start(&resources)?;
+                }
+                ([resource], None) => output.state(resource, client.start(resource)?)?,
+                (_, None) => start_all(&mut client, output, &resources)?,
+            }
+        }
+        Command::Stop {
+            timeout,
+            signal,
+            resource,
+            ..
+        } => {
+            let resource = target.resource(resource.clone())?;
+            output.state(&resource, client.stop(&resource, *timeout, signal.clone())?)?;
+        }
+        Command::Restart {
+            timeout, resource, ..
+        } => {
+            let resource = target.resource(resource.clone())?;
+            output.state(&resource, client.restart(&resource, *timeout)?)?;
+        }
+        Command::Status { resource, .. } => {
+            let resource = target.resource(resource.clone())?;
+            output.state(&resource, client.status(&resource)?)?;
+        }
+        Command::List { .. } => {
+            for resource in client.list()? {
+                output.list_entry(
+                    &resource.name,
+                    resource.state,
+                    resource.description.as_deref(),
+                )?;
+            }
+        }
+        Command::Pause { resource, .. } => {
+            let resource = target.resource(resource.clone())?;
+            output.state(&resource, client.pause(&resource)?)?;
+        }
+        Command::Unpause { resource, .. } => {
+            let resource = target.resource(resource.clone())?;
+            output.state(&resource, client.unpause(&resource)?)?;
+        }
+        Command::Logs {
+            follow,
+            tail,
+            resource,
+            ..
+        } => {
+            let resource = target.resource(resource.clone())?;
+            for data in client.logs(&resource, *tail, *follow)? {
+                output.logs(&data?)?;
+            }
+            output.logs_end()?;
+        }
+        #[cfg(feature = "tui")]
+        Command::Tui { .. } => return tui::run(client, &target.target.to_string()),
+        // printed without connecting to anything
+        Command::Completions { .. } => {}
+    }
+
+    client.close()
+}
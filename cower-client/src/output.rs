@@ -0,0 +1,355 @@
+//! What the client prints, either for people or as JSON Lines for scripts, and the exit codes it
+//! reports failures with.
+//!
+//! With `--output json`, every result is printed to standard output as a JSON object on a line of
+//! its own, and so is the error the client fails with:
+//!
+//! ```text
+//! {"resource":"minecraft","state":"Running"}
+//! {"error":"not_found","exit_code":6,"message":"Target responded with ResourceNotFound","resource":"web"}
+//! ```
+
+use std::{
+    io::{self, Write},
+    process::ExitCode,
+};
+
+use clap::ValueEnum;
+use cower_client::TargetError;
+use cower_common::message::{CloseReason, ContainerState, ErrorCode};
+use serde::Serialize;
+
+use crate::wait::TimedOut;
+
+/// Shown at the end of `--help`
+pub const EXIT_CODES: &str = "\
+Exit codes:
+  0  Success
+  1  Any other failure
+  2  Invalid command line
+  3  Connection failure
+  4  TLS failure
+  5  Denied by the target
+  6  Resource not found
+  7  Engine error
+  8  Timed out waiting (start --wait)";
+
+/// How results are printed
+#[derive(ValueEnum, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Format {
+    /// For people
+    #[default]
+    Text,
+    /// One JSON object per line
+    Json,
+}
+
+/// Classes of failures, each with its own exit code. Exit code 2 is left to the argument parser,
+/// which uses it for invalid command lines
+#[derive(Serialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+#[repr(u8)]
+pub enum ErrorClass {
+    /// Anything that doesn't fit the other classes, e.g. an invalid configuration file
+    Other = 1,
+    /// The target couldn't be reached, or the connection to it broke
+    Connection = 3,
+    /// The target's certificate couldn't be verified, or the TLS handshake failed otherwise
+    Tls = 4,
+    /// The target doesn't allow the operation
+    Denied = 5,
+    /// The target doesn't know the resource
+    NotFound = 6,
+    /// The target couldn't carry the operation out, usually because of its container engine
+    Engine = 7,
+    /// The resources weren't running, or the target couldn't be reached, before the wait timed out
+    Timeout = 8,
+}
+
+impl ErrorClass {
+    fn of_code(code: ErrorCode) -> Self {
+        match code {
+            ErrorCode::PermissionDenied => Self::Denied,
+            ErrorCode::ResourceNotFound => Self::NotFound,
+            ErrorCode::EngineUnreachable | ErrorCode::Unknown | ErrorCode::Unsupported => {
+                Self::Engine
+            }
+        }
+    }
+
+    fn of_library(error: &cower_common::Error) -> Self {
+        use cower_common::Error;
+
+        match error {
+            Error::TLSFailure(_)
+            | Error::TLSHandshakeFailure(_)
+            | Error::InvalidKeyMaterial
+            | Error::InvalidFingerprint
+            | Error::FingerprintMismatch(_) => Self::Tls,
+            Error::Closed {
+                reason: CloseReason::Unauthorized,
+                ..
+            } => Self::Denied,
+            Error::MesssageTooBig
+            | Error::UnknownMessage
+            | Error::LengthMismatch
+            | Error::MalformedPayload
+            | Error::UnsupportedVersion(_)
+            | Error::MissingField(_)
+            | Error::InvalidUtf8(_) => Self::Other,
+            _ => Self::Connection,
+        }
+    }
+
+    /// The class of the first error in the chain that has one
+    pub fn of(error: &anyhow::Error) -> Self {
+        // giving up on a connection failure is a timeout, not a connection failure
+        if error.downcast_ref::<TimedOut>().is_some() {
+            return Self::Timeout;
+        }
+
+        error
+            .chain()
+            .find_map(|cause| {
+                if let Some(TargetError(code)) = cause.downcast_ref() {
+                    Some(Self::of_code(*code))
+                } else {
+                    cause.downcast_ref().map(Self::of_library)
+                }
+            })
+            .unwrap_or(Self::Other)
+    }
+
+    pub fn exit_code(self) -> ExitCode {
+        ExitCode::from(self as u8)
+    }
+}
+
+#[derive(Serialize)]
+struct StateRecord<'a> {
+    resource: &'a str,
+    state: String,
+}
+
+#[derive(Serialize)]
+struct ResourceRecord<'a> {
+    resource: &'a str,
+    state: Option<String>,
+    description: Option<&'a str>,
+}
+
+#[derive(Serialize)]
+struct LogRecord<'a> {
+    line: &'a str,
+}
+
+#[derive(Serialize)]
+struct ErrorRecord<'a> {
+    error: ErrorClass,
+    exit_code: u8,
+    message: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    resource: Option<&'a str>,
+}
+
+/// Prints results in the chosen format
+pub struct Output {
+    format: Format,
+    /// The unfinished last line of the logs, which JSON Lines can't print yet
+    partial_line: Vec<u8>,
+}
+
+impl Output {
+    pub fn new(format: Format) -> Self {
+        Self {
+            format,
+            partial_line: vec![],
+        }
+    }
+
+    fn json(record: &impl Serialize) -> anyhow::Result<()> {
+        let mut stdout = io::stdout().lock();
+        serde_json::to_writer(&mut stdout, record)?;
+        writeln!(stdout)?;
+
+        Ok(())
+    }
+
+    /// The state of the only resource the command acts on
+    pub fn state(&self, resource: &str, state: ContainerState) -> anyhow::Result<()> {
+        match self.format {
+            Format::Text => println!("{state:?}"),
+            Format::Json => Self::json(&StateRecord {
+                resource,
+                state: format!("{state:?}"),
+            })?,
+        }
+
+        Ok(())
+    }
+
+    /// The state of one of several resources the command acts on
+    pub fn resource_state(&self, resource: &str, state: ContainerState) -> anyhow::Result<()> {
+        match self.format {
+            Format::Text => println!("{resource}: {state:?}"),
+            Format::Json => Self::json(&StateRecord {
+                resource,
+                state: format!("{state:?}"),
+            })?,
+        }
+
+        Ok(())
+    }
+
+    /// The error one of several resources failed with, while the others carry on
+    pub fn resource_error(&self, resource: &str, code: ErrorCode) -> anyhow::Result<()> {
+        match self.format {
+            Format::Text => eprintln!("{resource}: target responded with {code:?}"),
+            Format::Json => {
+                let class = ErrorClass::of_code(code);
+                Self::json(&ErrorRecord {
+                    error: class,
+                    exit_code: class as u8,
+                    message: TargetError(code).to_string(),
+                    resource: Some(resource),
+                })?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// A resource or group in the list the target sent
+    pub fn list_entry(
+        &self,
+        resource: &str,
+        state: Option<ContainerState>,
+        description: Option<&str>,
+    ) -> anyhow::Result<()> {
+        let state = state.map(|state| format!("{state:?}"));
+
+        match self.format {
+            Format::Text => {
+                let state = state.as_deref().unwrap_or("-");
+                match description {
+                    Some(description) => println!("{resource}\t{state}\t{description}"),
+                    None => println!("{resource}\t{state}"),
+                }
+            }
+            Format::Json => Self::json(&ResourceRecord {
+                resource,
+                state,
+                description,
+            })?,
+        }
+
+        Ok(())
+    }
+
+    /// A chunk of logs. In JSON, only whole lines are printed, the rest waits for the next chunk
+    /// or [`Output::logs_end`]
+    pub fn logs(&mut self, data: &[u8]) -> anyhow::Result<()> {
+        if self.format == Format::Text {
+            let mut stdout = io::stdout().lock();
+            stdout.write_all(data)?;
+            stdout.flush()?;
+
+            return Ok(());
+        }
+
+        self.partial_line.extend_from_slice(data);
+        while let Some(end) = self.partial_line.iter().position(|&byte| byte == b'\n') {
+            let line: Vec<u8> = self.partial_line.drain(..=end).collect();
+            let line = String::from_utf8_lossy(&line);
+            Self::json(&LogRecord {
+                line: line.trim_end_matches(['\n', '\r']),
+            })?;
+        }
+
+        Ok(())
+    }
+
+    /// Prints what's left of logs that didn't end with a newline
+    pub fn logs_end(&mut self) -> anyhow::Result<()> {
+        if self.partial_line.is_empty() {
+            return Ok(());
+        }
+
+        let line = std::mem::take(&mut self.partial_line);
+        Self::json(&LogRecord {
+            line: &String::from_utf8_lossy(&line),
+        })
+    }
+
+    /// What the client is waiting for, only told to people
+    pub fn progress(&self, message: &str) {
+        if self.format == Format::Text {
+            eprintln!("{message}");
+        }
+    }
+
+    /// Reports the error the client fails with, returning the exit code of its class
+    pub fn error(&self, error: &anyhow::Error) -> ExitCode {
+        let class = ErrorClass::of(error);
+
+        match self.format {
+            Format::Text => eprintln!("Error: {error:?}"),
+            Format::Json => {
+                let record = ErrorRecord {
+                    error: class,
+                    exit_code: class as u8,
+                    message: format!("{error:#}"),
+                    resource: None,
+                };
+                // there's nowhere left to report a failure to print the error to
+                _ = Self::json(&record);
+            }
+        }
+
+        class.exit_code()
+    }
+}
+
+#[cfg(test)]
+mod output_tests {
+    use anyhow::anyhow;
+    use cower_common::message::{CloseReason, ErrorCode};
+
+    use super::{ErrorClass, TargetError};
+
+    #[test]
+    fn classify_errors() -> anyhow::Result<()> {
+        let target = |code| anyhow::Error::new(TargetError(code));
+        assert_eq!(
+            ErrorClass::of(&target(ErrorCode::PermissionDenied)),
+            ErrorClass::Denied
+        );
+        assert_eq!(
+            ErrorClass::of(&target(ErrorCode::ResourceNotFound)),
+            ErrorClass::NotFound
+        );
+        assert_eq!(
+            ErrorClass::of(&target(ErrorCode::EngineUnreachable)),
+            ErrorClass::Engine
+        );
+
+        // the class survives context being added
+        let mismatch = cower_common::Error::FingerprintMismatch("00".repeat(32).parse()?);
+        let error = anyhow::Error::new(mismatch).context("The identity has changed");
+        assert_eq!(ErrorClass::of(&error), ErrorClass::Tls);
+
+        let io =
+            cower_common::Error::from(std::io::Error::from(std::io::ErrorKind::ConnectionRefused));
+        assert_eq!(ErrorClass::of(&io.into()), ErrorClass::Connection);
+
+        let unauthorized = cower_common::Error::Closed {
+            reason: CloseReason::Unauthorized,
+            text: None,
+        };
+        assert_eq!(ErrorClass::of(&unauthorized.into()), ErrorClass::Denied);
+
+        assert_eq!(ErrorClass::of(&anyhow!("bad config")), ErrorClass::Other);
+
+        Ok(())
+    }
+}
@@ -0,0 +1,219 @@
+//! Parsing of the targets given on the command line. A target is one of
+//!
+//! - `host[:port]`, with the port defaulting to 9989. IPv6 addresses go in brackets when they
+//!   come with a port, like `[::1]:9989`
+//! - `cower://host[:port]/resource`, which also names the resource to act on
+//! - `wss://host[:port]/path`, for targets behind a WebSocket
+//! - the path to the Unix socket of a target on this host, starting with `/` or `.`
+
+use std::{fmt, path::PathBuf, str::FromStr};
+
+use anyhow::anyhow;
+
+/// Port targets listen on unless told otherwise
+pub const DEFAULT_PORT: u16 = 9989;
+
+const COWER_SCHEME: &str = "cower://";
+const WEBSOCKET_SCHEME: &str = "wss://";
+
+/// How to reach a target
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Address {
+    /// A regular TLS connection
+    Tcp {
+        /// Host name or IP address, without brackets
+        host: String,
+        /// Port the target listens on
+        port: u16,
+    },
+    /// A TLS connection carried over a WebSocket, with the whole `wss://` URL
+    WebSocket(String),
+    /// A Unix socket of a target on this host
+    Unix(PathBuf),
+}
+
+/// A target together with the resource it was given with, if any
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Target {
+    /// How to reach the target
+    pub address: Address,
+    /// The resource given in a `cower://` URL
+    pub resource: Option<String>,
+}
+
+impl Target {
+    /// The host the target runs on, used as the TLS domain by default. `None` for Unix sockets
+    pub fn host(&self) -> Option<&str> {
+        match &self.address {
+            Address::Tcp { host, .. } => Some(host),
+            Address::WebSocket(url) => {
+                let authority = authority(url.strip_prefix(WEBSOCKET_SCHEME)?);
+                split_host_port(authority).ok().map(|(host, _)| host)
+            }
+            Address::Unix(_) => None,
+        }
+    }
+}
+
+/// What the target is recorded as in the known hosts file
+impl fmt::Display for Target {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.address {
+            Address::Tcp { host, port } if host.contains(':') => write!(f, "[{host}]:{port}"),
+            Address::Tcp { host, port } => write!(f, "{host}:{port}"),
+            Address::WebSocket(url) => f.write_str(url),
+            Address::Unix(path) => write!(f, "{}", path.display()),
+        }
+    }
+}
+
+/// The `host[:port]` part of what follows the scheme of a URL
+fn authority(rest: &str) -> &str {
+    rest.split_once('/')
+        .map_or(rest, |(authority, _)| authority)
+}
+
+/// Splits `host[:port]` apart, stripping the brackets around IPv6 addresses
+fn split_host_port(authority: &str) -> anyhow::Result<(&str, Option<u16>)> {
+    let (host, port) = if let Some(rest) = authority.strip_prefix('[') {
+        let (host, rest) = rest
+            .split_once(']')
+            .ok_or(anyhow!("Missing `]` in {authority}"))?;
+        let port = match rest {
+            "" => None,
+            rest => Some(
+                rest.strip_prefix(':')
+                    .ok_or(anyhow!("Unexpected {rest} after the address"))?,
+            ),
+        };
+
+        (host, port)
+    } else {
+        match authority.split_once(':') {
+            // more than one colon is an IPv6 address without a port
+            Some((_, port)) if port.contains(':') => (authority, None),
+            Some((host, port)) => (host, Some(port)),
+            None => (authority, None),
+        }
+    };
+
+    if host.is_empty() {
+        return Err(anyhow!("Missing host in {authority}"));
+    }
+    let port = port
+        .map(|port| port.parse().map_err(|_| anyhow!("Invalid port {port}")))
+        .transpose()?;
+
+    Ok((host, port))
+}
+
+impl FromStr for Target {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        if s.starts_with(WEBSOCKET_SCHEME) {
+            return Ok(Self {
+                address: Address::WebSocket(s.to_owned()),
+                resource: None,
+            });
+        }
+        if s.starts_with('/') || s.starts_with('.') {
+            return Ok(Self {
+                address: Address::Unix(PathBuf::from(s)),
+                resource: None,
+            });
+        }
+
+        let (authority, resource) = if let Some(rest) = s.strip_prefix(COWER_SCHEME) {
+            let resource = rest
+                .split_once('/')
+                .map(|(_, resource)| resource)
+                .filter(|resource| !resource.is_empty());
+            if resource.is_some_and(|resource| resource.contains('/')) {
+                return Err(anyhow!("Expected cower://host[:port]/resource, got {s}"));
+            }
+
+            (authority(rest), resource)
+        } else if let Some((scheme, _)) = s.split_once("://") {
+            return Err(anyhow!("Unsupported scheme {scheme}://"));
+        } else {
+            (s, None)
+        };
+
+        let (host, port) = split_host_port(authority)?;
+
+        Ok(Self {
+            address: Address::Tcp {
+                host: host.to_owned(),
+                port: port.unwrap_or(DEFAULT_PORT),
+            },
+            resource: resource.map(str::to_owned),
+        })
+    }
+}
+
+#[cfg(test)]
+mod target_tests {
+    use std::path::PathBuf;
+
+    use super::{Address, DEFAULT_PORT, Target};
+
+    fn tcp(host: &str, port: u16) -> Address {
+        Address::Tcp {
+            host: host.to_owned(),
+            port,
+        }
+    }
+
+    #[test]
+    fn parse_targets() -> anyhow::Result<()> {
+        let target: Target = "example.com".parse()?;
+        assert_eq!(target.address, tcp("example.com", DEFAULT_PORT));
+        assert_eq!(target.resource, None);
+        assert_eq!(target.to_string(), "example.com:9989");
+
+        let target: Target = "127.0.0.1:1234".parse()?;
+        assert_eq!(target.address, tcp("127.0.0.1", 1234));
+
+        let target: Target = "[::1]:1234".parse()?;
+        assert_eq!(target.address, tcp("::1", 1234));
+        assert_eq!(target.to_string(), "[::1]:1234");
+        assert_eq!("::1".parse::<Target>()?.address, tcp("::1", DEFAULT_PORT));
+
+        let target: Target = "cower://example.com/minecraft".parse()?;
+        assert_eq!(target.address, tcp("example.com", DEFAULT_PORT));
+        assert_eq!(target.resource.as_deref(), Some("minecraft"));
+
+        let target: Target = "cower://[::1]:1234".parse()?;
+        assert_eq!(target.address, tcp("::1", 1234));
+        assert_eq!(target.resource, None);
+
+        let target: Target = "wss://example.com:8443/cower".parse()?;
+        assert_eq!(target.host(), Some("example.com"));
+
+        let target: Target = "/run/cower.sock".parse()?;
+        assert_eq!(
+            target.address,
+            Address::Unix(PathBuf::from("/run/cower.sock"))
+        );
+        assert_eq!(target.host(), None);
+
+        Ok(())
+    }
+
+    #[test]
+    fn reject_malformed_targets() {
+        for target in [
+            "",
+            ":9989",
+            "example.com:port",
+            "example.com:99999",
+            "[::1",
+            "[::1]9989",
+            "https://example.com",
+            "cower://example.com/a/b",
+        ] {
+            assert!(target.parse::<Target>().is_err(), "{target} was accepted");
+        }
+    }
+}
@@ -0,0 +1,405 @@
+//! `cower-client tui`: a live list of the resources on a target, for people who'd rather press keys
+//! than type commands.
+//!
+//! The connection is driven by a worker thread, so that the interface stays responsive while the
+//! target is busy starting something. The list, and the logs if they're shown, are refreshed every
+//! few seconds.
+
+use std::{
+    collections::VecDeque,
+    sync::mpsc::{self, Receiver, Sender, TryRecvError},
+    thread,
+    time::{Duration, Instant},
+};
+
+use cower_client::{CowerClient, ResourceInfo};
+use cower_common::message::ContainerState;
+use ratatui::{
+    DefaultTerminal, Frame,
+    crossterm::event::{self, Event, KeyCode, KeyEventKind},
+    layout::{Constraint, Layout},
+    style::{Color, Style, Stylize},
+    text::Line,
+    widgets::{Block, Paragraph, Row, Table, TableState},
+};
+
+const REFRESH_INTERVAL: Duration = Duration::from_secs(3);
+/// How long to wait for a key before checking on the worker
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+/// Lines of logs requested from the target
+const LOG_TAIL: u32 = 200;
+/// Events kept at the bottom of the screen
+const MAX_EVENTS: u16 = 5;
+
+enum Request {
+    List,
+    Start(String),
+    Stop(String),
+    Logs(String),
+}
+
+enum Reply {
+    List(anyhow::Result<Vec<ResourceInfo>>),
+    State {
+        resource: String,
+        result: anyhow::Result<ContainerState>,
+    },
+    Logs {
+        resource: String,
+        result: anyhow::Result<Vec<u8>>,
+    },
+}
+
+/// Carries the requests out one by one over the only connection
+fn worker(mut client: CowerClient, requests: Receiver<Request>, replies: Sender<Reply>) {
+    for request in requests {
+        let reply = match request {
+            Request::List => Reply::List(client.list()),
+            Request::Start(resource) => Reply::State {
+                result: client.start(&resource),
+                resource,
+            },
+            Request::Stop(resource) => Reply::State {
+                result: client.stop(&resource, None, None),
+                resource,
+            },
+            Request::Logs(resource) => Reply::Logs {
+                result: client
+                    .logs(&resource, Some(LOG_TAIL), false)
+                    .and_then(|logs| logs.collect::<anyhow::Result<Vec<_>>>())
+                    .map(|chunks| chunks.concat()),
+                resource,
+            },
+        };
+
+        if replies.send(reply).is_err() {
+            break;
+        }
+    }
+
+    // the interface is gone, so there's nobody to tell about a failure
+    _ = client.close();
+}
+
+struct App {
+    title: String,
+    resources: Vec<ResourceInfo>,
+    table: TableState,
+    /// The resource whose logs are shown, and the logs once they arrive
+    logs: Option<(String, String)>,
+    events: VecDeque<String>,
+    requests: Sender<Request>,
+    /// Whether a list request is on its way, so that refreshes don't pile up behind a slow start
+    listing: bool,
+    /// Whether a logs request is on its way, for the same reason
+    fetching_logs: bool,
+    last_refresh: Option<Instant>,
+}
+
+impl App {
+    fn new(title: &str, requests: Sender<Request>) -> Self {
+        Self {
+            title: format!(" {title} "),
+            resources: vec![],
+            table: TableState::default(),
+            logs: None,
+            events: VecDeque::with_capacity(MAX_EVENTS.into()),
+            requests,
+            listing: false,
+            fetching_logs: false,
+            last_refresh: None,
+        }
+    }
+
+    fn selected(&self) -> Option<&ResourceInfo> {
+        self.resources.get(self.table.selected()?)
+    }
+
+    fn event(&mut self, event: String) {
+        if self.events.len() == usize::from(MAX_EVENTS) {
+            self.events.pop_front();
+        }
+        self.events.push_back(event);
+    }
+
+    fn request(&mut self, request: Request) {
+        if self.requests.send(request).is_err() {
+            self.event("The connection to the target is gone".to_owned());
+        }
+    }
+
+    fn refresh(&mut self) {
+        if !self.listing {
+            self.listing = true;
+            self.request(Request::List);
+        }
+        self.refresh_logs();
+        self.last_refresh = Some(Instant::now());
+    }
+
+    fn refresh_logs(&mut self) {
+        if let Some((resource, _)) = &self.logs
+            && !self.fetching_logs
+        {
+            let resource = resource.clone();
+            self.fetching_logs = true;
+            self.request(Request::Logs(resource));
+        }
+    }
+
+    fn handle_reply(&mut self, reply: Reply) {
+        match reply {
+            Reply::List(Ok(resources)) => {
+                self.listing = false;
+                self.resources = resources;
+                let selected = self.table.selected().unwrap_or_default();
+                self.table.select(match self.resources.len() {
+                    0 => None,
+                    len => Some(selected.min(len - 1)),
+                });
+            }
+            Reply::List(Err(why)) => {
+                self.listing = false;
+                self.event(format!("Couldn't list resources: {why:#}"));
+            }
+            Reply::State { resource, result } => match result {
+                Ok(state) => self.event(format!("{resource}: {state:?}")),
+                Err(why) => self.event(format!("{resource}: {why:#}")),
+            },
+            Reply::Logs { resource, result } => {
+                self.fetching_logs = false;
+                match (&mut self.logs, result) {
+                    (Some((shown, logs)), Ok(data)) if *shown == resource => {
+                        *logs = String::from_utf8_lossy(&data).into_owned();
+                    }
+                    // the logs of a different resource are shown by now, so get those instead
+                    (Some(_), Ok(_)) => self.refresh_logs(),
+                    (None, Ok(_)) => {}
+                    (_, Err(why)) => {
+                        self.event(format!("Couldn't get the logs of {resource}: {why:#}"))
+                    }
+                }
+            }
+        }
+    }
+
+    /// Handles a key press, returning whether to quit
+    fn handle_key(&mut self, key: KeyCode) -> bool {
+        match key {
+            KeyCode::Char('q') | KeyCode::Esc => return true,
+            KeyCode::Down | KeyCode::Char('j') => self.table.select_next(),
+            KeyCode::Up | KeyCode::Char('k') => self.table.select_previous(),
+            KeyCode::Char('r') => self.refresh(),
+            KeyCode::Char('s') => {
+                if let Some(resource) = self.selected().map(|resource| resource.name.clone()) {
+                    self.event(format!("Starting {resource}..."));
+                    self.request(Request::Start(resource));
+                    self.refresh();
+                }
+            }
+            KeyCode::Char('x') => {
+                if let Some(resource) = self.selected().map(|resource| resource.name.clone()) {
+                    self.event(format!("Stopping {resource}..."));
+                    self.request(Request::Stop(resource));
+                    self.refresh();
+                }
+            }
+            KeyCode::Char('l') | KeyCode::Enter => {
+                let selected = self.selected().map(|resource| resource.name.clone());
+                self.logs = match (self.logs.take(), selected) {
+                    (Some((shown, _)), Some(selected)) if shown == selected => None,
+                    (_, Some(selected)) => Some((selected, String::new())),
+                    (_, None) => None,
+                };
+                self.refresh();
+            }
+            _ => {}
+        }
+
+        false
+    }
+
+    fn draw(&mut self, frame: &mut Frame) {
+        let logs_height = if self.logs.is_some() {
+            Constraint::Percentage(50)
+        } else {
+            Constraint::Length(0)
+        };
+        let [table_area, logs_area, events_area, help_area] = Layout::vertical([
+            Constraint::Min(3),
+            logs_height,
+            Constraint::Length(MAX_EVENTS + 2),
+            Constraint::Length(1),
+        ])
+        .areas(frame.area());
+
+        let rows = self.resources.iter().map(|resource| {
+            let (state, color) = match resource.state {
+                Some(ContainerState::Running) => ("Running".to_owned(), Color::Green),
+                Some(ContainerState::Paused) => ("Paused".to_owned(), Color::Yellow),
+                Some(state) => (format!("{state:?}"), Color::Red),
+                None => ("group".to_owned(), Color::DarkGray),
+            };
+
+            Row::new([
+                resource.name.clone().into(),
+                Line::from(state).style(Style::new().fg(color)),
+                resource.description.clone().unwrap_or_default().into(),
+            ])
+        });
+        let table = Table::new(
+            rows,
+            [
+                Constraint::Percentage(30),
+                Constraint::Length(12),
+                Constraint::Fill(1),
+            ],
+        )
+        .header(Row::new(["Resource", "State", "Description"]).bold())
+        .row_highlight_style(Style::new().reversed())
+        .block(Block::bordered().title(self.title.as_str()));
+        frame.render_stateful_widget(table, table_area, &mut self.table);
+
+        if let Some((resource, logs)) = &self.logs {
+            // only the end of the logs fits
+            let visible = usize::from(logs_area.height.saturating_sub(2));
+            let lines: Vec<Line> = logs.lines().map(Line::raw).collect();
+            let skip = lines.len().saturating_sub(visible);
+            let paragraph = Paragraph::new(lines.into_iter().skip(skip).collect::<Vec<_>>())
+                .block(Block::bordered().title(format!("Logs of {resource}")));
+            frame.render_widget(paragraph, logs_area);
+        }
+
+        let events: Vec<Line> = self
+            .events
+            .iter()
+            .map(|event| Line::raw(event.as_str()))
+            .collect();
+        frame.render_widget(
+            Paragraph::new(events).block(Block::bordered().title("Events")),
+            events_area,
+        );
+
+        frame.render_widget(
+            Line::raw("↑/↓ select  s start  x stop  l logs  r refresh  q quit").dark_gray(),
+            help_area,
+        );
+    }
+
+    fn run(
+        &mut self,
+        terminal: &mut DefaultTerminal,
+        replies: &Receiver<Reply>,
+    ) -> anyhow::Result<()> {
+        loop {
+            if self
+                .last_refresh
+                .is_none_or(|last| last.elapsed() >= REFRESH_INTERVAL)
+            {
+                self.refresh();
+            }
+
+            loop {
+                match replies.try_recv() {
+                    Ok(reply) => self.handle_reply(reply),
+                    Err(TryRecvError::Empty) => break,
+                    Err(TryRecvError::Disconnected) => {
+                        return Err(anyhow::anyhow!("The connection to the target is gone"));
+                    }
+                }
+            }
+
+            terminal.draw(|frame| self.draw(frame))?;
+
+            if event::poll(POLL_INTERVAL)?
+                && let Event::Key(key) = event::read()?
+                && key.kind == KeyEventKind::Press
+                && self.handle_key(key.code)
+            {
+                return Ok(());
+            }
+        }
+    }
+}
+
+/// Runs the interface until the user quits, closing the connection afterwards
+pub fn run(client: CowerClient, title: &str) -> anyhow::Result<()> {
+    let (requests, worker_requests) = mpsc::channel();
+    let (worker_replies, replies) = mpsc::channel();
+    let handle = thread::spawn(move || worker(client, worker_requests, worker_replies));
+
+    let mut app = App::new(title, requests);
+
+    let mut terminal = ratatui::init();
+    let result = app.run(&mut terminal, &replies);
+    ratatui::restore();
+
+    // the worker stops after the request it's carrying out, as nobody takes its reply anymore. A
+    // start can take minutes, so it isn't waited for: the connection goes away with the process
+    drop(app);
+    drop(replies);
+    drop(handle);
+
+    result
+}
+
+#[cfg(test)]
+mod tui_tests {
+    use std::sync::mpsc;
+
+    use cower_client::ResourceInfo;
+    use cower_common::message::ContainerState;
+    use ratatui::crossterm::event::KeyCode;
+
+    use super::{App, Reply, Request};
+
+    fn resource(name: &str) -> ResourceInfo {
+        ResourceInfo {
+            name: name.to_owned(),
+            state: Some(ContainerState::Exited),
+            description: None,
+        }
+    }
+
+    #[test]
+    fn keys_make_requests() {
+        let (requests, received) = mpsc::channel();
+        let mut app = App::new("target", requests);
+
+        app.refresh();
+        assert!(matches!(received.try_recv(), Ok(Request::List)));
+        // no second list request while the first one is on its way
+        app.refresh();
+        assert!(received.try_recv().is_err());
+
+        app.handle_reply(Reply::List(Ok(vec![resource("db"), resource("web")])));
+        assert_eq!(app.table.selected(), Some(0));
+
+        app.handle_key(KeyCode::Down);
+        app.handle_key(KeyCode::Char('s'));
+        assert!(matches!(received.try_recv(), Ok(Request::Start(name)) if name == "web"));
+        assert!(matches!(received.try_recv(), Ok(Request::List)));
+
+        app.handle_key(KeyCode::Char('l'));
+        assert!(matches!(received.try_recv(), Ok(Request::Logs(name)) if name == "web"));
+        // no second logs request while the first one is on its way
+        app.refresh();
+        assert!(received.try_recv().is_err());
+
+        // logs of another resource are asked for once those of the previous one arrive
+        app.handle_key(KeyCode::Up);
+        app.handle_key(KeyCode::Char('l'));
+        assert!(received.try_recv().is_err());
+        app.handle_reply(Reply::Logs {
+            resource: "web".to_owned(),
+            result: Ok(b"started".to_vec()),
+        });
+        assert!(matches!(received.try_recv(), Ok(Request::Logs(name)) if name == "db"));
+
+        // the selection stays within a list that got shorter
+        app.handle_reply(Reply::List(Ok(vec![resource("db")])));
+        assert_eq!(app.table.selected(), Some(0));
+
+        assert!(app.handle_key(KeyCode::Char('q')));
+    }
+}
@@ -0,0 +1,418 @@
+//! `cower-client start --wait`: starts resources and waits until they're ready, so that whatever
+//! needs them can be run right after the client. Connections that fail or break on the way are
+//! retried with exponential backoff, as the target may itself still be starting.
+
+use std::{
+    fmt, thread,
+    time::{Duration, Instant},
+};
+
+use cower_client::{CowerClient, DEFAULT_TIMEOUTS, TargetError};
+use cower_common::{Timeouts, message::ContainerState};
+
+use crate::output::{ErrorClass, Output};
+
+/// How often the state of a starting resource is checked
+const POLL_INTERVAL: Duration = Duration::from_secs(1);
+/// The wait before the first retry, doubled after every failed one
+const FIRST_BACKOFF: Duration = Duration::from_millis(250);
+const MAX_BACKOFF: Duration = Duration::from_secs(8);
+
+/// Reported when the deadline passes before the resources are ready
+#[derive(Debug)]
+pub struct TimedOut;
+
+impl fmt::Display for TimedOut {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Gave up waiting")
+    }
+}
+
+impl std::error::Error for TimedOut {}
+
+/// The time to wait before retrying, and when to stop retrying
+struct Backoff {
+    delay: Duration,
+    deadline: Instant,
+}
+
+impl Backoff {
+    fn new(deadline: Instant) -> Self {
+        Self {
+            delay: FIRST_BACKOFF,
+            deadline,
+        }
+    }
+
+    /// Sleeps before the next attempt, or gives `why` up if the deadline would pass by then
+    fn wait(&mut self, why: anyhow::Error, output: &Output) -> anyhow::Result<()> {
+        if Instant::now() + self.delay >= self.deadline {
+            return Err(why.context(TimedOut));
+        }
+
+        output.progress(&format!("{why:#}, retrying in {:?}", self.delay));
+        thread::sleep(self.delay);
+        self.delay = (self.delay * 2).min(MAX_BACKOFF);
+
+        Ok(())
+    }
+}
+
+/// Caps `timeouts` at the time left before `deadline`, so that a target that stops responding
+/// can't keep the client waiting past it. Fails once the deadline has passed
+pub fn cap(timeouts: Timeouts, deadline: Instant) -> anyhow::Result<Timeouts> {
+    let left = deadline.saturating_duration_since(Instant::now());
+    if left.is_zero() {
+        return Err(TimedOut.into());
+    }
+    let cap = |timeout: Option<Duration>| Some(timeout.map_or(left, |timeout| timeout.min(left)));
+
+    Ok(Timeouts {
+        handshake: cap(timeouts.handshake),
+        read: cap(timeouts.read),
+        write: cap(timeouts.write),
+        // the read timeout runs out first anyway
+        idle: timeouts.idle,
+    })
+}
+
+/// Whether trying again might help, i.e. whether the target couldn't be reached or the connection
+/// broke. Anything the target replied with would just be replied with again
+fn is_transient(why: &anyhow::Error) -> bool {
+    ErrorClass::of(why) == ErrorClass::Connection
+}
+
+/// Makes `attempt` until it succeeds, fails for good, or the deadline passes
+pub fn retry<T>(
+    deadline: Instant,
+    output: &Output,
+    mut attempt: impl FnMut() -> anyhow::Result<T>,
+) -> anyhow::Result<T> {
+    let mut backoff = Backoff::new(deadline);
+    loop {
+        match attempt() {
+            Err(why) if is_transient(&why) => backoff.wait(why, output)?,
+            result => return result,
+        }
+    }
+}
+
+/// Starts resources and waits for them over a connection that's replaced whenever it breaks
+pub struct Waiter<'a, F> {
+    client: &'a mut CowerClient,
+    deadline: Instant,
+    output: &'a Output,
+    reconnect: F,
+}
+
+impl<'a, F: FnMut(Timeouts) -> anyhow::Result<CowerClient>> Waiter<'a, F> {
+    /// `reconnect` is called with the timeouts the new connection should use
+    pub fn new(
+        client: &'a mut CowerClient,
+        deadline: Instant,
+        output: &'a Output,
+        reconnect: F,
+    ) -> Self {
+        Self {
+            client,
+            deadline,
+            output,
+            reconnect,
+        }
+    }
+
+    /// Makes a request, reconnecting and making it again if the connection breaks. Starting a
+    /// resource twice does no harm, so every request is safe to repeat. The request can't take
+    /// longer than the time left before the deadline
+    fn request<T>(
+        &mut self,
+        mut request: impl FnMut(&mut CowerClient) -> anyhow::Result<T>,
+    ) -> anyhow::Result<T> {
+        let mut backoff = Backoff::new(self.deadline);
+        let mut broken = false;
+        loop {
+            let timeouts = cap(DEFAULT_TIMEOUTS, self.deadline)?;
+            let result = if broken {
+                (self.reconnect)(timeouts).map(|client| *self.client = client)
+            } else {
+                let conn = self.client.connection();
+                conn.set_timeouts(timeouts).map_err(anyhow::Error::from)
+            };
+
+            match result.and_then(|()| request(self.client)) {
+                Err(why) if is_transient(&why) => {
+                    broken = true;
+                    backoff.wait(why, self.output)?;
+                }
+                result => return result,
+            }
+        }
+    }
+
+    /// Polls `resource` until it's ready: running, and passing its healthcheck if it has one.
+    /// Targets that don't report readiness are trusted once the resource is running
+    fn until_ready(&mut self, resource: &str) -> anyhow::Result<()> {
+        let mut last = None;
+        loop {
+            let (state, ready) = self.request(|client| client.readiness(resource))?;
+            match state {
+                ContainerState::Running if ready != Some(false) => return Ok(()),
+                ContainerState::Exited
+                | ContainerState::Dead
+                | ContainerState::Paused
+                | ContainerState::Removing => {
+                    return Err(anyhow::anyhow!(
+                        "{resource} is {state:?} instead of running"
+                    ));
+                }
+                _ => {}
+            }
+
+            let status = match state {
+                ContainerState::Running => "running but not healthy yet".to_owned(),
+                state => format!("{state:?}"),
+            };
+            if last.as_ref() != Some(&status) {
+                self.output
+                    .progress(&format!("{resource} is {status}, waiting"));
+                last = Some(status);
+            }
+
+            if Instant::now() + POLL_INTERVAL >= self.deadline {
+                let why = anyhow::Error::new(TimedOut);
+                let status = last.unwrap_or_default();
+                return Err(why.context(format!("{resource} is still {status}")));
+            }
+            thread::sleep(POLL_INTERVAL);
+        }
+    }
+
+    /// Starts the resources and waits until they're all ready, printing their states once they
+    /// are. Resources the target refuses to start aren't waited for
+    pub fn start(&mut self, resources: &[String]) -> anyhow::Result<()> {
+        let outcomes = self.request(|client| client.start_all(resources))?;
+
+        let mut failures = vec![];
+        let mut started = vec![];
+        for outcome in outcomes {
+            match outcome.state {
+                Ok(_) => started.push(outcome.resource),
+                Err(code) if resources.len() == 1 => return Err(TargetError(code).into()),
+                Err(code) => {
+                    self.output.resource_error(&outcome.resource, code)?;
+                    failures.push(code);
+                }
+            }
+        }
+
+        for resource in started {
+            self.until_ready(&resource)?;
+            match resources.len() {
+                1 => self.output.state(&resource, ContainerState::Running)?,
+                _ => self
+                    .output
+                    .resource_state(&resource, ContainerState::Running)?,
+            }
+        }
+
+        // like without waiting, the exit code is that of the first failure
+        if let Some(&code) = failures.first() {
+            return Err(anyhow::Error::new(TargetError(code)).context(format!(
+                "{} of {} requests failed",
+                failures.len(),
+                resources.len()
+            )));
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod wait_tests {
+    use std::{
+        cell::Cell,
+        io,
+        net::TcpListener,
+        thread::{self, JoinHandle},
+        time::{Duration, Instant},
+    };
+
+    use anyhow::anyhow;
+    use cower_client::{CowerClient, DEFAULT_TIMEOUTS, target::Target};
+    use cower_common::{
+        Acceptor, Timeouts,
+        message::{CloseReason, ContainerState, Message},
+        tls::{Certificate, Identity, ServerVerification},
+    };
+
+    use super::{TimedOut, Waiter, cap, retry};
+    use crate::output::{ErrorClass, Format, Output};
+
+    const IDENT_FILE: &[u8] = include_bytes!("../../test-keys/identity.p12");
+    const IDENT_PASS: &str = include_str!("../../test-keys/creds.asc");
+    const CUSTOM_CERT: &[u8] = include_bytes!("../../test-keys/cert.crt");
+
+    fn refused() -> anyhow::Error {
+        cower_common::Error::from(io::Error::from(io::ErrorKind::ConnectionRefused)).into()
+    }
+
+    fn state(state: ContainerState, ready: Option<bool>) -> Message {
+        Message::StateMessage { state, ready }
+    }
+
+    /// A target answering the requests made over each connection with the replies given for it,
+    /// closing the connection once they run out. Returns the requests it received
+    fn fake_target(
+        connections: Vec<Vec<Message>>,
+    ) -> anyhow::Result<(Target, JoinHandle<cower_common::Result<Vec<Message>>>)> {
+        let acceptor = Acceptor::new(Identity::from_pkcs12(IDENT_FILE, IDENT_PASS.trim())?)?;
+        let listener = TcpListener::bind(("127.0.0.1", 0))?;
+        let target = format!("127.0.0.1:{}", listener.local_addr()?.port()).parse()?;
+
+        let handle = thread::spawn(move || {
+            let mut requests = vec![];
+            for replies in connections {
+                let (stream, _) = listener.accept()?;
+                let mut conn = acceptor.accept(stream)?;
+                for reply in replies {
+                    let (request_id, request) = conn.receive_request()?;
+                    requests.push(request);
+                    conn.send_request(request_id, &reply)?;
+                }
+                // like a target that's restarting
+                conn.close(CloseReason::GoingAway, None)?;
+            }
+
+            Ok(requests)
+        });
+
+        Ok((target, handle))
+    }
+
+    fn connect(target: &Target, timeouts: Timeouts) -> anyhow::Result<CowerClient> {
+        let verification =
+            ServerVerification::CertificateAuthority(Some(Certificate::from_pem(CUSTOM_CERT)?));
+
+        CowerClient::connect_with_timeouts(target, Some("localhost"), &verification, timeouts)
+    }
+
+    #[test]
+    fn wait_for_healthcheck() -> anyhow::Result<()> {
+        let (target, handle) = fake_target(vec![
+            vec![
+                state(ContainerState::Created, None),
+                state(ContainerState::Running, Some(false)),
+            ],
+            vec![state(ContainerState::Running, Some(true))],
+        ])?;
+        let output = Output::new(Format::Json);
+        let deadline = Instant::now() + Duration::from_secs(10);
+
+        let mut client = connect(&target, DEFAULT_TIMEOUTS)?;
+        let reconnects = Cell::new(0);
+        let reconnect = |timeouts: Timeouts| {
+            reconnects.set(reconnects.get() + 1);
+            // the connection can't outlast the deadline
+            assert!(
+                timeouts
+                    .read
+                    .is_some_and(|read| read <= Duration::from_secs(10))
+            );
+            connect(&target, timeouts)
+        };
+        Waiter::new(&mut client, deadline, &output, reconnect).start(&["web".to_owned()])?;
+
+        // the status request on the closed connection was made again over a new one
+        assert_eq!(reconnects.get(), 1);
+        let requests = handle.join().expect("fake target panicked")?;
+        assert!(matches!(
+            requests.as_slice(),
+            [
+                Message::StartMessage { .. },
+                Message::StatusMessage { .. },
+                Message::StatusMessage { .. }
+            ]
+        ));
+
+        Ok(())
+    }
+
+    #[test]
+    fn give_up_on_unhealthy() -> anyhow::Result<()> {
+        let (target, handle) = fake_target(vec![vec![
+            state(ContainerState::Created, None),
+            state(ContainerState::Running, Some(false)),
+        ]])?;
+        let output = Output::new(Format::Json);
+        let deadline = Instant::now() + Duration::from_millis(500);
+
+        let mut client = connect(&target, DEFAULT_TIMEOUTS)?;
+        let reconnect = |timeouts| connect(&target, timeouts);
+        let why = Waiter::new(&mut client, deadline, &output, reconnect)
+            .start(&["web".to_owned()])
+            .expect_err("an unhealthy resource was taken for ready");
+        assert_eq!(ErrorClass::of(&why), ErrorClass::Timeout);
+        assert_eq!(why.to_string(), "web is still running but not healthy yet");
+        handle.join().expect("fake target panicked")?;
+
+        // targets that don't report readiness are trusted once the resource is running
+        let (target, handle) = fake_target(vec![vec![
+            state(ContainerState::Created, None),
+            state(ContainerState::Running, None),
+        ]])?;
+        let deadline = Instant::now() + Duration::from_secs(10);
+        let mut client = connect(&target, DEFAULT_TIMEOUTS)?;
+        let reconnect = |timeouts| connect(&target, timeouts);
+        Waiter::new(&mut client, deadline, &output, reconnect).start(&["web".to_owned()])?;
+        handle.join().expect("fake target panicked")?;
+
+        Ok(())
+    }
+
+    #[test]
+    fn cap_timeouts() -> anyhow::Result<()> {
+        let deadline = Instant::now() + Duration::from_secs(5);
+        let timeouts = cap(DEFAULT_TIMEOUTS, deadline)?;
+        assert!(timeouts.handshake <= DEFAULT_TIMEOUTS.handshake);
+        for timeout in [timeouts.read, timeouts.write] {
+            assert!(timeout.is_some_and(|timeout| timeout <= Duration::from_secs(5)));
+        }
+
+        let why = cap(DEFAULT_TIMEOUTS, Instant::now()).expect_err("the deadline has passed");
+        assert!(why.downcast_ref::<TimedOut>().is_some());
+
+        Ok(())
+    }
+
+    #[test]
+    fn retry_transient_failures() {
+        let output = Output::new(Format::Json);
+        let deadline = Instant::now() + Duration::from_secs(10);
+
+        let attempts = Cell::new(0);
+        let result = retry(deadline, &output, || {
+            attempts.set(attempts.get() + 1);
+            match attempts.get() {
+                3 => Ok(()),
+                _ => Err(refused()),
+            }
+        });
+        assert!(result.is_ok());
+        assert_eq!(attempts.get(), 3);
+
+        // what the target replies with won't change by asking again
+        attempts.set(0);
+        let result: anyhow::Result<()> = retry(deadline, &output, || {
+            attempts.set(attempts.get() + 1);
+            Err(anyhow!("bad config"))
+        });
+        assert_eq!(ErrorClass::of(&result.unwrap_err()), ErrorClass::Other);
+        assert_eq!(attempts.get(), 1);
+
+        let result: anyhow::Result<()> = retry(Instant::now(), &output, || Err(refused()));
+        let why = result.unwrap_err();
+        assert!(why.downcast_ref::<TimedOut>().is_some());
+        assert_eq!(ErrorClass::of(&why), ErrorClass::Timeout);
+    }
+}
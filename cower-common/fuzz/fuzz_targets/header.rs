@@ -0,0 +1,11 @@
+#![no_main]
+
+use cower_common::message::MessageHeader;
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    if let Ok(header) = MessageHeader::deserialize(data) {
+        // whatever parses has to serialize back to the bytes it was parsed from
+        assert_eq!(header.serialize(), data[..header.size().into()]);
+    }
+});
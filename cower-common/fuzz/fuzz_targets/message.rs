@@ -0,0 +1,17 @@
+#![no_main]
+
+use cower_common::message::{Message, MessageHeader};
+use libfuzzer_sys::fuzz_target;
+
+// parses a frame the way `Connection::receive` does, except that the payload isn't required to
+// be as long as the header says
+fuzz_target!(|data: &[u8]| {
+    let Ok(header) = MessageHeader::deserialize(data) else {
+        return;
+    };
+    let payload = data.get(header.size().into()..).unwrap_or_default();
+
+    if let Ok(message) = Message::deserialize(&header, payload) {
+        _ = message.serialize();
+    }
+});
@@ -0,0 +1,607 @@
+//! Async counterparts of [`crate::Connection`] and [`crate::Acceptor`] built on `tokio`. Enable
+//! the `tokio-native-tls` or `tokio-rustls` feature, whichever matches the TLS backend, to use
+//! them.
+//!
+//! Messages are encoded the same way as with the blocking types, so both ends of a connection
+//! don't have to agree on which one they use.
+
+use std::{future::Future, io, marker::PhantomData, time::Duration};
+
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::{TcpStream, ToSocketAddrs},
+    time::Instant,
+};
+
+use crate::{
+    Acceptor, Client, MAX_RETAINED_BUFFER, Server, Timeouts,
+    message::{
+        CloseReason, DEFAULT_MAX_PAYLOAD_LENGTH, HEADER_SIZE, Message, MessageHeader,
+        NO_REQUEST_ID, RequestId,
+    },
+    tls::{
+        AsyncStream, Certificate, Identity, PeerIdentity, ServerVerification, TlsAcceptor,
+        TlsConnector,
+    },
+};
+
+/// How much room is made in the read buffer before every read
+const READ_CHUNK_SIZE: usize = 8 * 1024;
+
+/// Runs `future`, failing with [`crate::Error::PeerTimedOut`] if it doesn't finish within
+/// `timeout`
+pub(crate) async fn limit<T>(
+    timeout: Option<Duration>,
+    future: impl Future<Output = crate::Result<T>>,
+) -> crate::Result<T> {
+    match timeout {
+        Some(timeout) => tokio::time::timeout(timeout, future)
+            .await
+            .map_err(|_| crate::Error::PeerTimedOut)?,
+        None => future.await,
+    }
+}
+
+/// An encrypted connection between `cower` programs, usable from async code
+///
+/// # Initialization
+///
+/// If you need to connect to a client, use [`AsyncConnection::connect`]. If you need to accept a
+/// connection from a client, use [`AsyncAcceptor`] instead.
+///
+/// # Cancellation
+///
+/// Sending and receiving are cancel-safe: what was read of a frame or is left to write of a
+/// message is kept on the connection, so a future dropped halfway (for example by
+/// `tokio::time::timeout` or `tokio::select!`) doesn't corrupt the stream. A cancelled message is
+/// sent before the next one.
+pub struct AsyncConnection<T> {
+    stream: Box<dyn AsyncStream>,
+    peer: Option<PeerIdentity>,
+    /// Received bytes that don't make up a whole frame yet
+    read_buf: Vec<u8>,
+    /// Bytes of sent messages that haven't been written to the stream yet
+    write_buf: Vec<u8>,
+    max_payload_length: u32,
+    timeouts: Timeouts,
+    last_received: Instant,
+    ping_sent: bool,
+    #[cfg(unix)]
+    credentials: Option<crate::unix::PeerCredentials>,
+    _0: PhantomData<T>,
+}
+
+impl<T> AsyncConnection<T> {
+    pub(crate) fn new(stream: Box<dyn AsyncStream>, peer: Option<PeerIdentity>) -> Self {
+        Self {
+            stream,
+            peer,
+            read_buf: Vec::new(),
+            write_buf: Vec::new(),
+            max_payload_length: DEFAULT_MAX_PAYLOAD_LENGTH,
+            timeouts: Timeouts::default(),
+            last_received: Instant::now(),
+            ping_sent: false,
+            #[cfg(unix)]
+            credentials: None,
+            _0: PhantomData,
+        }
+    }
+
+    /// Fingerprints of the certificate the server presented. This is only available on the
+    /// client's end
+    pub fn peer_identity(&self) -> Option<&PeerIdentity> {
+        self.peer.as_ref()
+    }
+
+    /// Credentials of the process on the other end. This is only available on the accepting end
+    /// of Unix socket connections
+    #[cfg(unix)]
+    pub fn peer_credentials(&self) -> Option<&crate::unix::PeerCredentials> {
+        self.credentials.as_ref()
+    }
+
+    /// Sets the maximum length of payloads accepted from the peer, [`DEFAULT_MAX_PAYLOAD_LENGTH`]
+    /// by default
+    pub fn set_max_payload_length(&mut self, length: u32) {
+        self.max_payload_length = length;
+    }
+
+    /// Sets the read, write and idle timeouts, see [`Timeouts`]. The handshake timeout only
+    /// applies to [`AsyncConnection::connect_with_timeouts`] and [`AsyncAcceptor`]
+    pub fn set_timeouts(&mut self, timeouts: Timeouts) {
+        self.timeouts = timeouts;
+    }
+
+    /// Send a message over the connection
+    pub async fn send(&mut self, message: &Message) -> crate::Result<()> {
+        self.send_request(NO_REQUEST_ID, message).await
+    }
+
+    /// Send a message tagged with `request_id` over the connection
+    pub async fn send_request(
+        &mut self,
+        request_id: RequestId,
+        message: &Message,
+    ) -> crate::Result<()> {
+        self.write_buf
+            .extend(message.serialize_request(request_id)?);
+
+        self.flush().await
+    }
+
+    /// Sends a [`Message::PingMessage`], which the peer answers with a
+    /// [`Message::PongMessage`] the next time it receives
+    pub async fn ping(&mut self) -> crate::Result<()> {
+        self.send(&Message::PingMessage).await?;
+        self.ping_sent = true;
+
+        Ok(())
+    }
+
+    /// Writes out what's left of the messages sent so far
+    async fn flush(&mut self) -> crate::Result<()> {
+        let timeout = self.timeouts.write;
+        let write = async {
+            while !self.write_buf.is_empty() {
+                let written = self.stream.write(&self.write_buf).await?;
+                if written == 0 {
+                    return Err(io::Error::from(io::ErrorKind::WriteZero).into());
+                }
+                self.write_buf.drain(..written);
+            }
+            self.stream.flush().await?;
+            self.write_buf.shrink_to(MAX_RETAINED_BUFFER);
+
+            Ok(())
+        };
+
+        limit(timeout, write).await
+    }
+
+    /// Closes the connection on purpose, telling the peer why. This is the async counterpart of
+    /// [`crate::Connection::close`]
+    pub async fn close(mut self, reason: CloseReason, text: Option<&str>) -> crate::Result<()> {
+        self.send(&Message::CloseMessage {
+            reason,
+            text: text.map(str::to_owned),
+        })
+        .await?;
+        // sends the TLS close_notify alert
+        self.stream.shutdown().await?;
+
+        Ok(())
+    }
+
+    /// Receive a message over the connection
+    pub async fn receive(&mut self) -> crate::Result<Message> {
+        self.receive_request().await.map(|(_, message)| message)
+    }
+
+    /// Receive a message over the connection, together with the request ID it is tagged with
+    ///
+    /// Fails with [`crate::Error::MesssageTooBig`] if the peer announces a payload longer than
+    /// [`AsyncConnection::set_max_payload_length`] allows. The payload isn't read in that case, so
+    /// the connection can't be used anymore.
+    ///
+    /// Pings from the peer are answered here, so keep receiving even when no replies are expected
+    /// if the peer has an idle timeout.
+    pub async fn receive_request(&mut self) -> crate::Result<(RequestId, Message)> {
+        let started = Instant::now();
+
+        loop {
+            // pongs and messages whose sending was cancelled go out first
+            self.flush().await?;
+
+            let frame = match self.wait_time(started) {
+                Some(wait) => tokio::time::timeout(wait, self.receive_frame()).await.ok(),
+                None => Some(self.receive_frame().await),
+            };
+
+            match frame.transpose()? {
+                Some((request_id, Message::PingMessage)) => {
+                    self.send_request(request_id, &Message::PongMessage).await?;
+                }
+                Some((_, Message::PongMessage)) => {}
+                Some((_, Message::CloseMessage { reason, text })) => {
+                    return Err(crate::Error::Closed { reason, text });
+                }
+                Some(request) => return Ok(request),
+                None => {
+                    if let Some(idle) = self.timeouts.idle {
+                        let silence = self.last_received.elapsed();
+                        if silence >= idle {
+                            return Err(crate::Error::PeerTimedOut);
+                        }
+                        if !self.ping_sent && silence >= idle / 2 {
+                            self.ping().await?;
+                        }
+                    }
+                    if self
+                        .timeouts
+                        .read
+                        .is_some_and(|read| started.elapsed() >= read)
+                    {
+                        return Err(io::Error::from(io::ErrorKind::TimedOut).into());
+                    }
+                }
+            }
+        }
+    }
+
+    /// How long to wait for the next frame before checking on the read and idle timeouts
+    fn wait_time(&self, started: Instant) -> Option<Duration> {
+        let read = self
+            .timeouts
+            .read
+            .map(|read| read.saturating_sub(started.elapsed()));
+        let idle = self.timeouts.idle.map(|idle| {
+            let deadline = if self.ping_sent { idle } else { idle / 2 };
+            deadline.saturating_sub(self.last_received.elapsed())
+        });
+
+        read.into_iter().chain(idle).min()
+    }
+
+    /// Receives a single frame, whatever message it holds
+    async fn receive_frame(&mut self) -> crate::Result<(RequestId, Message)> {
+        loop {
+            if let Some(frame) = self.take_frame()? {
+                return Ok(frame);
+            }
+
+            // reading is cancel-safe, and what's read is kept in the buffer
+            self.read_buf.reserve(READ_CHUNK_SIZE);
+            if self.stream.read_buf(&mut self.read_buf).await? == 0 {
+                return Err(io::Error::from(io::ErrorKind::UnexpectedEof).into());
+            }
+            self.last_received = Instant::now();
+            self.ping_sent = false;
+        }
+    }
+
+    /// Takes the first frame out of the read buffer if it has been received whole
+    fn take_frame(&mut self) -> crate::Result<Option<(RequestId, Message)>> {
+        let Some(short_header) = self.read_buf.get(..HEADER_SIZE.into()) else {
+            return Ok(None);
+        };
+        let header_size = MessageHeader::serialized_size(short_header);
+        let header_size = usize::from(header_size.inspect_err(|_| self.read_buf.clear())?);
+        let Some(header) = self.read_buf.get(..header_size) else {
+            return Ok(None);
+        };
+
+        // a header that's rejected is dropped, so that it isn't rejected again by the next receive
+        let header = match MessageHeader::deserialize(header) {
+            Ok(header) if header.length <= self.max_payload_length => header,
+            Ok(_) => {
+                self.read_buf.drain(..header_size);
+                return Err(crate::Error::MesssageTooBig);
+            }
+            Err(why) => {
+                self.read_buf.drain(..header_size);
+                return Err(why);
+            }
+        };
+
+        let payload_length =
+            usize::try_from(header.length).map_err(|_| crate::Error::MesssageTooBig)?;
+        let frame_length = header_size.saturating_add(payload_length);
+        let Some(payload) = self.read_buf.get(header_size..frame_length) else {
+            return Ok(None);
+        };
+        let message = Message::deserialize_request(&header, payload);
+
+        self.read_buf.drain(..frame_length);
+        // don't keep large buffers around for the rest of the connection
+        self.read_buf.shrink_to(MAX_RETAINED_BUFFER);
+
+        message.map(Some)
+    }
+}
+
+impl AsyncConnection<()> {
+    /// Connects to the given server
+    pub async fn connect<A: ToSocketAddrs>(
+        addr: A,
+        domain: &str,
+        custom_cert: Option<Certificate>,
+    ) -> crate::Result<AsyncConnection<Client>> {
+        let verification = ServerVerification::CertificateAuthority(custom_cert);
+
+        Self::connect_with(addr, domain, &verification).await
+    }
+
+    /// Connects to the given server, checking its certificate according to `verification`
+    pub async fn connect_with<A: ToSocketAddrs>(
+        addr: A,
+        domain: &str,
+        verification: &ServerVerification,
+    ) -> crate::Result<AsyncConnection<Client>> {
+        Self::connect_with_timeouts(addr, domain, verification, Timeouts::default()).await
+    }
+
+    /// Same as [`AsyncConnection::connect_with`], but gives up on a server that doesn't respond
+    /// in time and applies `timeouts` to the resulting connection
+    pub async fn connect_with_timeouts<A: ToSocketAddrs>(
+        addr: A,
+        domain: &str,
+        verification: &ServerVerification,
+        timeouts: Timeouts,
+    ) -> crate::Result<AsyncConnection<Client>> {
+        let handshake = async {
+            let stream = TcpStream::connect(addr).await?;
+            TlsConnector::new(verification)?
+                .connect_async(domain, stream)
+                .await
+        };
+        let (tls_stream, peer_cert) = limit(timeouts.handshake, handshake).await?;
+        let peer = verification.check(peer_cert.as_deref())?;
+
+        let mut conn = AsyncConnection::new(tls_stream, peer);
+        conn.set_timeouts(timeouts);
+
+        Ok(conn)
+    }
+
+    /// Wraps a connection accepted by a [`tokio::net::UnixListener`]. This is the async
+    /// counterpart of [`crate::Connection::accept_unix`], so check
+    /// [`AsyncConnection::peer_credentials`] before serving the peer
+    #[cfg(unix)]
+    pub fn accept_unix(stream: tokio::net::UnixStream) -> crate::Result<AsyncConnection<Server>> {
+        let credentials = crate::unix::PeerCredentials::of_socket(&stream)?;
+
+        Ok(AsyncConnection {
+            credentials: Some(credentials),
+            ..AsyncConnection::new(Box::new(stream), None)
+        })
+    }
+}
+
+/// Accepts and initiates connections, verifies the identity of clients. This is the async
+/// counterpart of [`Acceptor`]
+#[derive(Clone)]
+pub struct AsyncAcceptor {
+    pub(crate) tls: TlsAcceptor,
+    pub(crate) timeouts: Timeouts,
+}
+
+impl AsyncAcceptor {
+    /// Constructs a new acceptor with sane TLS configuration.
+    pub fn new(identity: Identity) -> crate::Result<AsyncAcceptor> {
+        Ok(Self {
+            tls: TlsAcceptor::new(identity)?,
+            timeouts: Timeouts::default(),
+        })
+    }
+
+    /// Sets the timeouts of the handshake and of accepted connections, see [`Timeouts`]
+    pub fn set_timeouts(&mut self, timeouts: Timeouts) {
+        self.timeouts = timeouts;
+    }
+
+    /// Accepts an incoming connection. Pass the stream in before writing anything to it.
+    ///
+    /// Fails with [`crate::Error::PeerTimedOut`] if the handshake takes longer than the
+    /// handshake timeout.
+    pub async fn accept(&self, stream: TcpStream) -> crate::Result<AsyncConnection<Server>> {
+        let tls_stream = limit(self.timeouts.handshake, self.tls.accept_async(stream)).await?;
+
+        let mut conn = AsyncConnection::new(tls_stream, None);
+        conn.set_timeouts(self.timeouts);
+
+        Ok(conn)
+    }
+}
+
+impl From<Acceptor> for AsyncAcceptor {
+    fn from(acceptor: Acceptor) -> Self {
+        Self {
+            tls: acceptor.tls,
+            timeouts: acceptor.timeouts,
+        }
+    }
+}
+
+#[cfg(test)]
+mod async_acceptor_tests {
+    use std::time::Duration;
+
+    use tokio::{io::AsyncWriteExt, net::TcpListener};
+
+    use super::{AsyncAcceptor, AsyncConnection};
+    use crate::{
+        Server, Timeouts,
+        message::{CloseReason, HEADER_SIZE, Message},
+        tls::{Certificate, Identity},
+    };
+
+    const IDENT_FILE: &[u8] = include_bytes!("../../test-keys/identity.p12");
+    const IDENT_PASS: &str = include_str!("../../test-keys/creds.asc");
+    const CUSTOM_CERT: &[u8] = include_bytes!("../../test-keys/cert.crt");
+
+    fn setup_test() -> crate::Result<(AsyncAcceptor, Certificate)> {
+        let cert = Certificate::from_pem(CUSTOM_CERT)?;
+        let identity = Identity::from_pkcs12(IDENT_FILE, IDENT_PASS.trim())?;
+
+        Ok((AsyncAcceptor::new(identity)?, cert))
+    }
+
+    #[tokio::test]
+    async fn accept_message() -> crate::Result<()> {
+        let (acceptor, cert) = setup_test()?;
+
+        const RESOURCE_NAME: &str = "my_resource";
+
+        let listener = TcpListener::bind(("127.0.0.1", 0)).await?;
+        let addr = listener.local_addr()?;
+        let handle = tokio::spawn(async move {
+            let mut conn = AsyncConnection::connect(addr, "localhost", Some(cert)).await?;
+
+            let msg = Message::StartMessage {
+                resource_name: RESOURCE_NAME.to_owned(),
+            };
+            conn.send(&msg).await
+        });
+
+        let (stream, _) = listener.accept().await?;
+        let mut conn = acceptor.accept(stream).await?;
+        let msg = conn.receive().await?;
+
+        if let Message::StartMessage { resource_name } = msg {
+            assert_eq!(&resource_name, RESOURCE_NAME);
+        } else {
+            panic!("received different message type")
+        }
+
+        handle.await.expect("associated task panicked")?;
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn close_connection() -> crate::Result<()> {
+        let (acceptor, cert) = setup_test()?;
+
+        let listener = TcpListener::bind(("127.0.0.1", 0)).await?;
+        let addr = listener.local_addr()?;
+        let handle = tokio::spawn(async move {
+            let conn = AsyncConnection::connect(addr, "localhost", Some(cert)).await?;
+
+            conn.close(CloseReason::Normal, None).await
+        });
+
+        let (stream, _) = listener.accept().await?;
+        let mut conn = acceptor.accept(stream).await?;
+
+        assert!(matches!(
+            conn.receive().await,
+            Err(crate::Error::Closed {
+                reason: CloseReason::Normal,
+                text: None
+            })
+        ));
+
+        handle.await.expect("associated task panicked")?;
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn receive_after_cancelling() -> crate::Result<()> {
+        let (local, mut remote) = tokio::io::duplex(1024);
+        let mut conn = AsyncConnection::<Server>::new(Box::new(local), None);
+
+        let msg = Message::StartMessage {
+            resource_name: "my_resource".to_owned(),
+        };
+        let frame = msg.serialize_request(7)?;
+        let (first, rest) = frame.split_at(frame.len() / 2);
+
+        // the receive times out halfway through the frame
+        remote.write_all(first).await?;
+        let timeout = Duration::from_millis(50);
+        assert!(
+            tokio::time::timeout(timeout, conn.receive_request())
+                .await
+                .is_err()
+        );
+
+        remote.write_all(rest).await?;
+        let (request_id, received) = conn.receive_request().await?;
+        assert_eq!(request_id, 7);
+        assert!(matches!(
+            received,
+            Message::StartMessage { resource_name } if resource_name == "my_resource"
+        ));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn handshake_timeout() -> crate::Result<()> {
+        let (mut acceptor, _) = setup_test()?;
+        acceptor.set_timeouts(Timeouts {
+            handshake: Some(Duration::from_millis(100)),
+            ..Timeouts::default()
+        });
+
+        let listener = TcpListener::bind(("127.0.0.1", 0)).await?;
+        // connects without ever starting the handshake
+        let _silent = tokio::net::TcpStream::connect(listener.local_addr()?).await?;
+
+        let (stream, _) = listener.accept().await?;
+        assert!(matches!(
+            acceptor.accept(stream).await,
+            Err(crate::Error::PeerTimedOut)
+        ));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn idle_timeout() -> crate::Result<()> {
+        let (local, _remote) = tokio::io::duplex(1024);
+        let mut conn = AsyncConnection::<Server>::new(Box::new(local), None);
+        conn.set_timeouts(Timeouts {
+            idle: Some(Duration::from_millis(100)),
+            ..Timeouts::default()
+        });
+
+        // the peer never answers the ping
+        assert!(matches!(
+            conn.receive().await,
+            Err(crate::Error::PeerTimedOut)
+        ));
+
+        Ok(())
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn unix_connection() -> crate::Result<()> {
+        let (local, remote) = tokio::net::UnixStream::pair()?;
+        let mut conn = AsyncConnection::accept_unix(local)?;
+        let mut client = AsyncConnection::<crate::Client>::new(Box::new(remote), None);
+
+        // both ends are this process
+        let credentials = conn.peer_credentials().expect("no credentials");
+        assert_eq!(credentials.uid, nix::unistd::geteuid().as_raw());
+
+        client.send(&Message::LogsEndMessage).await?;
+        assert!(matches!(conn.receive().await?, Message::LogsEndMessage));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn receive_after_rejected_header() -> crate::Result<()> {
+        let (local, mut remote) = tokio::io::duplex(1024);
+        let mut conn = AsyncConnection::<Server>::new(Box::new(local), None);
+        conn.set_max_payload_length(16);
+
+        let too_big = Message::StartMessage {
+            resource_name: "a".repeat(32),
+        }
+        .serialize()?;
+        let valid = Message::StartMessage {
+            resource_name: "web".to_owned(),
+        }
+        .serialize()?;
+
+        // only the header of the message that's too big is sent, as its payload would be skipped
+        remote.write_all(&too_big[..HEADER_SIZE.into()]).await?;
+        remote.write_all(&valid).await?;
+
+        assert!(matches!(
+            conn.receive().await,
+            Err(crate::Error::MesssageTooBig)
+        ));
+        assert!(matches!(
+            conn.receive().await?,
+            Message::StartMessage { resource_name } if resource_name == "web"
+        ));
+
+        Ok(())
+    }
+}
@@ -3,22 +3,49 @@
 
 #![deny(missing_docs)]
 #![deny(clippy::unwrap_used)]
+// everything a peer sends goes through this crate, so it must not be able to crash the other end
+#![cfg_attr(
+    not(test),
+    deny(clippy::panic, clippy::expect_used, clippy::indexing_slicing)
+)]
 
+#[cfg(feature = "tokio")]
+pub mod asynchronous;
 pub mod message;
 pub mod prelude;
+#[cfg(feature = "quic")]
+pub mod quic;
+pub mod tls;
+#[cfg(unix)]
+pub mod unix;
+#[cfg(feature = "websocket")]
+pub mod websocket;
 
-use message::Message;
+use message::{CloseReason, Message};
 
 use core::str;
-use native_tls::{Certificate, Identity, TlsAcceptor, TlsStream};
 use std::{
     io::{self, Read, Write},
     marker::PhantomData,
-    net::{TcpStream, ToSocketAddrs},
+    net::{Shutdown, TcpStream, ToSocketAddrs},
     result,
+    time::{Duration, Instant},
 };
 
-use crate::message::{HEADER_SIZE, MessageHeader};
+use crate::{
+    message::{DEFAULT_MAX_PAYLOAD_LENGTH, HEADER_SIZE, MessageHeader, NO_REQUEST_ID, RequestId},
+    tls::{
+        Certificate, Identity, PeerIdentity, ServerVerification, Stream, TlsAcceptor, TlsConnector,
+    },
+};
+
+/// How long sending to a peer that hung up waits for the [`Message::CloseMessage`] the peer may
+/// have sent before
+const CLOSE_MESSAGE_WAIT: Duration = Duration::from_millis(100);
+
+/// Capacity of the read buffer kept between messages. Larger buffers are only allocated while a
+/// large message is being received
+const MAX_RETAINED_BUFFER: usize = 64 * 1024;
 
 /// Error type returned by all the different functions this library provides
 #[allow(missing_docs)]
@@ -27,20 +54,149 @@ pub enum Error {
     #[error("I/O error")]
     IOFailure(#[from] io::Error),
     #[error("TLS error")]
-    TLSFailure(#[from] native_tls::Error),
+    TLSFailure(#[from] tls::Error),
     #[error("TLS handshake error")]
-    TLSHandshakeFailure(#[from] native_tls::HandshakeError<TcpStream>),
+    TLSHandshakeFailure(#[from] tls::HandshakeError),
+    #[error("invalid certificate or key")]
+    InvalidKeyMaterial,
+    #[error("invalid fingerprint")]
+    InvalidFingerprint,
+    #[error("server certificate doesn't match the pinned fingerprint (got {0})")]
+    FingerprintMismatch(tls::Fingerprint),
     #[error("message too long")]
     MesssageTooBig,
+    #[error("peer stopped responding")]
+    PeerTimedOut,
+    #[error("peer closed the connection ({reason:?})")]
+    Closed {
+        reason: CloseReason,
+        text: Option<String>,
+    },
     #[error("unknown message type")]
     UnknownMessage,
+    #[error("payload length doesn't match the header")]
+    LengthMismatch,
+    #[error("malformed payload")]
+    MalformedPayload,
+    #[error("unsupported payload version {0}")]
+    UnsupportedVersion(u8),
+    #[error("missing payload field {0}")]
+    MissingField(u8),
     #[error("invalid UTF-8")]
     InvalidUtf8(#[from] str::Utf8Error),
+    #[cfg(feature = "websocket")]
+    #[error("WebSocket error")]
+    WebSocketFailure(#[from] tungstenite::Error),
+    #[cfg(feature = "quic")]
+    #[error("QUIC error")]
+    QuicFailure(#[source] quinn::ConnectionError),
+}
+
+impl Error {
+    /// Whether a read timed out (see [`Connection::set_read_timeout`]). The connection can still
+    /// be used after such an error
+    pub fn is_timeout(&self) -> bool {
+        matches!(self, Self::IOFailure(why) if timed_out(why))
+    }
+}
+
+/// Sockets report timeouts as `WouldBlock` on Unix and as `TimedOut` on Windows
+fn timed_out(error: &io::Error) -> bool {
+    matches!(
+        error.kind(),
+        io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut
+    )
 }
 
 /// The result type returned by this library's functions
 pub type Result<T> = result::Result<T, crate::Error>;
 
+/// Timeouts guarding a connection against peers that stop responding. `None` disables a timeout,
+/// which is the default for all of them
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Timeouts {
+    /// How long connecting and every step of the TLS handshake may wait for the peer. Running out
+    /// fails the handshake with [`Error::PeerTimedOut`]
+    pub handshake: Option<Duration>,
+    /// How long receiving waits for a message before failing with an error for which
+    /// [`Error::is_timeout`] returns `true`. The connection can still be used afterwards
+    pub read: Option<Duration>,
+    /// How long sending may block on a peer that doesn't read. Running out fails with
+    /// [`Error::PeerTimedOut`], after which the connection can't be used anymore
+    pub write: Option<Duration>,
+    /// How long the peer may stay silent before the connection is considered dead. Halfway
+    /// through, a [`Message::PingMessage`] is sent, so that a healthy peer has something to
+    /// answer. Running out closes the connection and fails receiving with [`Error::PeerTimedOut`]
+    pub idle: Option<Duration>,
+}
+
+/// The socket under a connection's stream, used to change socket options
+enum Socket {
+    Tcp(TcpStream),
+    #[cfg(unix)]
+    Unix(std::os::unix::net::UnixStream),
+}
+
+impl Socket {
+    fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        match self {
+            Self::Tcp(socket) => socket.set_read_timeout(timeout),
+            #[cfg(unix)]
+            Self::Unix(socket) => socket.set_read_timeout(timeout),
+        }
+    }
+
+    fn set_write_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        match self {
+            Self::Tcp(socket) => socket.set_write_timeout(timeout),
+            #[cfg(unix)]
+            Self::Unix(socket) => socket.set_write_timeout(timeout),
+        }
+    }
+
+    fn shutdown(&self, how: Shutdown) -> io::Result<()> {
+        match self {
+            Self::Tcp(socket) => socket.shutdown(how),
+            #[cfg(unix)]
+            Self::Unix(socket) => socket.shutdown(how),
+        }
+    }
+}
+
+/// Connects to the first address that accepts the connection within `timeout`
+fn connect_tcp<A: ToSocketAddrs>(addr: A, timeout: Option<Duration>) -> io::Result<TcpStream> {
+    let Some(timeout) = timeout else {
+        return TcpStream::connect(addr);
+    };
+
+    let mut last_error = io::Error::new(io::ErrorKind::InvalidInput, "no addresses to connect to");
+    for addr in addr.to_socket_addrs()? {
+        match TcpStream::connect_timeout(&addr, timeout) {
+            Ok(stream) => return Ok(stream),
+            Err(why) => last_error = why,
+        }
+    }
+
+    Err(last_error)
+}
+
+/// Runs a TLS handshake over `socket`, limiting every read and write to `timeout`
+fn handshake<S>(
+    socket: &TcpStream,
+    timeout: Option<Duration>,
+    handshake: impl FnOnce() -> crate::Result<S>,
+) -> crate::Result<S> {
+    socket.set_read_timeout(timeout)?;
+    socket.set_write_timeout(timeout)?;
+
+    let started = Instant::now();
+    // the TLS backends report timeouts in different ways, so tell them apart by the time spent
+    handshake().map_err(|why| match timeout {
+        Some(timeout) if started.elapsed() >= timeout => crate::Error::PeerTimedOut,
+        _ => why,
+    })
+}
+
 /// The end of a connection that acts as the initiator
 pub struct Client;
 /// The end of a connection that acts as the acceptor
@@ -52,34 +208,288 @@ pub struct Server;
 ///
 /// If you need to connect to a client, use [`Connection::connect`]. If you need to accept a
 /// connection from a client, use [`Acceptor`] instead.
+///
+/// # Multiplexing
+///
+/// Several requests can be in flight at once. Tag them with distinct IDs using
+/// [`Connection::send_request`] and match the replies coming from
+/// [`Connection::receive_request`] by their IDs, as the peer doesn't have to reply in order.
+///
+/// # Timeouts
+///
+/// By default, receiving and sending block for as long as the peer takes. See [`Timeouts`] for
+/// how to detect peers that went silent.
 pub struct Connection<T> {
-    stream: TlsStream<TcpStream>,
+    stream: Box<dyn Stream>,
+    socket: Socket,
+    /// The part of a frame received so far, kept across timed out reads
+    read_buf: Vec<u8>,
+    max_payload_length: u32,
+    timeouts: Timeouts,
+    /// When anything last arrived from the peer
+    last_received: Instant,
+    /// Whether a ping went unanswered since then
+    ping_sent: bool,
+    peer: Option<PeerIdentity>,
+    #[cfg(unix)]
+    credentials: Option<unix::PeerCredentials>,
     _0: PhantomData<T>,
 }
 
 impl<T> Connection<T> {
+    fn new(stream: Box<dyn Stream>, socket: Socket, peer: Option<PeerIdentity>) -> Self {
+        Self {
+            stream,
+            socket,
+            read_buf: Vec::with_capacity(HEADER_SIZE.into()),
+            max_payload_length: DEFAULT_MAX_PAYLOAD_LENGTH,
+            timeouts: Timeouts::default(),
+            last_received: Instant::now(),
+            ping_sent: false,
+            peer,
+            #[cfg(unix)]
+            credentials: None,
+            _0: PhantomData,
+        }
+    }
+
+    /// Fingerprints of the certificate the server presented. This is only available on the
+    /// client's end
+    pub fn peer_identity(&self) -> Option<&PeerIdentity> {
+        self.peer.as_ref()
+    }
+
+    /// Sets the maximum length of payloads accepted from the peer, [`DEFAULT_MAX_PAYLOAD_LENGTH`]
+    /// by default. The peer can send payloads up to [`message::MAX_MESSAGE_PAYLOAD_LENGTH`]
+    /// bytes long, which would all be buffered before they're parsed
+    pub fn set_max_payload_length(&mut self, length: u32) {
+        self.max_payload_length = length;
+        let header_size = usize::from(message::EXTENDED_HEADER_SIZE);
+        self.stream.set_max_packet_length(
+            usize::try_from(length)
+                .unwrap_or(usize::MAX)
+                .saturating_add(header_size),
+        );
+    }
+
+    /// Replaces the read, write and idle timeouts of the connection. The handshake timeout is
+    /// ignored, as the handshake is already over
+    pub fn set_timeouts(&mut self, timeouts: Timeouts) -> crate::Result<()> {
+        self.socket.set_write_timeout(timeouts.write)?;
+        self.timeouts = timeouts;
+
+        Ok(())
+    }
+
+    /// Makes receiving fail with an error for which [`Error::is_timeout`] returns `true` once
+    /// nothing arrives for `timeout`. `None` blocks indefinitely, which is the default
+    pub fn set_read_timeout(&mut self, timeout: Option<Duration>) -> crate::Result<()> {
+        self.set_timeouts(Timeouts {
+            read: timeout,
+            ..self.timeouts
+        })
+    }
+
+    /// Sets how long the peer may stay silent before the connection is considered dead, see
+    /// [`Timeouts::idle`]
+    pub fn set_idle_timeout(&mut self, timeout: Option<Duration>) -> crate::Result<()> {
+        self.set_timeouts(Timeouts {
+            idle: timeout,
+            ..self.timeouts
+        })
+    }
+
     /// Send a message over the connection
     pub fn send(&mut self, message: &Message) -> crate::Result<()> {
-        let header_buf = message.create_header()?.serialize();
-        let message_buf = message.serialize_payload()?;
+        self.send_request(NO_REQUEST_ID, message)
+    }
 
-        let buf = [&header_buf, message_buf.iter().as_slice()].concat();
+    /// Send a message tagged with `request_id` over the connection
+    pub fn send_request(&mut self, request_id: RequestId, message: &Message) -> crate::Result<()> {
+        let buf = message.serialize_request(request_id)?;
+
+        match self.stream.write_all(&buf) {
+            Ok(()) => Ok(()),
+            // a partially written message can't be finished, so the connection is dead
+            Err(why) if timed_out(&why) => Err(self.close_dead()),
+            Err(why)
+                if matches!(
+                    why.kind(),
+                    io::ErrorKind::BrokenPipe | io::ErrorKind::ConnectionReset
+                ) =>
+            {
+                Err(self.close_message().unwrap_or(why.into()))
+            }
+            Err(why) => Err(why.into()),
+        }
+    }
+
+    /// Checks that the peer is still there. The answer is handled while receiving, which fails
+    /// with [`Error::PeerTimedOut`] if it doesn't arrive within the idle timeout
+    pub fn ping(&mut self) -> crate::Result<()> {
+        self.send(&Message::PingMessage)?;
+        self.ping_sent = true;
 
-        self.stream.write_all(&buf)?;
         Ok(())
     }
 
     /// Receive a message over the connection
     pub fn receive(&mut self) -> crate::Result<Message> {
-        let mut buf = [0; HEADER_SIZE as usize];
-        self.stream.read_exact(&mut buf)?;
+        self.receive_request().map(|(_, message)| message)
+    }
 
-        let header = MessageHeader::deserialize(&buf[0..HEADER_SIZE as usize])?;
+    /// Receive a message over the connection, together with the request ID it is tagged with
+    ///
+    /// Fails with [`Error::MesssageTooBig`] if the peer announces a payload longer than
+    /// [`Connection::set_max_payload_length`] allows. The payload isn't read in that case, so the
+    /// connection can't be used anymore.
+    ///
+    /// Pings from the peer are answered here, so keep receiving even when no replies are expected
+    /// if the peer has an idle timeout.
+    pub fn receive_request(&mut self) -> crate::Result<(RequestId, Message)> {
+        let started = Instant::now();
 
-        let mut data_buf = vec![0; header.length.into()];
-        self.stream.read_exact(&mut data_buf)?;
+        loop {
+            self.socket.set_read_timeout(self.wait_time(started))?;
+            let timeout = match self.receive_frame() {
+                Ok((request_id, Message::PingMessage)) => {
+                    self.send_request(request_id, &Message::PongMessage)?;
+                    continue;
+                }
+                Ok((_, Message::PongMessage)) => continue,
+                Ok((_, Message::CloseMessage { reason, text })) => {
+                    return Err(crate::Error::Closed { reason, text });
+                }
+                Ok(request) => return Ok(request),
+                Err(why) if why.is_timeout() => why,
+                Err(why) => return Err(why),
+            };
+
+            let silence = self.last_received.elapsed();
+            if let Some(idle) = self.timeouts.idle {
+                if silence >= idle {
+                    return Err(self.close_dead());
+                }
+                if !self.ping_sent && silence >= idle / 2 {
+                    self.ping()?;
+                }
+            }
+
+            if self
+                .timeouts
+                .read
+                .is_some_and(|read| started.elapsed() >= read)
+            {
+                return Err(timeout);
+            }
+        }
+    }
+
+    /// How long the socket may block before the read or idle timeout needs attention
+    fn wait_time(&self, started: Instant) -> Option<Duration> {
+        let read = self
+            .timeouts
+            .read
+            .map(|read| read.saturating_sub(started.elapsed()));
+        let idle = self.timeouts.idle.map(|idle| {
+            let deadline = if self.ping_sent { idle } else { idle / 2 };
+            deadline.saturating_sub(self.last_received.elapsed())
+        });
+
+        // a zero timeout is rejected by the socket
+        let wait = read.into_iter().chain(idle).min()?;
+        Some(wait.max(Duration::from_millis(1)))
+    }
+
+    /// Closes the connection on purpose, telling the peer why. The peer's next receive fails
+    /// with [`Error::Closed`], so it can tell this apart from a broken connection
+    pub fn close(mut self, reason: CloseReason, text: Option<&str>) -> crate::Result<()> {
+        self.send(&Message::CloseMessage {
+            reason,
+            text: text.map(str::to_owned),
+        })?;
+        self.stream.close()?;
 
-        Message::deserialize(&header, &data_buf)
+        Ok(())
+    }
+
+    /// Looks for the [`Message::CloseMessage`] a peer that hung up may have sent, so that sending
+    /// to it fails with [`Error::Closed`] instead of a broken pipe
+    fn close_message(&mut self) -> Option<crate::Error> {
+        self.socket
+            .set_read_timeout(Some(CLOSE_MESSAGE_WAIT))
+            .ok()?;
+
+        loop {
+            // messages sent before the close message can't be answered anymore
+            if let (_, Message::CloseMessage { reason, text }) = self.receive_frame().ok()? {
+                return Some(crate::Error::Closed { reason, text });
+            }
+        }
+    }
+
+    /// Shuts the connection down after the peer went silent. Saying goodbye over TLS could block
+    /// on the silent peer, so the socket is just closed
+    fn close_dead(&mut self) -> crate::Error {
+        _ = self.socket.shutdown(Shutdown::Both);
+
+        crate::Error::PeerTimedOut
+    }
+
+    /// Receives a single frame, whatever message it holds
+    fn receive_frame(&mut self) -> crate::Result<(RequestId, Message)> {
+        self.fill(HEADER_SIZE.into())?;
+        let header_size = MessageHeader::serialized_size(&self.read_buf)?;
+
+        self.fill(header_size.into())?;
+        let header = MessageHeader::deserialize(&self.read_buf);
+        let header = header.inspect_err(|_| self.read_buf.clear())?;
+        if header.length > self.max_payload_length {
+            self.read_buf.clear();
+            return Err(crate::Error::MesssageTooBig);
+        }
+
+        let payload_length =
+            usize::try_from(header.length).map_err(|_| crate::Error::MesssageTooBig)?;
+        self.fill(usize::from(header_size) + payload_length)?;
+        let payload = self.read_buf.get(header_size.into()..).unwrap_or_default();
+        let message = Message::deserialize_request(&header, payload);
+
+        // don't keep large buffers around for the rest of the connection
+        self.read_buf.clear();
+        self.read_buf.shrink_to(MAX_RETAINED_BUFFER);
+
+        message
+    }
+
+    /// Reads until the read buffer holds `length` bytes. What was read before a read fails (for
+    /// example because it timed out) is kept, so that the next call continues where this one
+    /// stopped
+    fn fill(&mut self, length: usize) -> io::Result<()> {
+        let mut filled = self.read_buf.len();
+        self.read_buf.resize(length, 0);
+
+        while filled < length {
+            let buf = self.read_buf.get_mut(filled..).unwrap_or_default();
+            match self.stream.read(buf) {
+                Ok(0) => {
+                    self.read_buf.truncate(filled);
+                    return Err(io::ErrorKind::UnexpectedEof.into());
+                }
+                Ok(read) => {
+                    filled += read;
+                    self.last_received = Instant::now();
+                    self.ping_sent = false;
+                }
+                Err(why) if why.kind() == io::ErrorKind::Interrupted => {}
+                Err(why) => {
+                    self.read_buf.truncate(filled);
+                    return Err(why);
+                }
+            }
+        }
+
+        Ok(())
     }
 }
 
@@ -90,64 +500,98 @@ impl Connection<()> {
         domain: &str,
         custom_cert: Option<Certificate>,
     ) -> Result<Connection<Client>> {
-        let stream = TcpStream::connect(addr)?;
-        let mut connector = native_tls::TlsConnector::builder();
-        if let Some(cert) = custom_cert {
-            connector.add_root_certificate(cert);
-        }
-        let connector = connector.build()?;
+        let verification = ServerVerification::CertificateAuthority(custom_cert);
 
-        let tls_stream = connector.connect(domain, stream)?;
+        Self::connect_with(addr, domain, &verification)
+    }
 
-        Ok(Connection {
-            stream: tls_stream,
-            _0: PhantomData,
-        })
+    /// Connects to the given server, checking its certificate according to `verification`
+    pub fn connect_with<A: ToSocketAddrs>(
+        addr: A,
+        domain: &str,
+        verification: &ServerVerification,
+    ) -> Result<Connection<Client>> {
+        Self::connect_with_timeouts(addr, domain, verification, Timeouts::default())
+    }
+
+    /// Same as [`Connection::connect_with`], but gives up on servers that don't respond in time
+    pub fn connect_with_timeouts<A: ToSocketAddrs>(
+        addr: A,
+        domain: &str,
+        verification: &ServerVerification,
+        timeouts: Timeouts,
+    ) -> Result<Connection<Client>> {
+        let stream = connect_tcp(addr, timeouts.handshake)?;
+        let socket = stream.try_clone()?;
+        let connector = TlsConnector::new(verification)?;
+        let (tls_stream, peer_cert) = handshake(&socket, timeouts.handshake, || {
+            connector.connect(domain, stream)
+        })?;
+        let peer = verification.check(peer_cert.as_deref())?;
+
+        let mut conn = Connection::new(tls_stream, Socket::Tcp(socket), peer);
+        conn.set_timeouts(timeouts)?;
+
+        Ok(conn)
     }
 }
 
 /// Accepts and initiates connections, verifies the identity of clients
 #[derive(Clone)]
-pub struct Acceptor(TlsAcceptor);
+pub struct Acceptor {
+    tls: TlsAcceptor,
+    timeouts: Timeouts,
+}
 
 impl Acceptor {
     /// Constructs a new acceptor with sane TLS configuration.
     pub fn new(identity: Identity) -> crate::Result<Acceptor> {
-        let acceptor = TlsAcceptor::builder(identity)
-            // remove this if this causes problems for older platforms
-            .min_protocol_version(Some(native_tls::Protocol::Tlsv12))
-            .build()?;
+        Ok(Self {
+            tls: TlsAcceptor::new(identity)?,
+            timeouts: Timeouts::default(),
+        })
+    }
 
-        Ok(Self(acceptor))
+    /// Sets the timeouts of accepted connections. Set at least the handshake timeout when
+    /// accepting connections from untrusted networks, so that clients that never finish the
+    /// handshake don't tie up the acceptor forever
+    pub fn set_timeouts(&mut self, timeouts: Timeouts) {
+        self.timeouts = timeouts;
     }
 
     /// Accepts an incoming connection. Pass the stream in before writing anything to it.
     pub fn accept(&self, stream: TcpStream) -> crate::Result<Connection<Server>> {
-        let tls_stream = self.0.accept(stream)?;
+        let socket = stream.try_clone()?;
+        let tls_stream = handshake(&socket, self.timeouts.handshake, || self.tls.accept(stream))?;
 
-        Ok(Connection {
-            stream: tls_stream,
-            _0: PhantomData,
-        })
+        let mut conn = Connection::new(tls_stream, Socket::Tcp(socket), None);
+        conn.set_timeouts(self.timeouts)?;
+
+        Ok(conn)
     }
 }
 
 #[cfg(test)]
 mod acceptor_tests {
     use std::{
-        net::{TcpListener, ToSocketAddrs},
+        net::{TcpListener, TcpStream, ToSocketAddrs},
+        sync::mpsc,
         thread::{self, JoinHandle},
+        time::Duration,
     };
 
-    use native_tls::{Certificate, Identity};
-
-    use crate::message::Message;
+    use crate::{
+        message::{CloseReason, DEFAULT_MAX_PAYLOAD_LENGTH, ErrorCode, Message},
+        tls::{Certificate, Fingerprint, Identity, Pin, ServerVerification},
+    };
 
-    use super::{Acceptor, Connection};
+    use super::{Acceptor, Connection, Timeouts};
 
     const IDENT_FILE: &[u8] = include_bytes!("../../test-keys/identity.p12");
     const IDENT_PASS: &str = include_str!("../../test-keys/creds.asc");
     const CUSTOM_CERT: &[u8] = include_bytes!("../../test-keys/cert.crt");
+    const PRIVATE_KEY: &[u8] = include_bytes!("../../test-keys/private.pem");
+    const CERT_DER: &[u8] = include_bytes!("../../test-keys/cert.der");
 
     fn setup_test() -> crate::Result<(Acceptor, Certificate)> {
         let cert = Certificate::from_pem(CUSTOM_CERT)?;
@@ -186,6 +630,31 @@ mod acceptor_tests {
         Ok(())
     }
 
+    #[test]
+    fn accept_connection_pem_identity() -> crate::Result<()> {
+        let cert = Certificate::from_pem(CUSTOM_CERT)?;
+        let acceptor = Acceptor::new(Identity::from_pem(CUSTOM_CERT, PRIVATE_KEY)?)?;
+
+        let addr = get_local_addr().expect("failed to get local address");
+        let listener = TcpListener::bind(&addr)?;
+        let handle: JoinHandle<crate::Result<()>> = thread::spawn(move || {
+            _ = Connection::connect(&addr, "localhost", Some(cert))?;
+
+            Ok(())
+        });
+
+        let stream = listener
+            .incoming()
+            .next()
+            .expect("no next stream (this should never happen)")
+            .expect("failed to accept stream");
+        _ = acceptor.accept(stream)?;
+
+        handle.join().expect("associated thread panicked")?;
+
+        Ok(())
+    }
+
     #[test]
     fn accept_message() -> crate::Result<()> {
         let (acceptor, cert) = setup_test()?;
@@ -213,7 +682,6 @@ mod acceptor_tests {
         let mut conn = acceptor.accept(stream)?;
         let msg = conn.receive()?;
 
-        #[allow(irrefutable_let_patterns)] // TODO: remove this when more message types are added
         if let Message::StartMessage { resource_name } = msg {
             assert_eq!(&resource_name, RESOURCE_NAME);
         } else {
@@ -224,4 +692,349 @@ mod acceptor_tests {
 
         Ok(())
     }
+
+    /// Connects to a fresh acceptor with `verification`, returning the client's result
+    fn connect_pinned(
+        verification: ServerVerification,
+    ) -> crate::Result<crate::Connection<crate::Client>> {
+        let (acceptor, _) = setup_test()?;
+
+        let addr = get_local_addr().expect("failed to get local address");
+        let listener = TcpListener::bind(&addr)?;
+        let handle = thread::spawn(move || {
+            // "example.com" makes sure that the name isn't verified either
+            Connection::connect_with(&addr, "example.com", &verification)
+        });
+
+        let stream = listener
+            .incoming()
+            .next()
+            .expect("no next stream (this should never happen)")
+            .expect("failed to accept stream");
+        // the client may hang up right after the handshake if the pin doesn't match
+        _ = acceptor.accept(stream);
+
+        handle.join().expect("associated thread panicked")
+    }
+
+    #[test]
+    fn pinned_certificate() -> crate::Result<()> {
+        let pin = Pin::Certificate(Fingerprint::of(CERT_DER));
+        let conn = connect_pinned(ServerVerification::Pinned(pin))?;
+
+        let peer = conn
+            .peer_identity()
+            .expect("client should know the server's identity");
+        assert_eq!(peer.certificate, Fingerprint::of(CERT_DER));
+
+        Ok(())
+    }
+
+    #[test]
+    fn pinned_certificate_mismatch() {
+        let pin = Pin::Spki(Fingerprint::of(b"not the key"));
+
+        assert!(matches!(
+            connect_pinned(ServerVerification::Pinned(pin)),
+            Err(crate::Error::FingerprintMismatch(_))
+        ));
+    }
+
+    #[test]
+    fn trust_on_first_use() -> crate::Result<()> {
+        let conn = connect_pinned(ServerVerification::TrustOnFirstUse)?;
+
+        let peer = conn
+            .peer_identity()
+            .expect("client should know the server's identity");
+        let pin = Pin::Spki(peer.spki);
+        _ = connect_pinned(ServerVerification::Pinned(pin))?;
+
+        Ok(())
+    }
+
+    #[test]
+    fn multiplexed_requests() -> crate::Result<()> {
+        let (acceptor, cert) = setup_test()?;
+        let resources = ["first", "second", "third"];
+
+        let addr = get_local_addr().expect("failed to get local address");
+        let listener = TcpListener::bind(&addr)?;
+        let handle: JoinHandle<crate::Result<()>> = thread::spawn(move || {
+            let mut conn = Connection::connect(&addr, "localhost", Some(cert))?;
+
+            for (id, resource) in (1..).zip(resources) {
+                let msg = Message::StartMessage {
+                    resource_name: resource.to_owned(),
+                };
+                conn.send_request(id, &msg)?;
+            }
+
+            // the replies come in reverse order, the IDs tell which request they belong to
+            for _ in resources {
+                match conn.receive_request()? {
+                    (id, Message::ErrorMessage { code }) => {
+                        assert_eq!(code as u32, id);
+                    }
+                    (_, msg) => panic!("received different message type: {msg:?}"),
+                }
+            }
+
+            Ok(())
+        });
+
+        let stream = listener
+            .incoming()
+            .next()
+            .expect("no next stream (this should never happen)")
+            .expect("failed to accept stream");
+        let mut conn = acceptor.accept(stream)?;
+
+        let mut requests = vec![];
+        for resource in resources {
+            match conn.receive_request()? {
+                (id, Message::StartMessage { resource_name }) => {
+                    assert_eq!(resource_name, resource);
+                    requests.push(id);
+                }
+                (_, msg) => panic!("received different message type: {msg:?}"),
+            }
+        }
+        assert_eq!(requests, [1, 2, 3]);
+
+        for id in requests.into_iter().rev() {
+            let code = ErrorCode::from_repr(id as u8).expect("test IDs are valid error codes");
+            conn.send_request(id, &Message::ErrorMessage { code })?;
+        }
+
+        handle.join().expect("associated thread panicked")?;
+
+        Ok(())
+    }
+
+    /// Sends `data` as log data to a fresh acceptor whose connection accepts payloads up to
+    /// `max_payload_length` bytes, returning what the acceptor received
+    fn send_log_data(data: Vec<u8>, max_payload_length: u32) -> crate::Result<Message> {
+        let (acceptor, cert) = setup_test()?;
+
+        let addr = get_local_addr().expect("failed to get local address");
+        let listener = TcpListener::bind(&addr)?;
+        let handle = thread::spawn(move || {
+            let mut conn = Connection::connect(&addr, "localhost", Some(cert))?;
+            conn.send(&Message::LogDataMessage { data })?;
+            // closing with unread data (like TLS session tickets) resets the connection, which
+            // could drop the message before the acceptor reads it, so wait for it to hang up
+            _ = conn.receive();
+
+            crate::Result::Ok(())
+        });
+
+        let stream = listener
+            .incoming()
+            .next()
+            .expect("no next stream (this should never happen)")
+            .expect("failed to accept stream");
+        let mut conn = acceptor.accept(stream)?;
+        conn.set_max_payload_length(max_payload_length);
+        let result = conn.receive();
+        drop(conn);
+
+        _ = handle.join().expect("associated thread panicked");
+
+        result
+    }
+
+    #[test]
+    fn large_message() -> crate::Result<()> {
+        let data: Vec<u8> = (0..=u8::MAX).cycle().take(3 * 1024 * 1024).collect();
+
+        match send_log_data(data.clone(), DEFAULT_MAX_PAYLOAD_LENGTH)? {
+            Message::LogDataMessage { data: received } => assert!(received == data),
+            msg => panic!("received different message type: {msg:?}"),
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn message_over_limit() {
+        assert!(matches!(
+            send_log_data(vec![0; 2048], 1024),
+            Err(crate::Error::MesssageTooBig)
+        ));
+    }
+
+    #[test]
+    fn receive_after_timeout() -> crate::Result<()> {
+        let (acceptor, cert) = setup_test()?;
+
+        let addr = get_local_addr().expect("failed to get local address");
+        let listener = TcpListener::bind(&addr)?;
+        let (sent_tx, sent_rx) = mpsc::channel();
+        let handle: JoinHandle<crate::Result<()>> = thread::spawn(move || {
+            let mut conn = Connection::connect(&addr, "localhost", Some(cert))?;
+
+            // wait for the other end to time out first
+            _ = sent_rx.recv();
+            conn.send_request(
+                7,
+                &Message::StartMessage {
+                    resource_name: "my_resource".to_owned(),
+                },
+            )?;
+
+            Ok(())
+        });
+
+        let stream = listener
+            .incoming()
+            .next()
+            .expect("no next stream (this should never happen)")
+            .expect("failed to accept stream");
+        let mut conn = acceptor.accept(stream)?;
+        conn.set_read_timeout(Some(Duration::from_millis(50)))?;
+
+        match conn.receive() {
+            Err(why) if why.is_timeout() => {}
+            result => panic!("receive should have timed out, got {result:?}"),
+        }
+        _ = sent_tx.send(());
+
+        conn.set_read_timeout(None)?;
+        let (id, msg) = conn.receive_request()?;
+        assert_eq!(id, 7);
+        assert!(matches!(msg, Message::StartMessage { .. }));
+
+        handle.join().expect("associated thread panicked")?;
+
+        Ok(())
+    }
+
+    #[test]
+    fn close_connection() -> crate::Result<()> {
+        let (acceptor, cert) = setup_test()?;
+
+        let addr = get_local_addr().expect("failed to get local address");
+        let listener = TcpListener::bind(&addr)?;
+        let handle: JoinHandle<crate::Result<()>> = thread::spawn(move || {
+            let mut conn = Connection::connect(&addr, "localhost", Some(cert))?;
+            conn.send(&Message::LogsEndMessage)?;
+
+            conn.close(CloseReason::GoingAway, Some("shutting down"))
+        });
+
+        let stream = listener
+            .incoming()
+            .next()
+            .expect("no next stream (this should never happen)")
+            .expect("failed to accept stream");
+        let mut conn = acceptor.accept(stream)?;
+
+        // messages sent before closing still arrive
+        assert!(matches!(conn.receive()?, Message::LogsEndMessage));
+        match conn.receive() {
+            Err(crate::Error::Closed { reason, text }) => {
+                assert_eq!(reason, CloseReason::GoingAway);
+                assert_eq!(text.as_deref(), Some("shutting down"));
+            }
+            result => panic!("receive should have reported the close, got {result:?}"),
+        }
+
+        handle.join().expect("associated thread panicked")?;
+
+        Ok(())
+    }
+
+    #[test]
+    fn handshake_timeout() -> crate::Result<()> {
+        let (mut acceptor, _) = setup_test()?;
+        acceptor.set_timeouts(Timeouts {
+            handshake: Some(Duration::from_millis(100)),
+            ..Timeouts::default()
+        });
+
+        let addr = get_local_addr().expect("failed to get local address");
+        let listener = TcpListener::bind(&addr)?;
+        // connects, but never starts the handshake
+        let silent_peer = TcpStream::connect(&addr)?;
+
+        let stream = listener
+            .incoming()
+            .next()
+            .expect("no next stream (this should never happen)")
+            .expect("failed to accept stream");
+        assert!(matches!(
+            acceptor.accept(stream),
+            Err(crate::Error::PeerTimedOut)
+        ));
+
+        drop(silent_peer);
+
+        Ok(())
+    }
+
+    #[test]
+    fn silent_peer_times_out() -> crate::Result<()> {
+        let (acceptor, cert) = setup_test()?;
+
+        let addr = get_local_addr().expect("failed to get local address");
+        let listener = TcpListener::bind(&addr)?;
+        let (done_tx, done_rx) = mpsc::channel();
+        let handle: JoinHandle<crate::Result<()>> = thread::spawn(move || {
+            let stream = listener
+                .incoming()
+                .next()
+                .expect("no next stream (this should never happen)")
+                .expect("failed to accept stream");
+            // the connection stays open, but nothing is read from it, so pings go unanswered
+            let conn = acceptor.accept(stream)?;
+            _ = done_rx.recv();
+            drop(conn);
+
+            Ok(())
+        });
+
+        let mut conn = Connection::connect(&addr, "localhost", Some(cert))?;
+        conn.set_idle_timeout(Some(Duration::from_millis(200)))?;
+
+        assert!(matches!(conn.receive(), Err(crate::Error::PeerTimedOut)));
+        _ = done_tx.send(());
+
+        handle.join().expect("associated thread panicked")?;
+
+        Ok(())
+    }
+
+    #[test]
+    fn pings_keep_connection_alive() -> crate::Result<()> {
+        let (acceptor, cert) = setup_test()?;
+
+        let addr = get_local_addr().expect("failed to get local address");
+        let listener = TcpListener::bind(&addr)?;
+        let handle: JoinHandle<crate::Result<()>> = thread::spawn(move || {
+            let stream = listener
+                .incoming()
+                .next()
+                .expect("no next stream (this should never happen)")
+                .expect("failed to accept stream");
+            let mut conn = acceptor.accept(stream)?;
+
+            // stays silent for longer than the other end's idle timeout, but answers its pings
+            conn.set_read_timeout(Some(Duration::from_millis(500)))?;
+            match conn.receive() {
+                Err(why) if why.is_timeout() => {}
+                result => panic!("receive should have timed out, got {result:?}"),
+            }
+            conn.send(&Message::LogsEndMessage)
+        });
+
+        let mut conn = Connection::connect(&addr, "localhost", Some(cert))?;
+        conn.set_idle_timeout(Some(Duration::from_millis(200)))?;
+
+        assert!(matches!(conn.receive()?, Message::LogsEndMessage));
+
+        handle.join().expect("associated thread panicked")?;
+
+        Ok(())
+    }
 }
@@ -1,15 +1,65 @@
 //! Code related to messages that clients and servers can pass to one another.
 
-/// Maximum length of a message payload
-pub const MAX_MESSAGE_PAYLOAD_LENGTH: u16 = u16::MAX;
+mod payload;
+
+use payload::{PayloadReader, PayloadWriter, Tag, required};
+
+/// Maximum length of a message payload. Receivers usually enforce a much lower bound, see
+/// [`DEFAULT_MAX_PAYLOAD_LENGTH`]
+pub const MAX_MESSAGE_PAYLOAD_LENGTH: u32 = u32::MAX;
+
+/// Default upper bound on the length of payloads a [`crate::Connection`] accepts, so that peers
+/// can't exhaust the receiver's memory
+pub const DEFAULT_MAX_PAYLOAD_LENGTH: u32 = 16 * 1024 * 1024;
 
 /// Size of the message header in bytes
 // this doesn't take the size directly from `size_of::<MessageHeader>()` because alignment is
 // something that (fortunately) doesn't apply to bytes sent over the network.
 pub const HEADER_SIZE: u16 = (size_of::<OpCode>() + size_of::<u16>()) as u16;
 
-/// Max message length, header length and payload length combined
-pub const MAX_MESSAGE_LENGTH: usize = MAX_MESSAGE_PAYLOAD_LENGTH as usize + HEADER_SIZE as usize;
+/// Size of the header of messages with payloads that are [`EXTENDED_LENGTH`] bytes or longer
+pub const EXTENDED_HEADER_SIZE: u16 = HEADER_SIZE + size_of::<u32>() as u16;
+
+/// Value of a 16-bit length field announcing that the actual length follows as a `u32`. Lengths
+/// from this value up are always written that way
+pub const EXTENDED_LENGTH: u16 = u16::MAX;
+
+/// Appends `length` as a big-endian `u16`, or as [`EXTENDED_LENGTH`] followed by a big-endian
+/// `u32` if it doesn't fit
+fn push_length(buf: &mut Vec<u8>, length: u32) {
+    match u16::try_from(length) {
+        Ok(length) if length != EXTENDED_LENGTH => buf.extend_from_slice(&length.to_be_bytes()),
+        _ => {
+            buf.extend_from_slice(&EXTENDED_LENGTH.to_be_bytes());
+            buf.extend_from_slice(&length.to_be_bytes());
+        }
+    }
+}
+
+/// Splits a length written by [`push_length`] off the front of `buf`. Returns `None` if the
+/// length is truncated or if it was extended even though it fits into a `u16`, so that every
+/// length has a single encoding
+fn split_length(buf: &[u8]) -> Option<(u32, &[u8])> {
+    let (length, rest) = buf.split_first_chunk()?;
+
+    match u16::from_be_bytes(*length) {
+        EXTENDED_LENGTH => {
+            let (length, rest) = rest.split_first_chunk()?;
+            let length = u32::from_be_bytes(*length);
+
+            (length >= u32::from(EXTENDED_LENGTH)).then_some((length, rest))
+        }
+        length => Some((length.into(), rest)),
+    }
+}
+
+/// Identifies a request and the replies to it, so that several requests can be in flight over a
+/// single connection at once. Replies carry the ID of the request they answer
+pub type RequestId = u32;
+
+/// The ID of messages that aren't tagged with one. Peers that only send one request at a time
+/// don't need IDs
+pub const NO_REQUEST_ID: RequestId = 0;
 
 /// The different message opcode constants
 ///
@@ -25,56 +75,150 @@ pub const MAX_MESSAGE_LENGTH: usize = MAX_MESSAGE_PAYLOAD_LENGTH as usize + HEAD
 #[repr(u8)]
 pub enum OpCode {
     StartMessage = 0,
+    LogsMessage = 1,
+    LogDataMessage = 2,
+    LogsEndMessage = 3,
+    ErrorMessage = 4,
+    StopMessage = 5,
+    RestartMessage = 6,
+    StateMessage = 7,
+    PauseMessage = 8,
+    UnpauseMessage = 9,
+    PingMessage = 10,
+    PongMessage = 11,
+    CloseMessage = 12,
+    StatusMessage = 13,
+    ListMessage = 14,
+    ResourceMessage = 15,
+    ListEndMessage = 16,
+}
+
+/// Error codes that can be sent back to the peer in [`Message::ErrorMessage`]
+///
+/// # Stability
+///
+/// Same as with [`OpCode`], the discriminants shouldn't change.
+#[derive(strum::FromRepr, Clone, Copy, Debug, PartialEq)]
+#[repr(u8)]
+pub enum ErrorCode {
+    /// The peer isn't allowed to perform the requested operation on the resource
+    PermissionDenied = 0,
+    /// The requested resource doesn't exist
+    ResourceNotFound = 1,
+    /// The container engine couldn't be reached
+    EngineUnreachable = 2,
+    /// Something else went wrong
+    Unknown = 3,
+    /// The requested operation isn't supported on the resource
+    Unsupported = 4,
+}
+
+/// Why a peer closed the connection, sent in [`Message::CloseMessage`]
+///
+/// # Stability
+///
+/// Same as with [`OpCode`], the discriminants shouldn't change.
+#[derive(strum::FromRepr, Clone, Copy, Debug, PartialEq)]
+#[repr(u8)]
+pub enum CloseReason {
+    /// The peer is done with the connection
+    Normal = 0,
+    /// The peer is shutting down
+    GoingAway = 1,
+    /// The peer received something it couldn't parse
+    ProtocolError = 2,
+    /// The peer received a message longer than it accepts
+    MessageTooBig = 3,
+    /// The peer doesn't serve whoever is on the other end, e.g. a local user that isn't
+    /// authorized
+    Unauthorized = 4,
+    /// The peer sent a reason `cower` doesn't know about
+    Unknown = u8::MAX,
+}
+
+/// State of a container, as reported by the container engine
+///
+/// # Stability
+///
+/// Same as with [`OpCode`], the discriminants shouldn't change.
+#[allow(missing_docs)]
+#[derive(strum::FromRepr, Clone, Copy, Debug, PartialEq)]
+#[repr(u8)]
+pub enum ContainerState {
+    Created = 0,
+    Running = 1,
+    Paused = 2,
+    Restarting = 3,
+    Removing = 4,
+    Stopping = 5,
+    Exited = 6,
+    Dead = 7,
+    /// The engine reported a state `cower` doesn't know about
+    Unknown = u8::MAX,
 }
 
 /// The header of the message containing control fields
 ///
 /// # Serialization
 ///
+/// The header is usually [`HEADER_SIZE`] bytes long: the opcode as a single byte, followed by the
+/// payload length as a big-endian (network byte order) `u16`. Payloads that are
+/// [`EXTENDED_LENGTH`] bytes or longer have the `u16` set to [`EXTENDED_LENGTH`], followed by the
+/// actual length as a big-endian `u32`, making the header [`EXTENDED_HEADER_SIZE`] bytes long.
+/// See `PROTOCOL.md` for details.
+///
 /// If you are implementing the serialization mechanism for the header somewhere, **DON'T USE THE
 /// SIZE OF THE STRUCT PROVIDED BY** [`std::mem::size_of<MessageHeader>()`]**!** The struct is
 /// aligned, which means that the serialized bytes will be of a different length.
 #[allow(missing_docs)] // the fields are painfully obvious
-#[derive(Debug)]
+#[derive(Clone, Copy, Debug, PartialEq)]
 pub struct MessageHeader {
     pub opcode: OpCode,
-    pub length: u16,
+    pub length: u32,
 }
 
 impl MessageHeader {
-    /// Serialize the message header into bytes
-    pub fn serialize(&self) -> [u8; HEADER_SIZE as usize] {
-        let mut buf = [0; HEADER_SIZE as usize];
+    /// Size of the serialized header in bytes
+    pub fn size(&self) -> u16 {
+        if self.length < EXTENDED_LENGTH.into() {
+            HEADER_SIZE
+        } else {
+            EXTENDED_HEADER_SIZE
+        }
+    }
+
+    /// Size of the serialized header at the start of `buf`. Only the first [`HEADER_SIZE`] bytes
+    /// are needed to tell
+    pub fn serialized_size(buf: &[u8]) -> crate::Result<u16> {
+        let &[_, length_high, length_low, ..] = buf else {
+            return Err(crate::Error::UnknownMessage);
+        };
 
-        let (opcode, length) = buf.split_at_mut(size_of::<OpCode>());
-        // yes, conversion to big endian is useless here, but if the opcode type changes sometime
-        // later this will be needed
-        opcode[0] = (self.opcode as u8).to_be();
-        let length_bytes = self.length.to_be_bytes();
-        length.copy_from_slice(&length_bytes);
+        if u16::from_be_bytes([length_high, length_low]) == EXTENDED_LENGTH {
+            Ok(EXTENDED_HEADER_SIZE)
+        } else {
+            Ok(HEADER_SIZE)
+        }
+    }
+
+    /// Serialize the message header into bytes
+    pub fn serialize(&self) -> Vec<u8> {
+        let mut buf = Vec::with_capacity(self.size().into());
+        buf.push(self.opcode as u8);
+        push_length(&mut buf, self.length);
 
         buf
     }
 
-    /// Parse the header from a provided buffer
+    /// Parse the header from a provided buffer. Only the first [`MessageHeader::size`] bytes are
+    /// read
     pub fn deserialize(buf: &[u8]) -> crate::Result<Self> {
-        if buf.len() < HEADER_SIZE.into() {
+        let Some((&opcode, rest)) = buf.split_first() else {
             return Err(crate::Error::UnknownMessage);
-        }
-
-        let (opcode_buf, length_buf) = buf.split_at(size_of::<OpCode>());
-        // TODO: check if this behaves right on little endian
-        let opcode = if cfg!(target_endian = "big") {
-            opcode_buf[0]
-        } else {
-            opcode_buf[0].to_le()
         };
-        let opcode = OpCode::from_repr(opcode).ok_or(crate::Error::UnknownMessage)?;
 
-        // TODO: also check if this behaves right on little endian
-        let mut length: u16 = 0;
-        length += u16::from(length_buf[0] << 1);
-        length += u16::from(length_buf[1]);
+        let opcode = OpCode::from_repr(opcode).ok_or(crate::Error::UnknownMessage)?;
+        let (length, _) = split_length(rest).ok_or(crate::Error::UnknownMessage)?;
 
         Ok(Self { opcode, length })
     }
@@ -82,11 +226,19 @@ impl MessageHeader {
 
 #[cfg(test)]
 mod header_tests {
-    use crate::message::{HEADER_SIZE, MessageHeader, OpCode};
+    use proptest::prelude::*;
+
+    use crate::message::{
+        EXTENDED_HEADER_SIZE, EXTENDED_LENGTH, HEADER_SIZE, MessageHeader, OpCode,
+    };
+
+    fn opcode() -> impl Strategy<Value = OpCode> {
+        any::<u8>().prop_filter_map("unassigned opcode", OpCode::from_repr)
+    }
 
     #[test]
     fn serialize_header() {
-        const LENGTH: u16 = 69;
+        const LENGTH: u32 = 69;
 
         let header = MessageHeader {
             opcode: OpCode::StartMessage,
@@ -95,7 +247,8 @@ mod header_tests {
 
         let serialized = header.serialize();
 
-        assert_eq!(serialized[0], (OpCode::StartMessage as u8).to_be());
+        assert_eq!(serialized.len(), HEADER_SIZE.into());
+        assert_eq!(serialized[0], OpCode::StartMessage as u8);
         let length_offset: usize = 1;
         assert_eq!(
             &serialized[length_offset..(size_of::<u16>() + length_offset)],
@@ -103,6 +256,49 @@ mod header_tests {
         );
     }
 
+    #[test]
+    fn serialize_extended_header() {
+        let header = MessageHeader {
+            opcode: OpCode::LogDataMessage,
+            length: 0x0102_0304,
+        };
+
+        assert_eq!(header.size(), EXTENDED_HEADER_SIZE);
+        assert_eq!(
+            header.serialize(),
+            [
+                OpCode::LogDataMessage as u8,
+                0xff,
+                0xff,
+                0x01,
+                0x02,
+                0x03,
+                0x04
+            ]
+        );
+    }
+
+    #[test]
+    fn extended_length_boundary() -> crate::Result<()> {
+        for (length, size) in [
+            (u32::from(EXTENDED_LENGTH) - 1, HEADER_SIZE),
+            (u32::from(EXTENDED_LENGTH), EXTENDED_HEADER_SIZE),
+            (u32::MAX, EXTENDED_HEADER_SIZE),
+        ] {
+            let header = MessageHeader {
+                opcode: OpCode::StartMessage,
+                length,
+            };
+            let serialized = header.serialize();
+
+            assert_eq!(serialized.len(), size.into());
+            assert_eq!(MessageHeader::serialized_size(&serialized)?, size);
+            assert_eq!(MessageHeader::deserialize(&serialized)?, header);
+        }
+
+        Ok(())
+    }
+
     #[test]
     fn deserialize_header() -> crate::Result<()> {
         const OPCODE: OpCode = OpCode::StartMessage;
@@ -116,7 +312,16 @@ mod header_tests {
 
         let header = MessageHeader::deserialize(&header_buf)?;
         assert_eq!(header.opcode, OPCODE);
-        assert_eq!(header.length, LENGTH);
+        assert_eq!(header.length, LENGTH.into());
+
+        Ok(())
+    }
+
+    #[test]
+    fn deserialize_header_big_endian() -> crate::Result<()> {
+        let header = MessageHeader::deserialize(&[OpCode::LogDataMessage as u8, 0x01, 0x02])?;
+        assert_eq!(header.opcode, OpCode::LogDataMessage);
+        assert_eq!(header.length, 0x0102);
 
         Ok(())
     }
@@ -135,6 +340,49 @@ mod header_tests {
 
         Ok(())
     }
+
+    #[test]
+    fn deserialize_header_too_short() {
+        for length in 0..HEADER_SIZE.into() {
+            let header_buf = vec![0; length];
+            assert!(MessageHeader::deserialize(&header_buf).is_err());
+        }
+
+        // the extended length is missing
+        for length in 0..size_of::<u32>() {
+            let header_buf = [&[0, 0xff, 0xff], vec![0; length].as_slice()].concat();
+            assert!(MessageHeader::deserialize(&header_buf).is_err());
+        }
+    }
+
+    #[test]
+    fn deserialize_needlessly_extended_header() {
+        // 5 fits into the regular header, so there's no reason to extend it
+        let header_buf = [0, 0xff, 0xff, 0, 0, 0, 5];
+
+        assert!(MessageHeader::deserialize(&header_buf).is_err());
+    }
+
+    proptest! {
+        #[test]
+        fn header_roundtrip(opcode in opcode(), length in any::<u32>()) {
+            let header = MessageHeader { opcode, length };
+            let serialized = header.serialize();
+
+            prop_assert_eq!(serialized.len(), usize::from(header.size()));
+            prop_assert_eq!(MessageHeader::serialized_size(&serialized)?, header.size());
+            prop_assert_eq!(MessageHeader::deserialize(&serialized)?, header);
+        }
+
+        #[test]
+        fn header_length_is_big_endian(opcode in opcode(), length in any::<u16>()) {
+            prop_assume!(length != EXTENDED_LENGTH);
+            let serialized = MessageHeader { opcode, length: length.into() }.serialize();
+
+            prop_assert_eq!(serialized[0], opcode as u8);
+            prop_assert_eq!(&serialized[1..], &length.to_be_bytes());
+        }
+    }
 }
 
 /// A message to be sent or received over the network using [`crate::Connection`]
@@ -145,58 +393,480 @@ pub enum Message {
         /// Name/ID of the container to be started
         resource_name: String,
     },
+    /// A request for the logs of a container. The peer answers with any number of
+    /// [`Message::LogDataMessage`]s followed by a [`Message::LogsEndMessage`]
+    LogsMessage {
+        /// Name/ID of the container whose logs should be sent
+        resource_name: String,
+        /// Only send the last `tail` lines. `None` means the whole log
+        tail: Option<u32>,
+        /// Keep sending new output as it is produced
+        follow: bool,
+    },
+    /// A chunk of log output. The chunk isn't guaranteed to end on a line boundary
+    LogDataMessage {
+        /// Raw log bytes
+        data: Vec<u8>,
+    },
+    /// Marks the end of a log stream
+    LogsEndMessage,
+    /// Sent back when a request couldn't be fulfilled
+    ErrorMessage {
+        /// What went wrong
+        code: ErrorCode,
+    },
+    /// A message indicating a container should be stopped
+    StopMessage {
+        /// Name/ID of the container to be stopped
+        resource_name: String,
+        /// Seconds to wait before killing the container. `None` uses the engine's default
+        timeout: Option<u32>,
+        /// Signal to send instead of the container's default stop signal, e.g. `SIGINT`
+        signal: Option<String>,
+    },
+    /// A message indicating a container should be restarted
+    RestartMessage {
+        /// Name/ID of the container to be restarted
+        resource_name: String,
+        /// Seconds to wait before killing the container. `None` uses the engine's default
+        timeout: Option<u32>,
+    },
+    /// A message indicating a container should be paused (frozen without losing its memory)
+    PauseMessage {
+        /// Name/ID of the container to be paused
+        resource_name: String,
+    },
+    /// A message indicating a paused container should be resumed
+    UnpauseMessage {
+        /// Name/ID of the container to be resumed
+        resource_name: String,
+    },
+    /// The state of a container after an operation was performed on it
+    StateMessage {
+        /// The current state of the container
+        state: ContainerState,
+        /// Whether the container is running and passes its healthcheck, if it has one. Sent in
+        /// answer to [`Message::StatusMessage`]; `None` if the peer didn't check
+        ready: Option<bool>,
+    },
+    /// A heartbeat checking that the peer is still there. The peer answers with a
+    /// [`Message::PongMessage`] tagged with the same request ID. [`crate::Connection`] does this
+    /// on its own, so neither message is ever returned from receiving
+    PingMessage,
+    /// The answer to a [`Message::PingMessage`]
+    PongMessage,
+    /// The last message sent over a connection, telling the peer that the connection was closed
+    /// on purpose. [`crate::Connection`] reports it as [`crate::Error::Closed`] instead of
+    /// returning it from receiving
+    CloseMessage {
+        /// Why the connection was closed
+        reason: CloseReason,
+        /// Details about the reason, meant for humans
+        text: Option<String>,
+    },
+    /// A request for the state of a container. The peer answers with a [`Message::StateMessage`]
+    StatusMessage {
+        /// Name/ID of the container whose state should be sent
+        resource_name: String,
+    },
+    /// A request for the resources the peer exposes. The peer answers with a
+    /// [`Message::ResourceMessage`] for every resource followed by a [`Message::ListEndMessage`]
+    ListMessage,
+    /// A single resource exposed by the peer
+    ResourceMessage {
+        /// Name of the resource
+        resource_name: String,
+        /// The current state of the resource. `None` for resources that aren't a single
+        /// container, like groups
+        state: Option<ContainerState>,
+        /// Human-readable description of the resource
+        description: Option<String>,
+    },
+    /// Marks the end of a resource list
+    ListEndMessage,
+}
+
+/// Fails on payloads longer than [`MAX_MESSAGE_PAYLOAD_LENGTH`]
+fn payload_length(payload: &[u8]) -> crate::Result<u32> {
+    payload
+        .len()
+        .try_into()
+        .map_err(|_| crate::Error::MesssageTooBig)
 }
 
 impl Message {
+    fn opcode(&self) -> OpCode {
+        match self {
+            Self::StartMessage { .. } => OpCode::StartMessage,
+            Self::LogsMessage { .. } => OpCode::LogsMessage,
+            Self::LogDataMessage { .. } => OpCode::LogDataMessage,
+            Self::LogsEndMessage => OpCode::LogsEndMessage,
+            Self::ErrorMessage { .. } => OpCode::ErrorMessage,
+            Self::StopMessage { .. } => OpCode::StopMessage,
+            Self::RestartMessage { .. } => OpCode::RestartMessage,
+            Self::StateMessage { .. } => OpCode::StateMessage,
+            Self::PauseMessage { .. } => OpCode::PauseMessage,
+            Self::UnpauseMessage { .. } => OpCode::UnpauseMessage,
+            Self::PingMessage => OpCode::PingMessage,
+            Self::PongMessage => OpCode::PongMessage,
+            Self::CloseMessage { .. } => OpCode::CloseMessage,
+            Self::StatusMessage { .. } => OpCode::StatusMessage,
+            Self::ListMessage => OpCode::ListMessage,
+            Self::ResourceMessage { .. } => OpCode::ResourceMessage,
+            Self::ListEndMessage => OpCode::ListEndMessage,
+        }
+    }
+
     /// Create a header from the current message
     pub fn create_header(&self) -> crate::Result<MessageHeader> {
-        Ok(match self {
-            Self::StartMessage { resource_name } => MessageHeader {
-                opcode: OpCode::StartMessage,
-                length: resource_name
-                    .len()
-                    .try_into()
-                    .map_err(|_| crate::Error::MesssageTooBig)?,
-            },
+        Self::header_for(self.opcode(), &self.serialize_payload()?)
+    }
+
+    fn header_for(opcode: OpCode, payload: &[u8]) -> crate::Result<MessageHeader> {
+        Ok(MessageHeader {
+            opcode,
+            length: payload_length(payload)?,
         })
     }
 
+    /// Serialize the whole message - the header followed by the payload - into bytes ready to be
+    /// sent over the network
+    pub fn serialize(&self) -> crate::Result<Vec<u8>> {
+        self.serialize_request(NO_REQUEST_ID)
+    }
+
+    /// Same as [`Message::serialize`], but tags the message with `request_id`
+    pub fn serialize_request(&self, request_id: RequestId) -> crate::Result<Vec<u8>> {
+        let payload_buf = self.encode_payload(request_id)?;
+        let header_buf = Self::header_for(self.opcode(), &payload_buf)?.serialize();
+
+        Ok([header_buf.as_slice(), &payload_buf].concat())
+    }
+
     /// Serialize the payload data into bytes. This doesn't include the header; you have to
     /// construct the header separately
     pub fn serialize_payload(&self) -> crate::Result<Box<[u8]>> {
+        self.encode_payload(NO_REQUEST_ID)
+    }
+
+    fn encode_payload(&self, request_id: RequestId) -> crate::Result<Box<[u8]>> {
+        let mut payload = PayloadWriter::new();
+        if request_id != NO_REQUEST_ID {
+            payload.u32(Tag::RequestId, request_id)?;
+        }
+
         match self {
-            Self::StartMessage { resource_name } => {
-                if resource_name.len() > MAX_MESSAGE_PAYLOAD_LENGTH.into() {
-                    return Err(crate::Error::MesssageTooBig);
+            Self::StartMessage { resource_name }
+            | Self::PauseMessage { resource_name }
+            | Self::UnpauseMessage { resource_name }
+            | Self::StatusMessage { resource_name } => {
+                payload.str(Tag::ResourceName, resource_name)?;
+            }
+            Self::LogsMessage {
+                resource_name,
+                tail,
+                follow,
+            } => {
+                payload.str(Tag::ResourceName, resource_name)?;
+                if let Some(tail) = tail {
+                    payload.u32(Tag::Tail, *tail)?;
+                }
+                payload.bool(Tag::Follow, *follow)?;
+            }
+            Self::LogDataMessage { data } => payload.bytes(Tag::Data, data)?,
+            Self::LogsEndMessage
+            | Self::PingMessage
+            | Self::PongMessage
+            | Self::ListMessage
+            | Self::ListEndMessage => {}
+            Self::ErrorMessage { code } => payload.u8(Tag::ErrorCode, *code as u8)?,
+            Self::StopMessage {
+                resource_name,
+                timeout,
+                signal,
+            } => {
+                payload.str(Tag::ResourceName, resource_name)?;
+                if let Some(timeout) = timeout {
+                    payload.u32(Tag::Timeout, *timeout)?;
+                }
+                if let Some(signal) = signal {
+                    payload.str(Tag::Signal, signal)?;
+                }
+            }
+            Self::RestartMessage {
+                resource_name,
+                timeout,
+            } => {
+                payload.str(Tag::ResourceName, resource_name)?;
+                if let Some(timeout) = timeout {
+                    payload.u32(Tag::Timeout, *timeout)?;
+                }
+            }
+            Self::StateMessage { state, ready } => {
+                payload.u8(Tag::State, *state as u8)?;
+                if let Some(ready) = ready {
+                    payload.bool(Tag::Ready, *ready)?;
+                }
+            }
+            Self::CloseMessage { reason, text } => {
+                payload.u8(Tag::CloseReason, *reason as u8)?;
+                if let Some(text) = text {
+                    payload.str(Tag::Text, text)?;
+                }
+            }
+            Self::ResourceMessage {
+                resource_name,
+                state,
+                description,
+            } => {
+                payload.str(Tag::ResourceName, resource_name)?;
+                if let Some(state) = state {
+                    payload.u8(Tag::State, *state as u8)?;
+                }
+                if let Some(description) = description {
+                    payload.str(Tag::Text, description)?;
                 }
-
-                Ok(resource_name.as_bytes().to_vec().into_boxed_slice())
             }
         }
+
+        let buf = payload.finish();
+        payload_length(&buf)?;
+
+        Ok(buf.into_boxed_slice())
     }
 
-    /// Deserialize a message from a buffer. `payload_buf.len()` is assumed to be `<= MAX_MESSAGE_LENGTH`
+    /// Deserialize a message from a buffer. `payload_buf` has to be exactly as long as
+    /// `header.length` says. Malformed payloads are reported as errors, so this is safe to call
+    /// on anything a peer sends
     pub fn deserialize(header: &MessageHeader, payload_buf: &[u8]) -> crate::Result<Self> {
-        if payload_buf.len() > MAX_MESSAGE_PAYLOAD_LENGTH.into() {
-            return Err(crate::Error::MesssageTooBig);
+        Self::deserialize_request(header, payload_buf).map(|(_, message)| message)
+    }
+
+    /// Same as [`Message::deserialize`], but also returns the request ID the message is tagged
+    /// with. Untagged messages have the ID [`NO_REQUEST_ID`]
+    pub fn deserialize_request(
+        header: &MessageHeader,
+        payload_buf: &[u8],
+    ) -> crate::Result<(RequestId, Self)> {
+        if payload_length(payload_buf)? != header.length {
+            return Err(crate::Error::LengthMismatch);
         }
 
-        assert_eq!(payload_buf.len(), header.length.into());
+        let payload = PayloadReader::parse(payload_buf)?;
+        let request_id = payload.u32(Tag::RequestId)?.unwrap_or(NO_REQUEST_ID);
+        let resource_name = || -> crate::Result<String> {
+            let resource_name = payload.str(Tag::ResourceName)?;
+            Ok(required(resource_name, Tag::ResourceName)?.to_owned())
+        };
 
-        match header.opcode {
-            OpCode::StartMessage => {
-                let resource_name = &payload_buf[0..header.length.into()];
-                let resource_name = str::from_utf8(resource_name)?.to_owned();
+        let message: crate::Result<Self> = match header.opcode {
+            OpCode::StartMessage => Ok(Self::StartMessage {
+                resource_name: resource_name()?,
+            }),
+            OpCode::LogsMessage => Ok(Self::LogsMessage {
+                resource_name: resource_name()?,
+                tail: payload.u32(Tag::Tail)?,
+                follow: payload.bool(Tag::Follow)?.unwrap_or_default(),
+            }),
+            OpCode::LogDataMessage => Ok(Self::LogDataMessage {
+                data: required(payload.bytes(Tag::Data), Tag::Data)?.to_vec(),
+            }),
+            OpCode::LogsEndMessage => Ok(Self::LogsEndMessage),
+            OpCode::PingMessage => Ok(Self::PingMessage),
+            OpCode::PongMessage => Ok(Self::PongMessage),
+            OpCode::ErrorMessage => {
+                let code = required(payload.u8(Tag::ErrorCode)?, Tag::ErrorCode)?;
+                let code = ErrorCode::from_repr(code).ok_or(crate::Error::MalformedPayload)?;
 
-                Ok(Self::StartMessage { resource_name })
+                Ok(Self::ErrorMessage { code })
             }
-        }
+            OpCode::StopMessage => Ok(Self::StopMessage {
+                resource_name: resource_name()?,
+                timeout: payload.u32(Tag::Timeout)?,
+                signal: payload.str(Tag::Signal)?.map(str::to_owned),
+            }),
+            OpCode::RestartMessage => Ok(Self::RestartMessage {
+                resource_name: resource_name()?,
+                timeout: payload.u32(Tag::Timeout)?,
+            }),
+            OpCode::PauseMessage => Ok(Self::PauseMessage {
+                resource_name: resource_name()?,
+            }),
+            OpCode::UnpauseMessage => Ok(Self::UnpauseMessage {
+                resource_name: resource_name()?,
+            }),
+            OpCode::StateMessage => {
+                let state = required(payload.u8(Tag::State)?, Tag::State)?;
+                let state =
+                    ContainerState::from_repr(state).ok_or(crate::Error::MalformedPayload)?;
+
+                Ok(Self::StateMessage {
+                    state,
+                    ready: payload.bool(Tag::Ready)?,
+                })
+            }
+            OpCode::CloseMessage => {
+                let reason = required(payload.u8(Tag::CloseReason)?, Tag::CloseReason)?;
+
+                Ok(Self::CloseMessage {
+                    // the connection is closed either way, so new reasons don't need to be known
+                    reason: CloseReason::from_repr(reason).unwrap_or(CloseReason::Unknown),
+                    text: payload.str(Tag::Text)?.map(str::to_owned),
+                })
+            }
+            OpCode::StatusMessage => Ok(Self::StatusMessage {
+                resource_name: resource_name()?,
+            }),
+            OpCode::ListMessage => Ok(Self::ListMessage),
+            OpCode::ResourceMessage => {
+                let state = payload
+                    .u8(Tag::State)?
+                    .map(|state| {
+                        ContainerState::from_repr(state).ok_or(crate::Error::MalformedPayload)
+                    })
+                    .transpose()?;
+
+                Ok(Self::ResourceMessage {
+                    resource_name: resource_name()?,
+                    state,
+                    description: payload.str(Tag::Text)?.map(str::to_owned),
+                })
+            }
+            OpCode::ListEndMessage => Ok(Self::ListEndMessage),
+        };
+
+        Ok((request_id, message?))
     }
 }
 
 #[cfg(test)]
 mod message_tests {
-    use crate::Message;
+    use proptest::prelude::*;
+
+    use crate::{
+        Message,
+        message::{
+            CloseReason, ContainerState, EXTENDED_HEADER_SIZE, EXTENDED_LENGTH, ErrorCode,
+            HEADER_SIZE, MessageHeader, NO_REQUEST_ID, RequestId,
+        },
+    };
+
+    fn serde_roundtrip(message: &Message) -> crate::Result<Message> {
+        let header = message.create_header()?;
+        let payload = message.serialize_payload()?;
+
+        Message::deserialize(&header, &payload)
+    }
+
+    /// Goes through the bytes that would be sent over the network, header included
+    fn wire_roundtrip(message: &Message) -> crate::Result<Message> {
+        let bytes = message.serialize()?;
+        let header_size = MessageHeader::serialized_size(&bytes)?;
+        let (header, payload) = bytes.split_at(header_size.into());
+
+        let header = MessageHeader::deserialize(header)?;
+        assert_eq!(usize::try_from(header.length), Ok(payload.len()));
+
+        Message::deserialize(&header, payload)
+    }
+
+    proptest! {
+        #[test]
+        fn start_message_any_length(
+            resource_name in "[a-z0-9_.-]{0,1024}",
+            padding in 0..=200_000_usize,
+        ) {
+            // lengths are spread over both the regular and the extended header while keeping the
+            // names readable
+            let resource_name = resource_name + &"x".repeat(padding);
+            let message = Message::StartMessage {
+                resource_name: resource_name.clone(),
+            };
+
+            match wire_roundtrip(&message)? {
+                Message::StartMessage { resource_name: parsed } => {
+                    prop_assert_eq!(parsed, resource_name);
+                }
+                msg => prop_assert!(false, "deserialized to a different type: {msg:?}"),
+            }
+        }
+
+        #[test]
+        fn log_data_message_any_length(data in prop::collection::vec(any::<u8>(), 0..=1024)) {
+            let message = Message::LogDataMessage { data: data.clone() };
+
+            match wire_roundtrip(&message)? {
+                Message::LogDataMessage { data: parsed } => prop_assert_eq!(parsed, data),
+                msg => prop_assert!(false, "deserialized to a different type: {msg:?}"),
+            }
+        }
+    }
+
+    #[test]
+    fn start_message_wire_format() -> crate::Result<()> {
+        let message = Message::StartMessage {
+            resource_name: "ab".to_owned(),
+        };
+
+        // header, payload version, resource name field
+        let expected = [0, 0, 6, 1, 1, 0, 2, b'a', b'b'];
+        assert_eq!(message.serialize()?, expected);
+
+        Ok(())
+    }
+
+    #[test]
+    fn request_ids() -> crate::Result<()> {
+        let message = Message::LogsEndMessage;
+
+        for request_id in [NO_REQUEST_ID, 1, RequestId::MAX] {
+            let bytes = message.serialize_request(request_id)?;
+            let (header, payload) = bytes.split_at(HEADER_SIZE.into());
+            let header = MessageHeader::deserialize(header)?;
+
+            let (parsed_id, parsed) = Message::deserialize_request(&header, payload)?;
+            assert_eq!(parsed_id, request_id);
+            assert!(matches!(parsed, Message::LogsEndMessage));
+        }
+
+        // untagged messages are encoded the same way as before request IDs were introduced
+        assert_eq!(
+            message.serialize_request(NO_REQUEST_ID)?,
+            message.serialize()?
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn log_data_message_extended_field() -> crate::Result<()> {
+        let data: Vec<u8> = (0..=u8::MAX).cycle().take(EXTENDED_LENGTH.into()).collect();
+        let message = Message::LogDataMessage { data: data.clone() };
+
+        match wire_roundtrip(&message)? {
+            Message::LogDataMessage { data: parsed } => assert_eq!(parsed, data),
+            msg => panic!("Log data message deserialized to a different type: {msg:?}"),
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn serde_start_message_extended_length() -> crate::Result<()> {
+        let resource_name = "A".repeat(1024 * 1024);
+        let message = Message::StartMessage {
+            resource_name: resource_name.clone(),
+        };
+
+        match wire_roundtrip(&message)? {
+            Message::StartMessage {
+                resource_name: parsed,
+            } => assert_eq!(parsed, resource_name),
+            msg => panic!("Start message deserialized to a different type: {msg:?}"),
+        }
+
+        Ok(())
+    }
 
     #[test]
     fn serde_start_message() -> crate::Result<()> {
@@ -205,12 +875,8 @@ mod message_tests {
         let message = Message::StartMessage {
             resource_name: resource_name.to_owned(),
         };
-        let header = message.create_header()?;
-        let message = message.serialize_payload()?;
+        let message = serde_roundtrip(&message)?;
 
-        let message = Message::deserialize(&header, &message)?;
-
-        #[allow(irrefutable_let_patterns)] // TODO: remove this when more message types are added
         if let Message::StartMessage {
             resource_name: parsed_res_name,
         } = message
@@ -224,14 +890,555 @@ mod message_tests {
     }
 
     #[test]
-    fn serialize_start_message_payload_too_big() -> crate::Result<()> {
+    fn serialize_start_message_extended_header() -> crate::Result<()> {
         let fill_char = 'A';
         let s = fill_char.to_string().repeat((u16::MAX as usize) + 1);
 
-        if let Ok(msg) = (Message::StartMessage { resource_name: s }).serialize_payload() {
-            panic!("message serialized with overly long payload: {msg:?}")
+        // payloads that don't fit into a `u16` used to be rejected, now they extend the header
+        let header = (Message::StartMessage { resource_name: s }).create_header()?;
+        assert_eq!(header.size(), EXTENDED_HEADER_SIZE);
+
+        Ok(())
+    }
+
+    #[test]
+    fn serde_logs_message() -> crate::Result<()> {
+        for (tail, follow) in [(None, false), (Some(0), true), (Some(100), false)] {
+            let message = Message::LogsMessage {
+                resource_name: "my_resource".to_owned(),
+                tail,
+                follow,
+            };
+
+            match serde_roundtrip(&message)? {
+                Message::LogsMessage {
+                    resource_name: parsed_res_name,
+                    tail: parsed_tail,
+                    follow: parsed_follow,
+                } => {
+                    assert_eq!(parsed_res_name, "my_resource");
+                    assert_eq!(parsed_tail, tail);
+                    assert_eq!(parsed_follow, follow);
+                }
+                msg => panic!("Logs message deserialized to a different type: {msg:?}"),
+            }
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn serde_error_message() -> crate::Result<()> {
+        let message = Message::ErrorMessage {
+            code: ErrorCode::PermissionDenied,
+        };
+
+        match serde_roundtrip(&message)? {
+            Message::ErrorMessage { code } => assert_eq!(code, ErrorCode::PermissionDenied),
+            msg => panic!("Error message deserialized to a different type: {msg:?}"),
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn serde_stop_message() -> crate::Result<()> {
+        for (timeout, signal) in [(None, None), (Some(30), Some("SIGINT".to_owned()))] {
+            let message = Message::StopMessage {
+                resource_name: "my_resource".to_owned(),
+                timeout,
+                signal: signal.clone(),
+            };
+
+            match serde_roundtrip(&message)? {
+                Message::StopMessage {
+                    resource_name: parsed_res_name,
+                    timeout: parsed_timeout,
+                    signal: parsed_signal,
+                } => {
+                    assert_eq!(parsed_res_name, "my_resource");
+                    assert_eq!(parsed_timeout, timeout);
+                    assert_eq!(parsed_signal, signal);
+                }
+                msg => panic!("Stop message deserialized to a different type: {msg:?}"),
+            }
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn serde_restart_message() -> crate::Result<()> {
+        let message = Message::RestartMessage {
+            resource_name: "my_resource".to_owned(),
+            timeout: Some(10),
+        };
+
+        match serde_roundtrip(&message)? {
+            Message::RestartMessage {
+                resource_name,
+                timeout,
+            } => {
+                assert_eq!(resource_name, "my_resource");
+                assert_eq!(timeout, Some(10));
+            }
+            msg => panic!("Restart message deserialized to a different type: {msg:?}"),
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn serde_state_message() -> crate::Result<()> {
+        let message = Message::StateMessage {
+            state: ContainerState::Exited,
+            ready: None,
+        };
+
+        match serde_roundtrip(&message)? {
+            Message::StateMessage { state, ready } => {
+                assert_eq!(state, ContainerState::Exited);
+                assert_eq!(ready, None);
+            }
+            msg => panic!("State message deserialized to a different type: {msg:?}"),
+        }
+
+        let message = Message::StateMessage {
+            state: ContainerState::Running,
+            ready: Some(false),
+        };
+
+        match serde_roundtrip(&message)? {
+            Message::StateMessage { state, ready } => {
+                assert_eq!(state, ContainerState::Running);
+                assert_eq!(ready, Some(false));
+            }
+            msg => panic!("State message deserialized to a different type: {msg:?}"),
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn serde_pause_messages() -> crate::Result<()> {
+        let message = Message::PauseMessage {
+            resource_name: "my_resource".to_owned(),
+        };
+        match serde_roundtrip(&message)? {
+            Message::PauseMessage { resource_name } => assert_eq!(resource_name, "my_resource"),
+            msg => panic!("Pause message deserialized to a different type: {msg:?}"),
+        }
+
+        let message = Message::UnpauseMessage {
+            resource_name: "my_resource".to_owned(),
+        };
+        match serde_roundtrip(&message)? {
+            Message::UnpauseMessage { resource_name } => assert_eq!(resource_name, "my_resource"),
+            msg => panic!("Unpause message deserialized to a different type: {msg:?}"),
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn serde_close_message() -> crate::Result<()> {
+        let message = Message::CloseMessage {
+            reason: CloseReason::GoingAway,
+            text: Some("shutting down".to_owned()),
+        };
+        match wire_roundtrip(&message)? {
+            Message::CloseMessage { reason, text } => {
+                assert_eq!(reason, CloseReason::GoingAway);
+                assert_eq!(text.as_deref(), Some("shutting down"));
+            }
+            msg => panic!("Close message deserialized to a different type: {msg:?}"),
+        }
+
+        let message = Message::CloseMessage {
+            reason: CloseReason::Normal,
+            text: None,
+        };
+        match wire_roundtrip(&message)? {
+            Message::CloseMessage { reason, text } => {
+                assert_eq!(reason, CloseReason::Normal);
+                assert_eq!(text, None);
+            }
+            msg => panic!("Close message deserialized to a different type: {msg:?}"),
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn serde_list_messages() -> crate::Result<()> {
+        let message = Message::StatusMessage {
+            resource_name: "my_resource".to_owned(),
+        };
+        match wire_roundtrip(&message)? {
+            Message::StatusMessage { resource_name } => assert_eq!(resource_name, "my_resource"),
+            msg => panic!("Status message deserialized to a different type: {msg:?}"),
+        }
+
+        assert!(matches!(
+            wire_roundtrip(&Message::ListMessage)?,
+            Message::ListMessage
+        ));
+        assert!(matches!(
+            wire_roundtrip(&Message::ListEndMessage)?,
+            Message::ListEndMessage
+        ));
+
+        let message = Message::ResourceMessage {
+            resource_name: "minecraft".to_owned(),
+            state: Some(ContainerState::Paused),
+            description: Some("Survival server".to_owned()),
+        };
+        match wire_roundtrip(&message)? {
+            Message::ResourceMessage {
+                resource_name,
+                state,
+                description,
+            } => {
+                assert_eq!(resource_name, "minecraft");
+                assert_eq!(state, Some(ContainerState::Paused));
+                assert_eq!(description.as_deref(), Some("Survival server"));
+            }
+            msg => panic!("Resource message deserialized to a different type: {msg:?}"),
+        }
+
+        let message = Message::ResourceMessage {
+            resource_name: "stack".to_owned(),
+            state: None,
+            description: None,
+        };
+        match wire_roundtrip(&message)? {
+            Message::ResourceMessage {
+                state, description, ..
+            } => {
+                assert_eq!(state, None);
+                assert_eq!(description, None);
+            }
+            msg => panic!("Resource message deserialized to a different type: {msg:?}"),
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn serde_heartbeat_messages() -> crate::Result<()> {
+        assert!(matches!(
+            wire_roundtrip(&Message::PingMessage)?,
+            Message::PingMessage
+        ));
+        assert!(matches!(
+            wire_roundtrip(&Message::PongMessage)?,
+            Message::PongMessage
+        ));
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod malformed_tests {
+    use proptest::prelude::*;
+
+    use crate::{
+        Message,
+        message::{
+            CloseReason, EXTENDED_LENGTH, HEADER_SIZE, MessageHeader, OpCode,
+            payload::{PAYLOAD_VERSION, Tag},
+        },
+    };
+
+    /// Parses a whole frame, header included, like [`crate::Connection::receive`] does
+    fn parse_frame(frame: &[u8]) -> crate::Result<Message> {
+        let header = MessageHeader::deserialize(frame)?;
+        let payload = frame.get(header.size().into()..).unwrap_or_default();
+
+        Message::deserialize(&header, payload)
+    }
+
+    fn frame(opcode: OpCode, payload: &[u8]) -> Vec<u8> {
+        let length = u32::try_from(payload.len()).expect("test payload is too long");
+        let header = MessageHeader { opcode, length };
+
+        [header.serialize().as_slice(), payload].concat()
+    }
+
+    #[test]
+    fn truncated_header() {
+        let frame = frame(OpCode::StartMessage, b"my_resource");
+
+        for length in 0..HEADER_SIZE.into() {
+            assert!(matches!(
+                parse_frame(&frame[..length]),
+                Err(crate::Error::UnknownMessage)
+            ));
+        }
+    }
+
+    #[test]
+    fn truncated_payload() {
+        let frame = frame(OpCode::StartMessage, b"my_resource");
+
+        for length in usize::from(HEADER_SIZE)..frame.len() {
+            assert!(matches!(
+                parse_frame(&frame[..length]),
+                Err(crate::Error::LengthMismatch)
+            ));
+        }
+    }
+
+    #[test]
+    fn length_longer_than_payload() {
+        let header = MessageHeader {
+            opcode: OpCode::LogDataMessage,
+            length: u32::MAX,
+        };
+
+        assert!(matches!(
+            Message::deserialize(&header, b"short"),
+            Err(crate::Error::LengthMismatch)
+        ));
+    }
+
+    #[test]
+    fn payload_longer_than_length() {
+        let header = MessageHeader {
+            opcode: OpCode::LogDataMessage,
+            length: 1,
+        };
+
+        assert!(matches!(
+            Message::deserialize(&header, b"long"),
+            Err(crate::Error::LengthMismatch)
+        ));
+    }
+
+    #[test]
+    fn truncated_extended_header() {
+        let frame = frame(OpCode::LogDataMessage, &vec![0; EXTENDED_LENGTH.into()]);
+
+        for length in usize::from(HEADER_SIZE)..usize::from(header_size(&frame)) {
+            assert!(matches!(
+                parse_frame(&frame[..length]),
+                Err(crate::Error::UnknownMessage)
+            ));
+        }
+    }
+
+    fn header_size(frame: &[u8]) -> u16 {
+        MessageHeader::serialized_size(frame).expect("test frame has a header")
+    }
+
+    #[test]
+    fn unknown_opcodes() {
+        for opcode in 0..=u8::MAX {
+            if OpCode::from_repr(opcode).is_some() {
+                continue;
+            }
+
+            assert!(matches!(
+                parse_frame(&[opcode, 0, 0]),
+                Err(crate::Error::UnknownMessage)
+            ));
+        }
+    }
+
+    /// Encodes a payload field by hand, so that the tests don't depend on the encoder
+    fn field(tag: Tag, value: &[u8]) -> Vec<u8> {
+        let length = u16::try_from(value.len()).expect("test field is too long");
+
+        [&[tag as u8], length.to_be_bytes().as_slice(), value].concat()
+    }
+
+    fn payload(fields: &[Vec<u8>]) -> Vec<u8> {
+        [vec![PAYLOAD_VERSION], fields.concat()].concat()
+    }
+
+    #[test]
+    fn invalid_utf8() {
+        const INVALID: &[u8] = &[0xff, 0xfe, 0xfd];
+
+        let name = payload(&[field(Tag::ResourceName, INVALID)]);
+        let frames = [
+            frame(OpCode::StartMessage, &name),
+            frame(OpCode::PauseMessage, &name),
+            frame(OpCode::UnpauseMessage, &name),
+            frame(OpCode::LogsMessage, &name),
+            frame(OpCode::RestartMessage, &name),
+            frame(OpCode::StopMessage, &name),
+            frame(
+                OpCode::StopMessage,
+                &payload(&[
+                    field(Tag::ResourceName, b"my_resource"),
+                    field(Tag::Signal, INVALID),
+                ]),
+            ),
+        ];
+
+        for frame in frames {
+            assert!(
+                matches!(parse_frame(&frame), Err(crate::Error::InvalidUtf8(_))),
+                "{frame:?} was accepted"
+            );
+        }
+    }
+
+    #[test]
+    fn truncated_fields() {
+        let name = field(Tag::ResourceName, b"my_resource");
+        let frames = [
+            // missing tag-length-value parts
+            frame(OpCode::StartMessage, &[]),
+            frame(
+                OpCode::StartMessage,
+                &[PAYLOAD_VERSION, Tag::ResourceName as u8],
+            ),
+            frame(
+                OpCode::StartMessage,
+                &[PAYLOAD_VERSION, Tag::ResourceName as u8, 0],
+            ),
+            frame(OpCode::StartMessage, &name[..name.len() - 1]),
+            // extended field length that would fit into a `u16`
+            frame(
+                OpCode::StartMessage,
+                &[
+                    PAYLOAD_VERSION,
+                    Tag::ResourceName as u8,
+                    0xff,
+                    0xff,
+                    0,
+                    0,
+                    0,
+                    1,
+                    b'a',
+                ],
+            ),
+            // fixed-size values of the wrong size
+            frame(
+                OpCode::RestartMessage,
+                &payload(&[name.clone(), field(Tag::Timeout, &[0, 0, 1])]),
+            ),
+            frame(
+                OpCode::ErrorMessage,
+                &payload(&[field(Tag::ErrorCode, &[])]),
+            ),
+            frame(
+                OpCode::StateMessage,
+                &payload(&[field(Tag::State, &[0, 0])]),
+            ),
+            frame(
+                OpCode::LogsMessage,
+                &payload(&[name, field(Tag::Follow, &[2])]),
+            ),
+        ];
+
+        for frame in frames {
+            assert!(
+                matches!(parse_frame(&frame), Err(crate::Error::MalformedPayload)),
+                "{frame:?} was accepted"
+            );
+        }
+    }
+
+    #[test]
+    fn missing_fields() {
+        let frames = [
+            frame(OpCode::StartMessage, &payload(&[])),
+            frame(OpCode::LogDataMessage, &payload(&[])),
+            frame(
+                OpCode::StopMessage,
+                &payload(&[field(Tag::Signal, b"SIGINT")]),
+            ),
+        ];
+
+        for frame in frames {
+            assert!(
+                matches!(parse_frame(&frame), Err(crate::Error::MissingField(_))),
+                "{frame:?} was accepted"
+            );
+        }
+    }
+
+    #[test]
+    fn unknown_codes() {
+        let error = payload(&[field(Tag::ErrorCode, &[u8::MAX])]);
+        assert!(parse_frame(&frame(OpCode::ErrorMessage, &error)).is_err());
+
+        let state = payload(&[field(Tag::State, &[100])]);
+        assert!(parse_frame(&frame(OpCode::StateMessage, &state)).is_err());
+
+        // unlike the others, unknown close reasons don't prevent closing
+        let close = payload(&[field(Tag::CloseReason, &[100])]);
+        assert!(matches!(
+            parse_frame(&frame(OpCode::CloseMessage, &close)),
+            Ok(Message::CloseMessage {
+                reason: CloseReason::Unknown,
+                text: None
+            })
+        ));
+    }
+
+    #[test]
+    fn unsupported_version() {
+        let payload = [
+            &[PAYLOAD_VERSION + 1],
+            field(Tag::ResourceName, b"a").as_slice(),
+        ]
+        .concat();
+
+        assert!(matches!(
+            parse_frame(&frame(OpCode::StartMessage, &payload)),
+            Err(crate::Error::UnsupportedVersion(_))
+        ));
+    }
+
+    #[test]
+    fn duplicate_fields() {
+        let name = field(Tag::ResourceName, b"my_resource");
+
+        assert!(matches!(
+            parse_frame(&frame(
+                OpCode::StartMessage,
+                &payload(&[name.clone(), name])
+            )),
+            Err(crate::Error::MalformedPayload)
+        ));
+    }
+
+    #[test]
+    fn unknown_fields_are_skipped() -> crate::Result<()> {
+        const UNKNOWN_TAG: u8 = 200;
+
+        let unknown = [&[UNKNOWN_TAG], 3_u16.to_be_bytes().as_slice(), b"new"].concat();
+        let payload = payload(&[
+            unknown.clone(),
+            field(Tag::ResourceName, b"my_resource"),
+            unknown,
+        ]);
+
+        match parse_frame(&frame(OpCode::StartMessage, &payload))? {
+            Message::StartMessage { resource_name } => assert_eq!(resource_name, "my_resource"),
+            msg => panic!("Start message deserialized to a different type: {msg:?}"),
         }
 
         Ok(())
     }
+
+    proptest! {
+        #[test]
+        fn arbitrary_frames_dont_panic(frame in prop::collection::vec(any::<u8>(), 0..=512)) {
+            _ = parse_frame(&frame);
+        }
+
+        #[test]
+        fn arbitrary_payloads_dont_panic(
+            opcode in any::<u8>().prop_filter_map("unassigned opcode", OpCode::from_repr),
+            payload in prop::collection::vec(any::<u8>(), 0..=512),
+        ) {
+            _ = parse_frame(&frame(opcode, &payload));
+        }
+    }
 }
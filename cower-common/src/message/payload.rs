@@ -0,0 +1,160 @@
+//! The tag-length-value encoding of message payloads. See `PROTOCOL.md` for the specification.
+
+use std::collections::BTreeMap;
+
+use super::{push_length, split_length};
+
+/// Version of the payload encoding. Only bumped on incompatible changes; new fields don't need a
+/// new version, because receivers skip fields they don't know
+pub(crate) const PAYLOAD_VERSION: u8 = 1;
+
+/// Tags identifying payload fields. The tags are shared by all messages, so a tag means the same
+/// thing in every message it appears in
+///
+/// # Stability
+///
+/// Same as with [`super::OpCode`], the discriminants shouldn't change.
+#[derive(strum::FromRepr, Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+#[repr(u8)]
+pub(crate) enum Tag {
+    /// UTF-8 string
+    ResourceName = 1,
+    /// `u32`
+    Timeout = 2,
+    /// UTF-8 string
+    Signal = 3,
+    /// `u32`
+    Tail = 4,
+    /// `u8`, either 0 or 1
+    Follow = 5,
+    /// Raw bytes
+    Data = 6,
+    /// `u8`, see [`super::ErrorCode`]
+    ErrorCode = 7,
+    /// `u8`, see [`super::ContainerState`]
+    State = 8,
+    /// `u32`, see [`super::RequestId`]
+    RequestId = 9,
+    /// `u8`, see [`super::CloseReason`]
+    CloseReason = 10,
+    /// UTF-8 string meant for humans
+    Text = 11,
+    /// `u8`, either 0 or 1
+    Ready = 12,
+}
+
+/// Builds a payload field by field
+pub(crate) struct PayloadWriter(Vec<u8>);
+
+impl PayloadWriter {
+    pub(crate) fn new() -> Self {
+        Self(vec![PAYLOAD_VERSION])
+    }
+
+    pub(crate) fn bytes(&mut self, tag: Tag, value: &[u8]) -> crate::Result<()> {
+        let length = value
+            .len()
+            .try_into()
+            .map_err(|_| crate::Error::MesssageTooBig)?;
+
+        self.0.push(tag as u8);
+        push_length(&mut self.0, length);
+        self.0.extend_from_slice(value);
+
+        Ok(())
+    }
+
+    pub(crate) fn str(&mut self, tag: Tag, value: &str) -> crate::Result<()> {
+        self.bytes(tag, value.as_bytes())
+    }
+
+    pub(crate) fn u8(&mut self, tag: Tag, value: u8) -> crate::Result<()> {
+        self.bytes(tag, &value.to_be_bytes())
+    }
+
+    pub(crate) fn u32(&mut self, tag: Tag, value: u32) -> crate::Result<()> {
+        self.bytes(tag, &value.to_be_bytes())
+    }
+
+    pub(crate) fn bool(&mut self, tag: Tag, value: bool) -> crate::Result<()> {
+        self.u8(tag, value.into())
+    }
+
+    pub(crate) fn finish(self) -> Vec<u8> {
+        self.0
+    }
+}
+
+/// The fields of a parsed payload. Fields with unknown tags are skipped while parsing
+pub(crate) struct PayloadReader<'a>(BTreeMap<Tag, &'a [u8]>);
+
+impl<'a> PayloadReader<'a> {
+    /// Splits the payload into fields, failing instead of panicking on malformed payloads
+    pub(crate) fn parse(payload: &'a [u8]) -> crate::Result<Self> {
+        let Some((&version, mut rest)) = payload.split_first() else {
+            return Err(crate::Error::MalformedPayload);
+        };
+        if version != PAYLOAD_VERSION {
+            return Err(crate::Error::UnsupportedVersion(version));
+        }
+
+        let mut fields = BTreeMap::new();
+        while let Some((&tag, after_tag)) = rest.split_first() {
+            let (length, after_length) =
+                split_length(after_tag).ok_or(crate::Error::MalformedPayload)?;
+            let (value, after_value) = usize::try_from(length)
+                .ok()
+                .and_then(|length| after_length.split_at_checked(length))
+                .ok_or(crate::Error::MalformedPayload)?;
+            rest = after_value;
+
+            // fields added in newer versions of cower are skipped
+            let Some(tag) = Tag::from_repr(tag) else {
+                continue;
+            };
+            if fields.insert(tag, value).is_some() {
+                return Err(crate::Error::MalformedPayload);
+            }
+        }
+
+        Ok(Self(fields))
+    }
+
+    pub(crate) fn bytes(&self, tag: Tag) -> Option<&'a [u8]> {
+        self.0.get(&tag).copied()
+    }
+
+    pub(crate) fn str(&self, tag: Tag) -> crate::Result<Option<&'a str>> {
+        Ok(self.bytes(tag).map(str::from_utf8).transpose()?)
+    }
+
+    pub(crate) fn u8(&self, tag: Tag) -> crate::Result<Option<u8>> {
+        self.array(tag).map(|value| value.map(u8::from_be_bytes))
+    }
+
+    pub(crate) fn u32(&self, tag: Tag) -> crate::Result<Option<u32>> {
+        self.array(tag).map(|value| value.map(u32::from_be_bytes))
+    }
+
+    pub(crate) fn bool(&self, tag: Tag) -> crate::Result<Option<bool>> {
+        match self.u8(tag)? {
+            Some(0) => Ok(Some(false)),
+            Some(1) => Ok(Some(true)),
+            None => Ok(None),
+
+            Some(_) => Err(crate::Error::MalformedPayload),
+        }
+    }
+
+    fn array<const N: usize>(&self, tag: Tag) -> crate::Result<Option<[u8; N]>> {
+        self.bytes(tag)
+            .map(|value| value.try_into())
+            .transpose()
+            .map_err(|_| crate::Error::MalformedPayload)
+    }
+}
+
+/// Fails on missing fields that are required by a message
+pub(crate) fn required<T>(field: Option<T>, tag: Tag) -> crate::Result<T> {
+    field.ok_or(crate::Error::MissingField(tag as u8))
+}
@@ -0,0 +1,317 @@
+//! Connections over QUIC, built on `quinn`. Enable the `quic` feature to use them, which also
+//! enables `rustls` and `tokio`.
+//!
+//! Unlike the other transports, a QUIC connection carries every request on its own bidirectional
+//! stream, so a slow request never holds back the others and request IDs aren't needed. The
+//! streams are regular [`AsyncConnection`]s. QUIC connections also survive the client's address
+//! changing, e.g. when a home IP is renewed, without having to reconnect.
+
+use std::{
+    io,
+    marker::PhantomData,
+    net::{Ipv4Addr, Ipv6Addr, SocketAddr},
+    sync::Arc,
+};
+
+use quinn::{
+    ConnectionError, Endpoint, IdleTimeout, TransportConfig, VarInt,
+    crypto::rustls::{QuicClientConfig, QuicServerConfig},
+};
+use rustls::pki_types::CertificateDer;
+use tokio::net::{ToSocketAddrs, lookup_host};
+
+use crate::{
+    Acceptor, Client, Server, Timeouts,
+    asynchronous::AsyncConnection,
+    message::CloseReason,
+    tls::{PeerIdentity, ServerVerification, TlsConnector},
+};
+
+/// The ALPN protocol both ends have to agree on
+const ALPN_PROTOCOL: &[u8] = b"cower";
+
+/// Only the idle timeout applies to QUIC, which sends its own keep-alives in between
+fn transport_config(timeouts: Timeouts) -> Arc<TransportConfig> {
+    let mut transport = TransportConfig::default();
+    if let Some(idle) = timeouts.idle {
+        transport.max_idle_timeout(IdleTimeout::try_from(idle).ok());
+        transport.keep_alive_interval(Some(idle / 2));
+    }
+
+    Arc::new(transport)
+}
+
+fn tls_error(why: impl ToString) -> crate::Error {
+    crate::tls::Error::new(why.to_string()).into()
+}
+
+/// Turns the reason the connection was lost for into the error the other transports report
+fn connection_error(why: ConnectionError) -> crate::Error {
+    match why {
+        ConnectionError::ApplicationClosed(close) => crate::Error::Closed {
+            reason: u8::try_from(close.error_code.into_inner())
+                .ok()
+                .and_then(CloseReason::from_repr)
+                .unwrap_or(CloseReason::Unknown),
+            text: (!close.reason.is_empty())
+                .then(|| String::from_utf8_lossy(&close.reason).into_owned()),
+        },
+        ConnectionError::TimedOut => crate::Error::PeerTimedOut,
+        why => crate::Error::QuicFailure(why),
+    }
+}
+
+/// A QUIC connection between `cower` programs, carrying one request per stream
+///
+/// # Initialization
+///
+/// If you need to connect to a server, use [`QuicConnection::connect`]. If you need to accept a
+/// connection from a client, use [`QuicAcceptor`] instead.
+pub struct QuicConnection<T> {
+    connection: quinn::Connection,
+    /// The client's own endpoint, which has to stay around until the connection is closed
+    endpoint: Option<Endpoint>,
+    peer: Option<PeerIdentity>,
+    _0: PhantomData<T>,
+}
+
+impl<T> QuicConnection<T> {
+    /// Fingerprints of the certificate the server presented. This is only available on the
+    /// client's end
+    pub fn peer_identity(&self) -> Option<&PeerIdentity> {
+        self.peer.as_ref()
+    }
+
+    /// Closes the connection on purpose, telling the peer why. Requests that are still in
+    /// progress are abandoned
+    pub async fn close(self, reason: CloseReason, text: Option<&str>) -> crate::Result<()> {
+        let text = text.unwrap_or_default().as_bytes();
+        self.connection.close(VarInt::from(reason as u8), text);
+
+        // waits for the peer to learn about it, so that the process can exit right away
+        if let Some(endpoint) = self.endpoint {
+            endpoint.wait_idle().await;
+        }
+
+        Ok(())
+    }
+}
+
+impl QuicConnection<Client> {
+    /// Opens a stream for a new request. Send the request and receive the replies over it, then
+    /// drop it
+    pub async fn open_request(&self) -> crate::Result<AsyncConnection<Client>> {
+        let (send, receive) = self.connection.open_bi().await.map_err(connection_error)?;
+
+        Ok(AsyncConnection::new(
+            Box::new(tokio::io::join(receive, send)),
+            self.peer,
+        ))
+    }
+}
+
+impl QuicConnection<Server> {
+    /// Waits for the client to open a stream for a new request. Fails with
+    /// [`crate::Error::Closed`] once the client closes the connection
+    pub async fn accept_request(&self) -> crate::Result<AsyncConnection<Server>> {
+        let (send, receive) = self
+            .connection
+            .accept_bi()
+            .await
+            .map_err(connection_error)?;
+
+        Ok(AsyncConnection::new(
+            Box::new(tokio::io::join(receive, send)),
+            None,
+        ))
+    }
+}
+
+impl QuicConnection<()> {
+    /// Connects to the given server, checking its certificate according to `verification`. Only
+    /// the idle timeout of `timeouts` is used
+    pub async fn connect<A: ToSocketAddrs>(
+        addr: A,
+        domain: &str,
+        verification: &ServerVerification,
+        timeouts: Timeouts,
+    ) -> crate::Result<QuicConnection<Client>> {
+        let addr = lookup_host(addr)
+            .await?
+            .next()
+            .ok_or(io::Error::from(io::ErrorKind::AddrNotAvailable))?;
+        let local_addr: SocketAddr = if addr.is_ipv6() {
+            (Ipv6Addr::UNSPECIFIED, 0).into()
+        } else {
+            (Ipv4Addr::UNSPECIFIED, 0).into()
+        };
+
+        let mut tls = TlsConnector::new(verification)?.config().clone();
+        tls.alpn_protocols = vec![ALPN_PROTOCOL.to_vec()];
+        let crypto = QuicClientConfig::try_from(tls).map_err(tls_error)?;
+        let mut config = quinn::ClientConfig::new(Arc::new(crypto));
+        config.transport_config(transport_config(timeouts));
+
+        let endpoint = Endpoint::client(local_addr)?;
+        let connection = endpoint
+            .connect_with(config, addr, domain)
+            .map_err(|why| io::Error::new(io::ErrorKind::InvalidInput, why))?
+            .await
+            .map_err(connection_error)?;
+
+        let peer_cert = connection
+            .peer_identity()
+            .and_then(|identity| identity.downcast::<Vec<CertificateDer>>().ok())
+            .and_then(|chain| chain.first().map(|cert| cert.to_vec()));
+        let peer = verification.check(peer_cert.as_deref())?;
+
+        Ok(QuicConnection {
+            connection,
+            endpoint: Some(endpoint),
+            peer,
+            _0: PhantomData,
+        })
+    }
+}
+
+/// Accepts QUIC connections on a UDP socket, using the same certificate and timeouts as an
+/// [`Acceptor`]
+pub struct QuicAcceptor(Endpoint);
+
+impl QuicAcceptor {
+    /// Starts listening on `addr`. This has to be called from within a `tokio` runtime
+    pub fn bind(acceptor: &Acceptor, addr: SocketAddr) -> crate::Result<Self> {
+        let mut tls = acceptor.tls.config().clone();
+        tls.alpn_protocols = vec![ALPN_PROTOCOL.to_vec()];
+        let crypto = QuicServerConfig::try_from(tls).map_err(tls_error)?;
+        let mut config = quinn::ServerConfig::with_crypto(Arc::new(crypto));
+        config.transport_config(transport_config(acceptor.timeouts));
+
+        Ok(Self(Endpoint::server(config, addr)?))
+    }
+
+    /// The address the acceptor listens on, useful when binding to port 0
+    pub fn local_addr(&self) -> crate::Result<SocketAddr> {
+        Ok(self.0.local_addr()?)
+    }
+
+    /// Waits for the next client and finishes its handshake. Returns `None` once the acceptor
+    /// is closed
+    pub async fn accept(&self) -> Option<crate::Result<QuicConnection<Server>>> {
+        let incoming = self.0.accept().await?;
+
+        Some(
+            incoming
+                .await
+                .map(|connection| QuicConnection {
+                    connection,
+                    endpoint: None,
+                    peer: None,
+                    _0: PhantomData,
+                })
+                .map_err(connection_error),
+        )
+    }
+}
+
+#[cfg(test)]
+mod quic_tests {
+    use std::net::SocketAddr;
+
+    use super::{QuicAcceptor, QuicConnection};
+    use crate::{
+        Acceptor, Timeouts,
+        message::{CloseReason, ContainerState, Message},
+        tls::{Certificate, Identity, ServerVerification},
+    };
+
+    const IDENT_FILE: &[u8] = include_bytes!("../../test-keys/identity.p12");
+    const IDENT_PASS: &str = include_str!("../../test-keys/creds.asc");
+    const CUSTOM_CERT: &[u8] = include_bytes!("../../test-keys/cert.crt");
+
+    fn setup_test() -> crate::Result<(QuicAcceptor, ServerVerification)> {
+        let cert = Certificate::from_pem(CUSTOM_CERT)?;
+        let identity = Identity::from_pkcs12(IDENT_FILE, IDENT_PASS.trim())?;
+        let addr: SocketAddr = ([127, 0, 0, 1], 0).into();
+
+        Ok((
+            QuicAcceptor::bind(&Acceptor::new(identity)?, addr)?,
+            ServerVerification::CertificateAuthority(Some(cert)),
+        ))
+    }
+
+    #[tokio::test]
+    async fn request_per_stream() -> crate::Result<()> {
+        let (acceptor, verification) = setup_test()?;
+
+        let addr = acceptor.local_addr()?;
+        let handle = tokio::spawn(async move {
+            let conn =
+                QuicConnection::connect(addr, "localhost", &verification, Timeouts::default())
+                    .await?;
+
+            // the replies arrive on the streams of their requests, whatever order they're sent in
+            let mut first = conn.open_request().await?;
+            first
+                .send(&Message::StartMessage {
+                    resource_name: "first".to_owned(),
+                })
+                .await?;
+            let mut second = conn.open_request().await?;
+            second
+                .send(&Message::StartMessage {
+                    resource_name: "second".to_owned(),
+                })
+                .await?;
+
+            let replies = (second.receive().await?, first.receive().await?);
+            conn.close(CloseReason::Normal, None).await?;
+
+            crate::Result::Ok(replies)
+        });
+
+        let conn = acceptor.accept().await.expect("acceptor closed")?;
+        let mut streams = vec![];
+        for _ in 0..2 {
+            let mut stream = conn.accept_request().await?;
+            let Message::StartMessage { resource_name } = stream.receive().await? else {
+                panic!("received different message type");
+            };
+            let state = match resource_name.as_str() {
+                "first" => ContainerState::Running,
+                _ => ContainerState::Created,
+            };
+            streams.push((stream, state));
+        }
+        for (mut stream, state) in streams.into_iter().rev() {
+            stream
+                .send(&Message::StateMessage { state, ready: None })
+                .await?;
+        }
+
+        assert!(matches!(
+            conn.accept_request().await,
+            Err(crate::Error::Closed {
+                reason: CloseReason::Normal,
+                text: None
+            })
+        ));
+
+        let replies = handle.await.expect("associated task panicked")?;
+        assert!(matches!(
+            replies,
+            (
+                Message::StateMessage {
+                    state: ContainerState::Created,
+                    ..
+                },
+                Message::StateMessage {
+                    state: ContainerState::Running,
+                    ..
+                }
+            )
+        ));
+
+        Ok(())
+    }
+}
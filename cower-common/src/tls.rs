@@ -0,0 +1,347 @@
+//! TLS primitives that don't depend on the TLS backend in use
+//!
+//! `cower-common` can be built either with `native-tls` (the default) or with `rustls`, selected
+//! through the cargo features of the same name. The public API is the same with both backends. If
+//! both features are enabled, `rustls` is used.
+
+#[cfg(not(any(feature = "native-tls", feature = "rustls")))]
+compile_error!("either the `native-tls` or the `rustls` feature has to be enabled");
+#[cfg(all(feature = "tokio", feature = "rustls", not(feature = "tokio-rustls")))]
+compile_error!("async connections over `rustls` need the `tokio-rustls` feature");
+#[cfg(all(
+    feature = "tokio",
+    not(feature = "rustls"),
+    not(feature = "tokio-native-tls")
+))]
+compile_error!("async connections over `native-tls` need the `tokio-native-tls` feature");
+
+#[cfg(all(feature = "native-tls", not(feature = "rustls")))]
+mod native;
+#[cfg(all(feature = "native-tls", not(feature = "rustls")))]
+use self::native as backend;
+
+#[cfg(feature = "rustls")]
+mod rustls;
+#[cfg(feature = "rustls")]
+use self::rustls as backend;
+
+use std::{
+    fmt,
+    io::{self, Read, Write},
+    str::FromStr,
+};
+
+use sha2::{Digest, Sha256};
+
+pub(crate) use backend::{TlsAcceptor, TlsConnector};
+
+/// A boxed error of the TLS backend in use
+type BackendError = Box<dyn std::error::Error + Send + Sync>;
+
+/// Error returned by the TLS library. It's the same type with both backends and shows the
+/// backend's message
+#[derive(Debug)]
+pub struct Error(BackendError);
+
+impl Error {
+    pub(crate) fn new(why: impl Into<BackendError>) -> Self {
+        Self(why.into())
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        self.0.source()
+    }
+}
+
+/// Error returned when the TLS handshake fails. It's the same type with both backends and shows
+/// the backend's message
+#[derive(Debug)]
+pub struct HandshakeError(BackendError);
+
+impl HandshakeError {
+    pub(crate) fn new(why: impl Into<BackendError>) -> Self {
+        Self(why.into())
+    }
+}
+
+impl fmt::Display for HandshakeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+impl std::error::Error for HandshakeError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        self.0.source()
+    }
+}
+
+/// Anything a connection can be carried over
+pub(crate) trait Stream: Read + Write + Send {
+    /// Tells the peer that nothing more will be sent, e.g. with a TLS `close_notify` alert
+    fn close(&mut self) -> io::Result<()>;
+
+    /// Lets transports with their own framing know how long packets can get
+    fn set_max_packet_length(&mut self, _length: usize) {}
+}
+
+/// A stream returned by a connector, together with the DER-encoded certificate of the server
+pub(crate) type ClientStream<S> = (Box<S>, Option<Vec<u8>>);
+
+/// Anything an async connection can be carried over
+#[cfg(feature = "tokio")]
+pub(crate) trait AsyncStream:
+    tokio::io::AsyncRead + tokio::io::AsyncWrite + Send + Unpin
+{
+}
+#[cfg(feature = "tokio")]
+impl<T: tokio::io::AsyncRead + tokio::io::AsyncWrite + Send + Unpin> AsyncStream for T {}
+
+/// An X.509 certificate
+#[derive(Clone)]
+pub struct Certificate(pub(crate) backend::Certificate);
+
+impl Certificate {
+    /// Parses a PEM-encoded certificate
+    pub fn from_pem(pem: &[u8]) -> crate::Result<Self> {
+        backend::Certificate::from_pem(pem).map(Self)
+    }
+
+    /// Parses a DER-encoded certificate
+    pub fn from_der(der: &[u8]) -> crate::Result<Self> {
+        backend::Certificate::from_der(der).map(Self)
+    }
+}
+
+/// A certificate chain together with its private key, used by servers to prove their identity
+#[derive(Clone)]
+pub struct Identity(pub(crate) backend::Identity);
+
+impl Identity {
+    /// Parses a DER-encoded PKCS#12 archive protected by `password`
+    pub fn from_pkcs12(der: &[u8], password: &str) -> crate::Result<Self> {
+        backend::Identity::from_pkcs12(der, password).map(Self)
+    }
+
+    /// Parses a PEM-encoded certificate chain (leaf certificate first) and a PEM-encoded PKCS#8
+    /// private key
+    pub fn from_pem(cert_chain: &[u8], key: &[u8]) -> crate::Result<Self> {
+        backend::Identity::from_pem(cert_chain, key).map(Self)
+    }
+}
+
+/// A SHA-256 fingerprint of a certificate or of its public key
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct Fingerprint(pub [u8; 32]);
+
+impl Fingerprint {
+    /// Computes the fingerprint of `data`
+    pub fn of(data: &[u8]) -> Self {
+        Self(Sha256::digest(data).into())
+    }
+}
+
+/// Formats the fingerprint as lowercase hex
+impl fmt::Display for Fingerprint {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.0.iter().try_for_each(|byte| write!(f, "{byte:02x}"))
+    }
+}
+
+/// Parses a hex-encoded fingerprint. Bytes may be separated by colons, like in the output of
+/// `openssl x509 -fingerprint -sha256`
+impl FromStr for Fingerprint {
+    type Err = crate::Error;
+
+    fn from_str(s: &str) -> crate::Result<Self> {
+        let hex: Vec<u8> = s.bytes().filter(|c| *c != b':').collect();
+
+        let mut fingerprint = [0; 32];
+        if hex.len() != fingerprint.len() * 2 {
+            return Err(crate::Error::InvalidFingerprint);
+        }
+
+        for (byte, digits) in fingerprint.iter_mut().zip(hex.chunks_exact(2)) {
+            let digits = std::str::from_utf8(digits)?;
+            *byte = u8::from_str_radix(digits, 16).map_err(|_| crate::Error::InvalidFingerprint)?;
+        }
+
+        Ok(Self(fingerprint))
+    }
+}
+
+/// Fingerprints identifying the certificate a server presented
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct PeerIdentity {
+    /// Fingerprint of the whole DER-encoded certificate
+    pub certificate: Fingerprint,
+    /// Fingerprint of the DER-encoded SubjectPublicKeyInfo. Unlike the certificate fingerprint,
+    /// this one stays the same when the certificate is renewed with the same key
+    pub spki: Fingerprint,
+}
+
+impl PeerIdentity {
+    /// Computes the fingerprints of a DER-encoded certificate
+    pub fn from_der(cert: &[u8]) -> crate::Result<Self> {
+        let spki = spki_der(cert).ok_or(crate::Error::InvalidKeyMaterial)?;
+
+        Ok(Self {
+            certificate: Fingerprint::of(cert),
+            spki: Fingerprint::of(spki),
+        })
+    }
+}
+
+/// The fingerprint a server's certificate has to match
+#[derive(Clone, Copy, Debug)]
+pub enum Pin {
+    /// Fingerprint of the whole certificate
+    Certificate(Fingerprint),
+    /// Fingerprint of the certificate's public key (SubjectPublicKeyInfo)
+    Spki(Fingerprint),
+}
+
+/// How a client checks the certificate of the server it connects to
+#[derive(Clone)]
+pub enum ServerVerification {
+    /// Regular validation against the root certificates of the TLS backend, with an optional
+    /// extra root certificate
+    CertificateAuthority(Option<Certificate>),
+    /// The certificate has to match the pin. CA validation is skipped, so self-signed
+    /// certificates work, but any mismatch fails the connection
+    Pinned(Pin),
+    /// Any certificate is accepted. Only use this to learn the identity of a server (see
+    /// [`crate::Connection::peer_identity`]) and pin it for later connections, like SSH does with
+    /// unknown hosts
+    TrustOnFirstUse,
+}
+
+impl ServerVerification {
+    /// Checks the certificate the server presented. Returns the server's identity if the
+    /// certificate is known
+    pub(crate) fn check(&self, peer_cert: Option<&[u8]>) -> crate::Result<Option<PeerIdentity>> {
+        let peer = peer_cert.map(PeerIdentity::from_der).transpose()?;
+
+        match (self, peer) {
+            (Self::Pinned(Pin::Certificate(pin)), Some(peer)) if peer.certificate != *pin => {
+                Err(crate::Error::FingerprintMismatch(peer.certificate))
+            }
+            (Self::Pinned(Pin::Spki(pin)), Some(peer)) if peer.spki != *pin => {
+                Err(crate::Error::FingerprintMismatch(peer.spki))
+            }
+            (Self::Pinned(_), None) => Err(crate::Error::InvalidKeyMaterial),
+
+            (_, peer) => Ok(peer),
+        }
+    }
+}
+
+/// The tag, the whole element, the element's contents and the rest of the buffer
+type DerElement<'a> = (u8, &'a [u8], &'a [u8], &'a [u8]);
+
+/// Splits the first DER element off `buf`
+fn der_element(buf: &[u8]) -> Option<DerElement<'_>> {
+    let (&tag, rest) = buf.split_first()?;
+    let (&length, rest) = rest.split_first()?;
+
+    let (length, rest) = if length < 0x80 {
+        (usize::from(length), rest)
+    } else {
+        // long form - the low bits are the number of bytes the length is encoded in
+        let length_size = usize::from(length & 0x7f);
+        if length_size == 0 || length_size > size_of::<usize>() || rest.len() < length_size {
+            return None;
+        }
+
+        let (length, rest) = rest.split_at(length_size);
+        let length = length
+            .iter()
+            .fold(0, |length, byte| (length << 8) | usize::from(*byte));
+
+        (length, rest)
+    };
+
+    if rest.len() < length {
+        return None;
+    }
+
+    let header_length = buf.len() - rest.len();
+    let (contents, rest) = rest.split_at(length);
+
+    Some((tag, buf.get(..header_length + length)?, contents, rest))
+}
+
+/// Finds the DER-encoded SubjectPublicKeyInfo in a DER-encoded X.509 certificate
+fn spki_der(cert: &[u8]) -> Option<&[u8]> {
+    const SEQUENCE: u8 = 0x30;
+    const EXPLICIT_VERSION: u8 = 0xa0;
+
+    let (SEQUENCE, _, cert, _) = der_element(cert)? else {
+        return None;
+    };
+    let (SEQUENCE, _, tbs_cert, _) = der_element(cert)? else {
+        return None;
+    };
+
+    let mut fields = tbs_cert;
+    if let (EXPLICIT_VERSION, _, _, rest) = der_element(fields)? {
+        fields = rest;
+    }
+
+    // serial number, signature algorithm, issuer, validity, subject
+    for _ in 0..5 {
+        (_, _, _, fields) = der_element(fields)?;
+    }
+
+    let (SEQUENCE, spki, _, _) = der_element(fields)? else {
+        return None;
+    };
+
+    Some(spki)
+}
+
+#[cfg(test)]
+mod pin_tests {
+    use super::{Fingerprint, PeerIdentity};
+
+    const CERT: &[u8] = include_bytes!("../../test-keys/cert.der");
+    const PUBLIC_KEY: &[u8] = include_bytes!("../../test-keys/public.der");
+
+    #[test]
+    fn spki_matches_public_key() -> crate::Result<()> {
+        let identity = PeerIdentity::from_der(CERT)?;
+
+        assert_eq!(identity.certificate, Fingerprint::of(CERT));
+        assert_eq!(identity.spki, Fingerprint::of(PUBLIC_KEY));
+        assert_ne!(identity.spki, identity.certificate);
+
+        Ok(())
+    }
+
+    #[test]
+    fn fingerprint_roundtrip() -> crate::Result<()> {
+        let fingerprint = Fingerprint::of(b"cower");
+
+        assert_eq!(fingerprint.to_string().parse::<Fingerprint>()?, fingerprint);
+
+        let with_colons = fingerprint
+            .0
+            .iter()
+            .map(|byte| format!("{byte:02X}"))
+            .collect::<Vec<_>>()
+            .join(":");
+        assert_eq!(with_colons.parse::<Fingerprint>()?, fingerprint);
+
+        assert!("abcd".parse::<Fingerprint>().is_err());
+
+        Ok(())
+    }
+}
@@ -0,0 +1,154 @@
+//! TLS backend built on `native-tls`, which uses OpenSSL on Linux, Security.framework on macOS and
+//! SChannel on Windows
+
+use std::{io, net::TcpStream};
+
+#[cfg(feature = "tokio-native-tls")]
+use super::AsyncStream;
+use super::{ClientStream, Error, HandshakeError, ServerVerification, Stream};
+
+fn handshake_error(why: native_tls::HandshakeError<TcpStream>) -> crate::Error {
+    match why {
+        native_tls::HandshakeError::Failure(why) => HandshakeError::new(why).into(),
+        // only happens when the socket times out
+        native_tls::HandshakeError::WouldBlock(_) => {
+            HandshakeError::new(io::Error::from(io::ErrorKind::WouldBlock)).into()
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct Certificate(native_tls::Certificate);
+
+impl Certificate {
+    pub fn from_pem(pem: &[u8]) -> crate::Result<Self> {
+        Ok(Self(
+            native_tls::Certificate::from_pem(pem).map_err(Error::new)?,
+        ))
+    }
+
+    pub fn from_der(der: &[u8]) -> crate::Result<Self> {
+        Ok(Self(
+            native_tls::Certificate::from_der(der).map_err(Error::new)?,
+        ))
+    }
+}
+
+#[derive(Clone)]
+pub struct Identity(native_tls::Identity);
+
+impl Identity {
+    pub fn from_pkcs12(der: &[u8], password: &str) -> crate::Result<Self> {
+        Ok(Self(
+            native_tls::Identity::from_pkcs12(der, password).map_err(Error::new)?,
+        ))
+    }
+
+    pub fn from_pem(cert_chain: &[u8], key: &[u8]) -> crate::Result<Self> {
+        Ok(Self(
+            native_tls::Identity::from_pkcs8(cert_chain, key).map_err(Error::new)?,
+        ))
+    }
+}
+
+impl Stream for native_tls::TlsStream<TcpStream> {
+    fn close(&mut self) -> io::Result<()> {
+        self.shutdown()
+    }
+}
+
+#[derive(Clone)]
+pub struct TlsConnector(native_tls::TlsConnector);
+
+impl TlsConnector {
+    pub fn new(verification: &ServerVerification) -> crate::Result<Self> {
+        let mut connector = native_tls::TlsConnector::builder();
+        match verification {
+            ServerVerification::CertificateAuthority(Some(cert)) => {
+                connector.add_root_certificate(cert.0.0.clone());
+            }
+            ServerVerification::CertificateAuthority(None) => {}
+            // the certificate is checked by the caller after the handshake
+            ServerVerification::Pinned(_) | ServerVerification::TrustOnFirstUse => {
+                connector
+                    .danger_accept_invalid_certs(true)
+                    .danger_accept_invalid_hostnames(true);
+            }
+        }
+
+        Ok(Self(connector.build().map_err(Error::new)?))
+    }
+
+    /// Connects over `stream`, returning the TLS stream and the DER-encoded certificate of the
+    /// server
+    pub fn connect(
+        &self,
+        domain: &str,
+        stream: TcpStream,
+    ) -> crate::Result<ClientStream<dyn Stream>> {
+        let tls_stream = self.0.connect(domain, stream).map_err(handshake_error)?;
+        let peer_cert = tls_stream
+            .peer_certificate()
+            .map_err(Error::new)?
+            .map(|cert| cert.to_der())
+            .transpose()
+            .map_err(Error::new)?;
+
+        Ok((Box::new(tls_stream), peer_cert))
+    }
+
+    #[cfg(feature = "tokio-native-tls")]
+    pub async fn connect_async(
+        &self,
+        domain: &str,
+        stream: tokio::net::TcpStream,
+    ) -> crate::Result<ClientStream<dyn AsyncStream>> {
+        let connector = tokio_native_tls::TlsConnector::from(self.0.clone());
+        let tls_stream = connector
+            .connect(domain, stream)
+            .await
+            .map_err(HandshakeError::new)?;
+        let peer_cert = tls_stream
+            .get_ref()
+            .peer_certificate()
+            .map_err(Error::new)?
+            .map(|cert| cert.to_der())
+            .transpose()
+            .map_err(Error::new)?;
+
+        Ok((Box::new(tls_stream), peer_cert))
+    }
+}
+
+#[derive(Clone)]
+pub struct TlsAcceptor(native_tls::TlsAcceptor);
+
+impl TlsAcceptor {
+    pub fn new(identity: super::Identity) -> crate::Result<Self> {
+        let acceptor = native_tls::TlsAcceptor::builder(identity.0.0)
+            // remove this if this causes problems for older platforms
+            .min_protocol_version(Some(native_tls::Protocol::Tlsv12))
+            .build()
+            .map_err(Error::new)?;
+
+        Ok(Self(acceptor))
+    }
+
+    pub fn accept(&self, stream: TcpStream) -> crate::Result<Box<dyn Stream>> {
+        let tls_stream = self.0.accept(stream).map_err(handshake_error)?;
+
+        Ok(Box::new(tls_stream))
+    }
+
+    #[cfg(feature = "tokio-native-tls")]
+    pub async fn accept_async(
+        &self,
+        stream: tokio::net::TcpStream,
+    ) -> crate::Result<Box<dyn AsyncStream>> {
+        let acceptor = tokio_native_tls::TlsAcceptor::from(self.0.clone());
+
+        let tls_stream = acceptor.accept(stream).await.map_err(HandshakeError::new)?;
+
+        Ok(Box::new(tls_stream))
+    }
+}
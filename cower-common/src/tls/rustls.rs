@@ -0,0 +1,282 @@
+//! TLS backend built on `rustls`, which doesn't depend on any system libraries
+
+use std::{
+    io::{self, Write},
+    net::TcpStream,
+    sync::Arc,
+};
+
+use rustls::{
+    ClientConfig, ClientConnection, DigitallySignedStruct, Error, RootCertStore, ServerConfig,
+    ServerConnection, SignatureScheme, StreamOwned,
+    client::danger::{HandshakeSignatureValid, ServerCertVerified, ServerCertVerifier},
+    crypto::{CryptoProvider, verify_tls12_signature, verify_tls13_signature},
+    pki_types::{CertificateDer, PrivateKeyDer, ServerName, UnixTime, pem::PemObject},
+};
+
+#[cfg(feature = "tokio-rustls")]
+use super::AsyncStream;
+use super::{ClientStream, HandshakeError, ServerVerification, Stream};
+
+#[derive(Clone)]
+pub struct Certificate(CertificateDer<'static>);
+
+impl Certificate {
+    pub fn from_pem(pem: &[u8]) -> crate::Result<Self> {
+        let cert =
+            CertificateDer::from_pem_slice(pem).map_err(|_| crate::Error::InvalidKeyMaterial)?;
+
+        Ok(Self(cert))
+    }
+
+    pub fn from_der(der: &[u8]) -> crate::Result<Self> {
+        Ok(Self(CertificateDer::from(der.to_vec())))
+    }
+}
+
+pub struct Identity {
+    cert_chain: Vec<CertificateDer<'static>>,
+    key: PrivateKeyDer<'static>,
+}
+
+// `PrivateKeyDer` doesn't implement `Clone` to make copying keys around explicit
+impl Clone for Identity {
+    fn clone(&self) -> Self {
+        Self {
+            cert_chain: self.cert_chain.clone(),
+            key: self.key.clone_key(),
+        }
+    }
+}
+
+impl Identity {
+    pub fn from_pkcs12(der: &[u8], password: &str) -> crate::Result<Self> {
+        use p12_keystore::{KeyStore, Pkcs12ImportPolicy};
+
+        let keystore = KeyStore::from_pkcs12(der, password, Pkcs12ImportPolicy::Strict)
+            .map_err(|_| crate::Error::InvalidKeyMaterial)?;
+        let (_, chain) = keystore
+            .private_key_chain()
+            .ok_or(crate::Error::InvalidKeyMaterial)?;
+
+        let key = PrivateKeyDer::try_from(chain.key().as_der().to_vec())
+            .map_err(|_| crate::Error::InvalidKeyMaterial)?;
+        let cert_chain = chain
+            .certs()
+            .iter()
+            .map(|cert| CertificateDer::from(cert.as_der().to_vec()))
+            .collect();
+
+        Ok(Self { cert_chain, key })
+    }
+
+    pub fn from_pem(cert_chain: &[u8], key: &[u8]) -> crate::Result<Self> {
+        let cert_chain = CertificateDer::pem_slice_iter(cert_chain)
+            .collect::<Result<_, _>>()
+            .map_err(|_| crate::Error::InvalidKeyMaterial)?;
+        let key =
+            PrivateKeyDer::from_pem_slice(key).map_err(|_| crate::Error::InvalidKeyMaterial)?;
+
+        Ok(Self { cert_chain, key })
+    }
+}
+
+fn provider() -> Arc<CryptoProvider> {
+    Arc::new(rustls::crypto::ring::default_provider())
+}
+
+/// Drives the handshake to completion, so that handshake failures are reported when connecting
+/// like with the other backend
+fn complete_handshake<C, S>(stream: &mut StreamOwned<C, TcpStream>) -> crate::Result<()>
+where
+    C: std::ops::DerefMut<Target = rustls::ConnectionCommon<S>>,
+    S: rustls::SideData,
+{
+    while stream.conn.is_handshaking() {
+        stream
+            .conn
+            .complete_io(&mut stream.sock)
+            .map_err(HandshakeError::new)?;
+    }
+
+    Ok(())
+}
+
+impl<C, S> Stream for StreamOwned<C, TcpStream>
+where
+    C: std::ops::DerefMut<Target = rustls::ConnectionCommon<S>> + Send,
+    S: rustls::SideData,
+{
+    fn close(&mut self) -> io::Result<()> {
+        self.conn.send_close_notify();
+        // writes the alert out
+        self.flush()
+    }
+}
+
+/// Accepts any server certificate, leaving the checks to the caller. The handshake signatures are
+/// still verified, so the server has to own the key of the certificate it presents
+#[derive(Debug)]
+struct AcceptAnyCertificate(Arc<CryptoProvider>);
+
+impl ServerCertVerifier for AcceptAnyCertificate {
+    fn verify_server_cert(
+        &self,
+        _end_entity: &CertificateDer<'_>,
+        _intermediates: &[CertificateDer<'_>],
+        _server_name: &ServerName<'_>,
+        _ocsp_response: &[u8],
+        _now: UnixTime,
+    ) -> Result<ServerCertVerified, Error> {
+        Ok(ServerCertVerified::assertion())
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, Error> {
+        verify_tls12_signature(
+            message,
+            cert,
+            dss,
+            &self.0.signature_verification_algorithms,
+        )
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, Error> {
+        verify_tls13_signature(
+            message,
+            cert,
+            dss,
+            &self.0.signature_verification_algorithms,
+        )
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        self.0.signature_verification_algorithms.supported_schemes()
+    }
+}
+
+#[derive(Clone)]
+pub struct TlsConnector(Arc<ClientConfig>);
+
+impl TlsConnector {
+    pub fn new(verification: &ServerVerification) -> crate::Result<Self> {
+        let builder = ClientConfig::builder_with_provider(provider())
+            .with_safe_default_protocol_versions()
+            .map_err(super::Error::new)?;
+
+        let config = match verification {
+            ServerVerification::CertificateAuthority(custom_cert) => {
+                let mut roots =
+                    RootCertStore::from_iter(webpki_roots::TLS_SERVER_ROOTS.iter().cloned());
+                if let Some(cert) = custom_cert {
+                    roots.add(cert.0.0.clone()).map_err(super::Error::new)?;
+                }
+
+                builder.with_root_certificates(roots)
+            }
+            // the certificate is checked by the caller after the handshake
+            ServerVerification::Pinned(_) | ServerVerification::TrustOnFirstUse => builder
+                .dangerous()
+                .with_custom_certificate_verifier(Arc::new(AcceptAnyCertificate(provider()))),
+        };
+
+        Ok(Self(Arc::new(config.with_no_client_auth())))
+    }
+
+    #[cfg(feature = "quic")]
+    pub fn config(&self) -> &ClientConfig {
+        &self.0
+    }
+
+    fn server_name(domain: &str) -> crate::Result<ServerName<'static>> {
+        ServerName::try_from(domain.to_owned()).map_err(|_| {
+            super::Error::new(Error::General(format!("invalid domain name {domain}"))).into()
+        })
+    }
+
+    /// Connects over `stream`, returning the TLS stream and the DER-encoded certificate of the
+    /// server
+    pub fn connect(
+        &self,
+        domain: &str,
+        stream: TcpStream,
+    ) -> crate::Result<ClientStream<dyn Stream>> {
+        let conn = ClientConnection::new(self.0.clone(), Self::server_name(domain)?)
+            .map_err(super::Error::new)?;
+        let mut tls_stream = StreamOwned::new(conn, stream);
+        complete_handshake(&mut tls_stream)?;
+        let peer_cert = leaf_certificate(tls_stream.conn.peer_certificates());
+
+        Ok((Box::new(tls_stream), peer_cert))
+    }
+
+    #[cfg(feature = "tokio-rustls")]
+    pub async fn connect_async(
+        &self,
+        domain: &str,
+        stream: tokio::net::TcpStream,
+    ) -> crate::Result<ClientStream<dyn AsyncStream>> {
+        let connector = tokio_rustls::TlsConnector::from(self.0.clone());
+        let tls_stream = connector
+            .connect(Self::server_name(domain)?, stream)
+            .await
+            .map_err(HandshakeError::new)?;
+        let peer_cert = leaf_certificate(tls_stream.get_ref().1.peer_certificates());
+
+        Ok((Box::new(tls_stream), peer_cert))
+    }
+}
+
+fn leaf_certificate(chain: Option<&[CertificateDer<'_>]>) -> Option<Vec<u8>> {
+    chain?.first().map(|cert| cert.to_vec())
+}
+
+#[derive(Clone)]
+pub struct TlsAcceptor(Arc<ServerConfig>);
+
+impl TlsAcceptor {
+    pub fn new(identity: super::Identity) -> crate::Result<Self> {
+        // rustls only supports TLS 1.2 and newer, so there's no need to set the minimum version
+        let config = ServerConfig::builder_with_provider(provider())
+            .with_safe_default_protocol_versions()
+            .map_err(super::Error::new)?
+            .with_no_client_auth()
+            .with_single_cert(identity.0.cert_chain, identity.0.key)
+            .map_err(super::Error::new)?;
+
+        Ok(Self(Arc::new(config)))
+    }
+
+    #[cfg(feature = "quic")]
+    pub fn config(&self) -> &ServerConfig {
+        &self.0
+    }
+
+    pub fn accept(&self, stream: TcpStream) -> crate::Result<Box<dyn Stream>> {
+        let conn = ServerConnection::new(self.0.clone()).map_err(super::Error::new)?;
+        let mut tls_stream = StreamOwned::new(conn, stream);
+        complete_handshake(&mut tls_stream)?;
+
+        Ok(Box::new(tls_stream))
+    }
+
+    #[cfg(feature = "tokio-rustls")]
+    pub async fn accept_async(
+        &self,
+        stream: tokio::net::TcpStream,
+    ) -> crate::Result<Box<dyn AsyncStream>> {
+        let acceptor = tokio_rustls::TlsAcceptor::from(self.0.clone());
+        let tls_stream = acceptor.accept(stream).await.map_err(HandshakeError::new)?;
+
+        Ok(Box::new(tls_stream))
+    }
+}
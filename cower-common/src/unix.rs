@@ -0,0 +1,160 @@
+//! Connections over Unix domain sockets, for peers on the same host. These connections aren't
+//! encrypted, as they never leave the host. Instead of a certificate, the peer is identified by
+//! the credentials the kernel reports for it.
+
+use std::{
+    io,
+    net::Shutdown,
+    os::{fd::AsFd, unix::net::UnixStream},
+    path::Path,
+};
+
+use crate::{Client, Connection, Server, Socket, tls::Stream};
+
+/// The user and group of the process on the other end of a Unix socket, as reported by the
+/// kernel. Unlike anything the peer sends, these can't be forged
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct PeerCredentials {
+    /// Effective user ID of the process
+    pub uid: u32,
+    /// Effective primary group ID of the process. Supplementary groups aren't reported
+    pub gid: u32,
+    /// ID of the process, on platforms that report it
+    pub pid: Option<i32>,
+}
+
+impl PeerCredentials {
+    /// Asks the kernel who is connected to `stream`
+    pub fn of(stream: &UnixStream) -> crate::Result<Self> {
+        Self::of_socket(stream)
+    }
+
+    /// Same as [`PeerCredentials::of`], for Unix sockets of other libraries
+    pub(crate) fn of_socket(socket: &impl AsFd) -> crate::Result<Self> {
+        query_credentials(socket).map_err(|errno| crate::Error::IOFailure(errno.into()))
+    }
+}
+
+#[cfg(any(target_os = "linux", target_os = "android"))]
+fn query_credentials(stream: &impl AsFd) -> nix::Result<PeerCredentials> {
+    use nix::sys::socket::{getsockopt, sockopt};
+
+    let credentials = getsockopt(stream, sockopt::PeerCredentials)?;
+
+    Ok(PeerCredentials {
+        uid: credentials.uid(),
+        gid: credentials.gid(),
+        pid: Some(credentials.pid()),
+    })
+}
+
+#[cfg(any(
+    target_os = "macos",
+    target_os = "ios",
+    target_os = "freebsd",
+    target_os = "openbsd",
+    target_os = "netbsd",
+    target_os = "dragonfly",
+))]
+fn query_credentials(stream: &impl AsFd) -> nix::Result<PeerCredentials> {
+    let (uid, gid) = nix::unistd::getpeereid(stream)?;
+
+    Ok(PeerCredentials {
+        uid: uid.as_raw(),
+        gid: gid.as_raw(),
+        pid: None,
+    })
+}
+
+#[cfg(not(any(
+    target_os = "linux",
+    target_os = "android",
+    target_os = "macos",
+    target_os = "ios",
+    target_os = "freebsd",
+    target_os = "openbsd",
+    target_os = "netbsd",
+    target_os = "dragonfly",
+)))]
+fn query_credentials(_stream: &impl AsFd) -> nix::Result<PeerCredentials> {
+    Err(nix::errno::Errno::ENOSYS)
+}
+
+impl Stream for UnixStream {
+    fn close(&mut self) -> io::Result<()> {
+        self.shutdown(Shutdown::Write)
+    }
+}
+
+impl<T> Connection<T> {
+    /// Credentials of the process on the other end. This is only available on the accepting end
+    /// of Unix socket connections
+    pub fn peer_credentials(&self) -> Option<&PeerCredentials> {
+        self.credentials.as_ref()
+    }
+}
+
+impl Connection<()> {
+    /// Connects to the Unix socket at `path`
+    pub fn connect_unix<P: AsRef<Path>>(path: P) -> crate::Result<Connection<Client>> {
+        let stream = UnixStream::connect(path)?;
+        let socket = Socket::Unix(stream.try_clone()?);
+
+        Ok(Connection::new(Box::new(stream), socket, None))
+    }
+
+    /// Wraps a connection accepted by a [`std::os::unix::net::UnixListener`]. Anyone who can
+    /// open the socket file can connect, so check [`Connection::peer_credentials`] before
+    /// serving the peer
+    pub fn accept_unix(stream: UnixStream) -> crate::Result<Connection<Server>> {
+        let credentials = PeerCredentials::of(&stream)?;
+        let socket = Socket::Unix(stream.try_clone()?);
+
+        Ok(Connection {
+            credentials: Some(credentials),
+            ..Connection::new(Box::new(stream), socket, None)
+        })
+    }
+}
+
+#[cfg(test)]
+mod unix_tests {
+    use std::{env, fs, os::unix::net::UnixListener, process, thread};
+
+    use crate::{Connection, message::Message};
+
+    #[test]
+    fn unix_connection() -> crate::Result<()> {
+        let path = env::temp_dir().join(format!("cower-{}.sock", process::id()));
+        _ = fs::remove_file(&path);
+        let listener = UnixListener::bind(&path)?;
+
+        let client_path = path.clone();
+        let handle = thread::spawn(move || {
+            let mut conn = Connection::connect_unix(client_path)?;
+            conn.send(&Message::StartMessage {
+                resource_name: "my_resource".to_owned(),
+            })?;
+
+            conn.receive()
+        });
+
+        let (stream, _) = listener.accept()?;
+        let mut conn = Connection::accept_unix(stream)?;
+
+        // both ends are this process
+        let credentials = conn.peer_credentials().expect("no credentials");
+        assert_eq!(credentials.uid, nix::unistd::geteuid().as_raw());
+        assert_eq!(credentials.gid, nix::unistd::getegid().as_raw());
+
+        assert!(matches!(conn.receive()?, Message::StartMessage { .. }));
+        conn.send(&Message::LogsEndMessage)?;
+
+        let reply = handle.join().expect("associated thread panicked")?;
+        assert!(matches!(reply, Message::LogsEndMessage));
+
+        fs::remove_file(&path)?;
+
+        Ok(())
+    }
+}
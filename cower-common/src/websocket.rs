@@ -0,0 +1,492 @@
+//! Connections carried over WebSockets, for networks that only let HTTPS through. Enable the
+//! `websocket` feature to use them.
+//!
+//! The WebSocket runs over the same TLS setup as regular connections, so certificates and pinning
+//! work the same way. Every packet is sent in its own binary WebSocket message.
+
+use std::{
+    io::{self, Read, Write},
+    net::TcpStream,
+};
+#[cfg(feature = "tokio-websocket")]
+use std::{
+    pin::Pin,
+    task::{Context, Poll, ready},
+};
+
+use tungstenite::{
+    Bytes, HandshakeError, WebSocket,
+    client::IntoClientRequest,
+    error::UrlError,
+    handshake::{
+        HandshakeRole,
+        server::{ErrorResponse, Request, Response},
+    },
+    http::StatusCode,
+    protocol::WebSocketConfig,
+};
+
+use crate::{
+    Acceptor, Client, Connection, Server, Socket, Timeouts, connect_tcp, handshake,
+    message::{DEFAULT_MAX_PAYLOAD_LENGTH, EXTENDED_HEADER_SIZE},
+    tls::{ServerVerification, Stream, TlsConnector},
+};
+#[cfg(feature = "tokio-websocket")]
+use crate::{
+    asynchronous::{AsyncAcceptor, AsyncConnection, limit},
+    tls::AsyncStream,
+};
+
+const DEFAULT_PORT: u16 = 443;
+
+/// Length of the longest packet with a payload of `payload_length` bytes
+fn max_packet_length(payload_length: u32) -> usize {
+    usize::try_from(payload_length)
+        .unwrap_or(usize::MAX)
+        .saturating_add(EXTENDED_HEADER_SIZE.into())
+}
+
+fn config() -> WebSocketConfig {
+    let mut config = WebSocketConfig::default();
+    config.max_message_size = Some(max_packet_length(DEFAULT_MAX_PAYLOAD_LENGTH));
+    config.max_frame_size = config.max_message_size;
+
+    config
+}
+
+fn handshake_error<R: HandshakeRole>(error: HandshakeError<R>) -> crate::Error {
+    match error {
+        HandshakeError::Failure(tungstenite::Error::Io(why)) => why.into(),
+        HandshakeError::Failure(why) => why.into(),
+        // the socket timed out in the middle of the handshake
+        HandshakeError::Interrupted(_) => io::Error::from(io::ErrorKind::TimedOut).into(),
+    }
+}
+
+fn io_error(error: tungstenite::Error) -> io::Error {
+    match error {
+        tungstenite::Error::Io(why) => why,
+        tungstenite::Error::ConnectionClosed | tungstenite::Error::AlreadyClosed => {
+            io::ErrorKind::BrokenPipe.into()
+        }
+        why => io::Error::other(why),
+    }
+}
+
+/// Whether `path` is `prefix` or lies under it
+fn is_under(path: &str, prefix: &str) -> bool {
+    path.strip_prefix(prefix)
+        .is_some_and(|rest| rest.is_empty() || rest.starts_with('/') || prefix.ends_with('/'))
+}
+
+/// Turns the messages of a WebSocket back into the byte stream a [`Connection`] expects
+struct WebSocketStream {
+    websocket: WebSocket<Box<dyn Stream>>,
+    /// What's left of the last binary message received
+    incoming: Bytes,
+}
+
+impl WebSocketStream {
+    fn new(websocket: WebSocket<Box<dyn Stream>>) -> Self {
+        Self {
+            websocket,
+            incoming: Bytes::new(),
+        }
+    }
+}
+
+impl Read for WebSocketStream {
+    fn read(&mut self, mut buf: &mut [u8]) -> io::Result<usize> {
+        use tungstenite::Message;
+
+        while self.incoming.is_empty() {
+            match self.websocket.read() {
+                Ok(Message::Binary(data)) => self.incoming = data,
+                // pings are answered by tungstenite
+                Ok(Message::Ping(_) | Message::Pong(_) | Message::Frame(_)) => {}
+                Ok(Message::Text(_)) => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        "unexpected text WebSocket message",
+                    ));
+                }
+                Ok(Message::Close(_))
+                | Err(tungstenite::Error::ConnectionClosed | tungstenite::Error::AlreadyClosed) => {
+                    return Ok(0);
+                }
+                Err(why) => return Err(io_error(why)),
+            }
+        }
+
+        let read = buf.write(&self.incoming)?;
+        _ = self.incoming.split_to(read);
+
+        Ok(read)
+    }
+}
+
+impl Write for WebSocketStream {
+    /// Sends all of `buf` in a single message, so that every packet written at once gets its own
+    /// message
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let message = tungstenite::Message::Binary(Bytes::copy_from_slice(buf));
+        self.websocket.send(message).map_err(io_error)?;
+
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.websocket.flush().map_err(io_error)
+    }
+}
+
+impl Stream for WebSocketStream {
+    fn close(&mut self) -> io::Result<()> {
+        self.websocket.close(None).map_err(io_error)?;
+        self.websocket.get_mut().close()
+    }
+
+    fn set_max_packet_length(&mut self, length: usize) {
+        self.websocket.set_config(|config| {
+            config.max_message_size = Some(length);
+            config.max_frame_size = Some(length);
+        });
+    }
+}
+
+impl Connection<()> {
+    /// Connects to the server at `url`, which has to look like `wss://host[:port]/path`. The port
+    /// defaults to 443 and the host is used as the TLS domain
+    pub fn connect_websocket(
+        url: &str,
+        verification: &ServerVerification,
+        timeouts: Timeouts,
+    ) -> crate::Result<Connection<Client>> {
+        let request = url.into_client_request()?;
+        if request.uri().scheme_str() != Some("wss") {
+            return Err(tungstenite::Error::Url(UrlError::UnsupportedUrlScheme).into());
+        }
+        let domain = request
+            .uri()
+            .host()
+            .ok_or(tungstenite::Error::Url(UrlError::EmptyHostName))?
+            // IPv6 addresses are enclosed in brackets in URLs only
+            .trim_start_matches('[')
+            .trim_end_matches(']')
+            .to_owned();
+        let port = request.uri().port_u16().unwrap_or(DEFAULT_PORT);
+
+        let stream = connect_tcp((domain.as_str(), port), timeouts.handshake)?;
+        let socket = stream.try_clone()?;
+        let connector = TlsConnector::new(verification)?;
+        let (stream, peer) = handshake(&socket, timeouts.handshake, || {
+            let (tls_stream, peer_cert) = connector.connect(&domain, stream)?;
+            let peer = verification.check(peer_cert.as_deref())?;
+            let (websocket, _) =
+                tungstenite::client::client_with_config(request, tls_stream, Some(config()))
+                    .map_err(handshake_error)?;
+
+            Ok((WebSocketStream::new(websocket), peer))
+        })?;
+
+        let mut conn = Connection::new(Box::new(stream), Socket::Tcp(socket), peer);
+        conn.set_timeouts(timeouts)?;
+
+        Ok(conn)
+    }
+}
+
+impl Acceptor {
+    /// Accepts an incoming WebSocket connection. Upgrade requests for paths that aren't
+    /// `path_prefix` or under it are refused, so that the port can be shared with a reverse proxy
+    /// routing by path
+    pub fn accept_websocket(
+        &self,
+        stream: TcpStream,
+        path_prefix: &str,
+    ) -> crate::Result<Connection<Server>> {
+        // the signature is dictated by tungstenite
+        #[allow(clippy::result_large_err)]
+        let check_path = |request: &Request, response: Response| {
+            if is_under(request.uri().path(), path_prefix) {
+                Ok(response)
+            } else {
+                let mut response = ErrorResponse::new(None);
+                *response.status_mut() = StatusCode::NOT_FOUND;
+
+                Err(response)
+            }
+        };
+
+        let socket = stream.try_clone()?;
+        let stream = handshake(&socket, self.timeouts.handshake, || {
+            let tls_stream = self.tls.accept(stream)?;
+            let websocket =
+                tungstenite::accept_hdr_with_config(tls_stream, check_path, Some(config()))
+                    .map_err(handshake_error)?;
+
+            Ok(WebSocketStream::new(websocket))
+        })?;
+
+        let mut conn = Connection::new(Box::new(stream), Socket::Tcp(socket), None);
+        conn.set_timeouts(self.timeouts)?;
+
+        Ok(conn)
+    }
+}
+
+/// Async counterpart of [`WebSocketStream`]
+#[cfg(feature = "tokio-websocket")]
+struct AsyncWebSocketStream {
+    websocket: tokio_tungstenite::WebSocketStream<Box<dyn AsyncStream>>,
+    /// What's left of the last binary message received
+    incoming: Bytes,
+}
+
+#[cfg(feature = "tokio-websocket")]
+impl tokio::io::AsyncRead for AsyncWebSocketStream {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut tokio::io::ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        use futures_util::Stream;
+        use tungstenite::Message;
+
+        while self.incoming.is_empty() {
+            match ready!(Pin::new(&mut self.websocket).poll_next(cx)) {
+                Some(Ok(Message::Binary(data))) => self.incoming = data,
+                // pings are answered by tungstenite
+                Some(Ok(Message::Ping(_) | Message::Pong(_) | Message::Frame(_))) => {}
+                Some(Ok(Message::Text(_))) => {
+                    return Poll::Ready(Err(io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        "unexpected text WebSocket message",
+                    )));
+                }
+                Some(Ok(Message::Close(_)))
+                | Some(Err(
+                    tungstenite::Error::ConnectionClosed | tungstenite::Error::AlreadyClosed,
+                ))
+                | None => return Poll::Ready(Ok(())),
+                Some(Err(why)) => return Poll::Ready(Err(io_error(why))),
+            }
+        }
+
+        let read = buf.remaining().min(self.incoming.len());
+        buf.put_slice(&self.incoming.split_to(read));
+
+        Poll::Ready(Ok(()))
+    }
+}
+
+#[cfg(feature = "tokio-websocket")]
+impl tokio::io::AsyncWrite for AsyncWebSocketStream {
+    /// Sends all of `buf` in a single message, so that every packet written at once gets its own
+    /// message
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        use futures_util::Sink;
+
+        let mut websocket = Pin::new(&mut self.websocket);
+        ready!(websocket.as_mut().poll_ready(cx)).map_err(io_error)?;
+        let message = tungstenite::Message::Binary(Bytes::copy_from_slice(buf));
+        websocket.start_send(message).map_err(io_error)?;
+
+        Poll::Ready(Ok(buf.len()))
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        use futures_util::Sink;
+
+        Pin::new(&mut self.websocket)
+            .poll_flush(cx)
+            .map_err(io_error)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        use futures_util::Sink;
+
+        Pin::new(&mut self.websocket)
+            .poll_close(cx)
+            .map_err(io_error)
+    }
+}
+
+#[cfg(feature = "tokio-websocket")]
+impl AsyncAcceptor {
+    /// Accepts an incoming WebSocket connection. This is the async counterpart of
+    /// [`Acceptor::accept_websocket`]. The WebSocket refuses messages longer than what
+    /// [`DEFAULT_MAX_PAYLOAD_LENGTH`] allows
+    ///
+    /// Fails with [`crate::Error::PeerTimedOut`] if the TLS and WebSocket handshakes take longer
+    /// than the handshake timeout.
+    pub async fn accept_websocket(
+        &self,
+        stream: tokio::net::TcpStream,
+        path_prefix: &str,
+    ) -> crate::Result<AsyncConnection<Server>> {
+        // the signature is dictated by tungstenite
+        #[allow(clippy::result_large_err)]
+        let check_path = |request: &Request, response: Response| {
+            if is_under(request.uri().path(), path_prefix) {
+                Ok(response)
+            } else {
+                let mut response = ErrorResponse::new(None);
+                *response.status_mut() = StatusCode::NOT_FOUND;
+
+                Err(response)
+            }
+        };
+
+        let handshake = async {
+            let tls_stream = self.tls.accept_async(stream).await?;
+            let websocket = tokio_tungstenite::accept_hdr_async_with_config(
+                tls_stream,
+                check_path,
+                Some(config()),
+            )
+            .await
+            .map_err(|why| match why {
+                tungstenite::Error::Io(why) => crate::Error::from(why),
+                why => why.into(),
+            })?;
+
+            Ok(AsyncWebSocketStream {
+                websocket,
+                incoming: Bytes::new(),
+            })
+        };
+        let stream = limit(self.timeouts.handshake, handshake).await?;
+
+        let mut conn = AsyncConnection::new(Box::new(stream), None);
+        conn.set_timeouts(self.timeouts);
+
+        Ok(conn)
+    }
+}
+
+#[cfg(test)]
+mod websocket_tests {
+    use std::{net::TcpListener, thread};
+
+    use crate::{
+        Acceptor, Connection, Timeouts,
+        message::Message,
+        tls::{Certificate, Identity, ServerVerification},
+    };
+
+    const IDENT_FILE: &[u8] = include_bytes!("../../test-keys/identity.p12");
+    const IDENT_PASS: &str = include_str!("../../test-keys/creds.asc");
+    const CUSTOM_CERT: &[u8] = include_bytes!("../../test-keys/cert.crt");
+
+    fn setup_test() -> crate::Result<(Acceptor, ServerVerification)> {
+        let cert = Certificate::from_pem(CUSTOM_CERT)?;
+        let identity = Identity::from_pkcs12(IDENT_FILE, IDENT_PASS.trim())?;
+
+        Ok((
+            Acceptor::new(identity)?,
+            ServerVerification::CertificateAuthority(Some(cert)),
+        ))
+    }
+
+    #[test]
+    fn websocket_connection() -> crate::Result<()> {
+        let (acceptor, verification) = setup_test()?;
+
+        let listener = TcpListener::bind(("127.0.0.1", 0))?;
+        let url = format!("wss://localhost:{}/cower/v1", listener.local_addr()?.port());
+        let handle = thread::spawn(move || {
+            let mut conn = Connection::connect_websocket(&url, &verification, Timeouts::default())?;
+            conn.send_request(
+                3,
+                &Message::StartMessage {
+                    resource_name: "my_resource".to_owned(),
+                },
+            )?;
+            let reply = conn.receive_request()?;
+            conn.close(crate::message::CloseReason::Normal, None)?;
+
+            crate::Result::Ok(reply)
+        });
+
+        let (stream, _) = listener.accept()?;
+        let mut conn = acceptor.accept_websocket(stream, "/cower")?;
+
+        let (request_id, message) = conn.receive_request()?;
+        assert_eq!(request_id, 3);
+        assert!(matches!(message, Message::StartMessage { .. }));
+
+        // large enough to need the extended length
+        let data = vec![7; 100_000];
+        conn.send_request(request_id, &Message::LogDataMessage { data: data.clone() })?;
+        assert!(matches!(conn.receive(), Err(crate::Error::Closed { .. })));
+
+        match handle.join().expect("associated thread panicked")? {
+            (3, Message::LogDataMessage { data: received }) => assert!(received == data),
+            reply => panic!("received a different reply: {reply:?}"),
+        }
+
+        Ok(())
+    }
+
+    #[cfg(feature = "tokio-websocket")]
+    #[tokio::test]
+    async fn async_websocket_connection() -> crate::Result<()> {
+        let (acceptor, verification) = setup_test()?;
+        let acceptor = crate::asynchronous::AsyncAcceptor::from(acceptor);
+
+        let listener = tokio::net::TcpListener::bind(("127.0.0.1", 0)).await?;
+        let url = format!("wss://localhost:{}/cower", listener.local_addr()?.port());
+        // the client blocks, which shows that both kinds of connections understand each other
+        let handle = thread::spawn(move || {
+            let mut conn = Connection::connect_websocket(&url, &verification, Timeouts::default())?;
+            conn.send_request(
+                5,
+                &Message::StartMessage {
+                    resource_name: "my_resource".to_owned(),
+                },
+            )?;
+
+            conn.receive_request()
+        });
+
+        let (stream, _) = listener.accept().await?;
+        let mut conn = acceptor.accept_websocket(stream, "/cower").await?;
+
+        let (request_id, message) = conn.receive_request().await?;
+        assert_eq!(request_id, 5);
+        assert!(matches!(message, Message::StartMessage { .. }));
+
+        let data = vec![7; 100_000];
+        conn.send_request(request_id, &Message::LogDataMessage { data: data.clone() })
+            .await?;
+
+        match handle.join().expect("associated thread panicked")? {
+            (5, Message::LogDataMessage { data: received }) => assert!(received == data),
+            reply => panic!("received a different reply: {reply:?}"),
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn websocket_wrong_path() -> crate::Result<()> {
+        let (acceptor, verification) = setup_test()?;
+
+        let listener = TcpListener::bind(("127.0.0.1", 0))?;
+        let url = format!("wss://localhost:{}/cowering", listener.local_addr()?.port());
+        let handle = thread::spawn(move || {
+            Connection::connect_websocket(&url, &verification, Timeouts::default()).map(|_| ())
+        });
+
+        let (stream, _) = listener.accept()?;
+        assert!(acceptor.accept_websocket(stream, "/cower").is_err());
+        assert!(handle.join().expect("associated thread panicked").is_err());
+
+        Ok(())
+    }
+}
@@ -0,0 +1,250 @@
+//! Configuration of the target - which resources are exposed and what clients may do with them
+
+use std::{collections::HashMap, fs, path::Path};
+
+use serde::Deserialize;
+use strum::VariantArray;
+
+/// Operations that clients can perform on resources
+#[allow(missing_docs)]
+#[derive(Deserialize, Clone, Copy, Debug, PartialEq, VariantArray)]
+#[serde(rename_all = "lowercase")]
+pub enum Operation {
+    Start,
+    Stop,
+    Restart,
+    Pause,
+    Unpause,
+    Logs,
+    /// Report the state of the resource, and show it when clients list resources
+    Status,
+}
+
+/// What to do with a resource once it becomes idle
+#[derive(Deserialize, Clone, Copy, Debug, Default, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum IdleAction {
+    /// Stop the container, freeing all of its resources
+    #[default]
+    Stop,
+    /// Freeze the container. It keeps its memory, but waking it up is much faster than a cold
+    /// start
+    Pause,
+}
+
+/// Configuration of a single resource
+#[derive(Deserialize, Clone, Debug, Default)]
+pub struct ResourceConfig {
+    /// Name/ID of the container backing the resource. Defaults to the name of the resource
+    pub container: Option<String>,
+    /// Operations clients are allowed to perform on the resource
+    #[serde(default)]
+    pub allow: Vec<Operation>,
+    /// Seconds without requests after which the resource is suspended according to
+    /// `idle_action`. Resources without one are never suspended
+    pub idle_timeout: Option<u64>,
+    /// What to do with the resource once it becomes idle
+    #[serde(default)]
+    pub idle_action: IdleAction,
+    /// Human-readable description of the resource
+    pub description: Option<String>,
+}
+
+/// Where the members of a group come from
+#[derive(Deserialize, Clone, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum GroupSource {
+    /// All containers of a Docker Compose project, found by their `com.docker.compose.project`
+    /// label. Dependencies between services are taken from the project as well
+    Compose(String),
+    /// All containers of a Podman pod
+    Pod(String),
+}
+
+/// Configuration of a group of resources that are started and stopped together
+#[derive(Deserialize, Clone, Debug)]
+pub struct GroupConfig {
+    /// Where to take members from, in addition to the ones listed in `members`
+    pub source: Option<GroupSource>,
+    /// Members of the group, each mapped to the members it depends on. Members are started after
+    /// their dependencies are ready and stopped before them
+    #[serde(default)]
+    pub members: HashMap<String, Vec<String>>,
+    /// Operations clients are allowed to perform on the group
+    #[serde(default)]
+    pub allow: Vec<Operation>,
+    /// Seconds to wait for each member to become ready before giving up
+    #[serde(default = "default_ready_timeout")]
+    pub ready_timeout: u64,
+}
+
+fn default_ready_timeout() -> u64 {
+    60
+}
+
+/// Configuration of resource discovery from container labels
+#[derive(Deserialize, Clone, Debug)]
+pub struct DiscoveryConfig {
+    /// Whether containers should be discovered at all
+    #[serde(default)]
+    pub enabled: bool,
+    /// Seconds between two discovery runs
+    #[serde(default = "default_discovery_interval")]
+    pub interval: u64,
+}
+
+fn default_discovery_interval() -> u64 {
+    30
+}
+
+impl Default for DiscoveryConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            interval: default_discovery_interval(),
+        }
+    }
+}
+
+/// Who may control the target over its Unix socket. Local clients are identified by the user and
+/// group the kernel reports for them, so they don't need certificates
+#[derive(Deserialize, Clone, Debug)]
+pub struct LocalConfig {
+    /// Users (by uid) allowed to connect. Root is always allowed
+    #[serde(default)]
+    pub uids: Vec<u32>,
+    /// Groups (by gid) allowed to connect. Only the primary group of the connecting process
+    /// counts, as the kernel doesn't report supplementary groups
+    #[serde(default)]
+    pub gids: Vec<u32>,
+    /// Operations local clients may perform on any resource, regardless of what the resource
+    /// allows remote clients to do
+    #[serde(default = "all_operations")]
+    pub allow: Vec<Operation>,
+}
+
+fn all_operations() -> Vec<Operation> {
+    Operation::VARIANTS.to_vec()
+}
+
+impl Default for LocalConfig {
+    fn default() -> Self {
+        Self {
+            uids: Vec::new(),
+            gids: Vec::new(),
+            allow: all_operations(),
+        }
+    }
+}
+
+impl LocalConfig {
+    /// Check whether a process running as `uid` and `gid` may connect
+    pub fn is_authorized(&self, uid: u32, gid: u32) -> bool {
+        uid == 0 || self.uids.contains(&uid) || self.gids.contains(&gid)
+    }
+}
+
+/// The whole target configuration, usually loaded from a TOML file
+#[derive(Deserialize, Clone, Debug)]
+pub struct Config {
+    /// Operations allowed on resources that aren't listed in `resources`
+    #[serde(default = "default_allow")]
+    pub default_allow: Vec<Operation>,
+    /// Resources exposed by the target, keyed by the name clients use
+    #[serde(default)]
+    pub resources: HashMap<String, ResourceConfig>,
+    /// Groups of resources, keyed by the name clients use
+    #[serde(default)]
+    pub groups: HashMap<String, GroupConfig>,
+    /// Resource discovery settings
+    #[serde(default)]
+    pub discovery: DiscoveryConfig,
+    /// Access over the Unix socket
+    #[serde(default)]
+    pub local: LocalConfig,
+}
+
+// this keeps the behaviour of targets running without a config file the same as it was before
+// permissions existed
+fn default_allow() -> Vec<Operation> {
+    vec![Operation::Start]
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            default_allow: default_allow(),
+            resources: HashMap::new(),
+            groups: HashMap::new(),
+            discovery: DiscoveryConfig::default(),
+            local: LocalConfig::default(),
+        }
+    }
+}
+
+impl Config {
+    /// Load the configuration from a TOML file
+    pub fn load(path: &Path) -> anyhow::Result<Self> {
+        let contents = fs::read_to_string(path)?;
+
+        Ok(toml::from_str(&contents)?)
+    }
+
+    /// Check whether `operation` may be performed on `resource`
+    pub fn is_allowed(&self, resource: &str, operation: Operation) -> bool {
+        if let Some(group) = self.groups.get(resource) {
+            return group.allow.contains(&operation);
+        }
+
+        match self.resources.get(resource) {
+            Some(config) => config.allow.contains(&operation),
+            None => self.default_allow.contains(&operation),
+        }
+    }
+
+    /// Get the name/ID of the container backing `resource`
+    pub fn container_id<'a>(&'a self, resource: &'a str) -> &'a str {
+        self.resources
+            .get(resource)
+            .and_then(|config| config.container.as_deref())
+            .unwrap_or(resource)
+    }
+
+    /// Get the action to perform when `resource` becomes idle
+    pub fn idle_action(&self, resource: &str) -> IdleAction {
+        self.resources
+            .get(resource)
+            .map(|config| config.idle_action)
+            .unwrap_or_default()
+    }
+}
+
+#[cfg(test)]
+mod config_tests {
+    use super::{Config, Operation};
+
+    #[test]
+    fn local_access() -> anyhow::Result<()> {
+        let config: Config = toml::from_str(
+            r#"
+            [local]
+            uids = [1000]
+            gids = [27]
+            allow = ["start", "logs"]
+            "#,
+        )?;
+
+        assert!(config.local.is_authorized(0, 0));
+        assert!(config.local.is_authorized(1000, 1000));
+        assert!(config.local.is_authorized(1001, 27));
+        assert!(!config.local.is_authorized(1001, 1001));
+        assert_eq!(config.local.allow, [Operation::Start, Operation::Logs]);
+
+        // without a `[local]` table, only root may connect, but it may do anything
+        let config: Config = toml::from_str("")?;
+        assert!(!config.local.is_authorized(1000, 1000));
+        assert!(config.local.allow.contains(&Operation::Stop));
+
+        Ok(())
+    }
+}
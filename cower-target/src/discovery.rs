@@ -0,0 +1,221 @@
+//! Discovery of resources from container labels, so that they don't have to be listed in the
+//! config file by hand
+//!
+//! Containers are exposed when they have the `cower.enable=true` label. The following labels
+//! configure the resulting resource:
+//!
+//! - `cower.alias` - name clients use for the resource, defaults to the container name
+//! - `cower.allow` - comma-separated list of allowed operations, defaults to `default_allow`
+//! - `cower.description` - human-readable description
+
+use std::{
+    collections::HashMap,
+    sync::{Arc, RwLock},
+    thread::{self, JoinHandle},
+    time::Duration,
+};
+
+use serde::{Deserialize, de::IntoDeserializer};
+
+use crate::{
+    ContainerEngine, ContainerError, ContainerInfo,
+    config::{Config, Operation, ResourceConfig},
+};
+
+/// Label that has to be set to `true` for the container to be discovered
+const ENABLE_LABEL: &str = "cower.enable";
+const ALIAS_LABEL: &str = "cower.alias";
+const ALLOW_LABEL: &str = "cower.allow";
+const DESCRIPTION_LABEL: &str = "cower.description";
+
+/// Configuration shared between connection handlers that can be replaced at runtime
+///
+/// Handlers should clone the inner [`Arc`] and release the lock right away, so that long
+/// operations (like following logs) don't block refreshes.
+pub type SharedConfig = Arc<RwLock<Arc<Config>>>;
+
+/// Discover resources from the labels of containers managed by `engine`. Resources are keyed by
+/// their alias
+pub fn discover(
+    engine: &ContainerEngine,
+    default_allow: &[Operation],
+) -> Result<HashMap<String, ResourceConfig>, ContainerError> {
+    let containers = engine.list_containers(&format!("{ENABLE_LABEL}=true"))?;
+
+    Ok(resources_from_containers(containers, default_allow))
+}
+
+/// Turns containers into resources keyed by their alias. Aliases claimed by several containers are
+/// skipped, as which container wins would otherwise depend on the order the engine lists them in
+fn resources_from_containers(
+    containers: Vec<ContainerInfo>,
+    default_allow: &[Operation],
+) -> HashMap<String, ResourceConfig> {
+    let mut claims: HashMap<String, Vec<ResourceConfig>> = HashMap::new();
+    for container in containers {
+        let (alias, resource) = resource_from_labels(container, default_allow);
+        claims.entry(alias).or_default().push(resource);
+    }
+
+    claims
+        .into_iter()
+        .filter_map(|(alias, mut resources)| {
+            if resources.len() > 1 {
+                let mut containers: Vec<_> = resources
+                    .iter()
+                    .filter_map(|resource| resource.container.as_deref())
+                    .collect();
+                containers.sort_unstable();
+                println!(
+                    "Ignoring {alias}, which is the alias of several containers: {}",
+                    containers.join(", ")
+                );
+
+                return None;
+            }
+
+            Some((alias, resources.pop()?))
+        })
+        .collect()
+}
+
+fn resource_from_labels(
+    container: ContainerInfo,
+    default_allow: &[Operation],
+) -> (String, ResourceConfig) {
+    let alias = container
+        .labels
+        .get(ALIAS_LABEL)
+        .cloned()
+        .unwrap_or_else(|| container.name.clone());
+
+    let allow = match container.labels.get(ALLOW_LABEL) {
+        Some(allow) => allow
+            .split(',')
+            .map(str::trim)
+            .filter(|operation| !operation.is_empty())
+            .filter_map(|operation| {
+                let parsed = Operation::deserialize(
+                    IntoDeserializer::<serde::de::value::Error>::into_deserializer(operation),
+                );
+                if parsed.is_err() {
+                    println!(
+                        "Ignoring unknown operation {operation} on {}",
+                        container.name
+                    );
+                }
+
+                parsed.ok()
+            })
+            .collect(),
+        None => default_allow.to_vec(),
+    };
+
+    let resource = ResourceConfig {
+        container: Some(container.name),
+        allow,
+        description: container.labels.get(DESCRIPTION_LABEL).cloned(),
+        ..Default::default()
+    };
+
+    (alias, resource)
+}
+
+/// Build a config from `base` with discovered resources added. Resources listed in `base` take
+/// precedence over discovered ones
+pub fn merge(base: &Config, discovered: HashMap<String, ResourceConfig>) -> Config {
+    let mut config = base.clone();
+    for (alias, resource) in discovered {
+        config.resources.entry(alias).or_insert(resource);
+    }
+
+    config
+}
+
+/// Spawn a thread that periodically rediscovers resources and replaces the shared config
+pub fn spawn_discovery_thread(
+    engine: Arc<ContainerEngine>,
+    base: Config,
+    shared: SharedConfig,
+) -> JoinHandle<()> {
+    let interval = Duration::from_secs(base.discovery.interval);
+
+    thread::spawn(move || {
+        loop {
+            match discover(&engine, &base.default_allow) {
+                Ok(discovered) => {
+                    let config = Arc::new(merge(&base, discovered));
+                    match shared.write() {
+                        Ok(mut shared) => *shared = config,
+                        // a handler panicked while holding the lock, the config itself is fine
+                        Err(poisoned) => *poisoned.into_inner() = config,
+                    }
+                }
+                Err(why) => println!("Failed to discover resources: {why}"),
+            }
+
+            thread::sleep(interval);
+        }
+    })
+}
+
+#[cfg(test)]
+mod discovery_tests {
+    use std::collections::HashMap;
+
+    use super::{resource_from_labels, resources_from_containers};
+    use crate::{ContainerInfo, config::Operation};
+
+    fn container(name: &str, alias: Option<&str>) -> ContainerInfo {
+        let labels = alias.map(|alias| ("cower.alias".to_owned(), alias.to_owned()));
+
+        ContainerInfo {
+            name: name.to_owned(),
+            labels: labels.into_iter().collect(),
+        }
+    }
+
+    #[test]
+    fn labels_are_parsed() {
+        let container = ContainerInfo {
+            name: "mc-server-1".to_owned(),
+            labels: HashMap::from([
+                ("cower.enable".to_owned(), "true".to_owned()),
+                ("cower.alias".to_owned(), "minecraft".to_owned()),
+                ("cower.allow".to_owned(), "start, logs,nonsense".to_owned()),
+                ("cower.description".to_owned(), "Fabric server".to_owned()),
+            ]),
+        };
+
+        let (alias, resource) = resource_from_labels(container, &[]);
+        assert_eq!(alias, "minecraft");
+        assert_eq!(resource.container.as_deref(), Some("mc-server-1"));
+        assert_eq!(resource.allow, [Operation::Start, Operation::Logs]);
+        assert_eq!(resource.description.as_deref(), Some("Fabric server"));
+    }
+
+    #[test]
+    fn defaults_are_used_without_labels() {
+        let container = ContainerInfo {
+            name: "mc-server-1".to_owned(),
+            labels: HashMap::new(),
+        };
+
+        let (alias, resource) = resource_from_labels(container, &[Operation::Start]);
+        assert_eq!(alias, "mc-server-1");
+        assert_eq!(resource.allow, [Operation::Start]);
+    }
+
+    #[test]
+    fn duplicate_aliases_are_skipped() {
+        let containers = vec![
+            container("mc-1", Some("minecraft")),
+            container("web", None),
+            container("mc-2", Some("minecraft")),
+        ];
+
+        let resources = resources_from_containers(containers, &[]);
+        assert_eq!(resources.len(), 1);
+        assert!(resources.contains_key("web"));
+    }
+}
@@ -0,0 +1,466 @@
+//! The Docker Engine API, spoken over the Unix socket the daemon listens on
+//!
+//! `ureq` only knows how to reach hosts over TCP, so requests go through a connector that opens
+//! the socket instead. The host part of the URLs is ignored.
+
+use std::{
+    fmt,
+    io::{self, Read, Write},
+    net::{Ipv4Addr, SocketAddr},
+    os::unix::net::UnixStream,
+    path::PathBuf,
+    sync::{Arc, Mutex, PoisonError},
+};
+
+use ureq::{
+    Agent, Body,
+    config::Config,
+    http::{Response, StatusCode, Uri},
+    unversioned::{
+        resolver::{ResolvedSocketAddrs, Resolver},
+        transport::{Buffers, ConnectionDetails, Connector, LazyBuffers, NextTimeout, Transport},
+    },
+};
+
+use crate::{ContainerError, ContainerInfo, parse_labels};
+
+/// Where the daemon listens unless configured otherwise
+pub const DEFAULT_SOCKET_PATH: &str = "/var/run/docker.sock";
+
+/// A client of the Docker Engine API
+pub struct DockerApi {
+    agent: Agent,
+    socket: PathBuf,
+}
+
+impl DockerApi {
+    /// Creates a client talking to the daemon listening on `socket`
+    pub fn new(socket: impl Into<PathBuf>) -> Self {
+        let socket = socket.into();
+        let connector = UnixConnector {
+            path: socket.clone(),
+            opened: None,
+        };
+
+        Self {
+            agent: Self::agent(connector),
+            socket,
+        }
+    }
+
+    fn agent(connector: UnixConnector) -> Agent {
+        let config = Agent::config_builder()
+            // error statuses carry meaning, like 404 for unknown containers
+            .http_status_as_error(false)
+            .build();
+
+        Agent::with_parts(config, connector, UnixResolver)
+    }
+
+    /// Checks whether a daemon is listening on the socket
+    pub fn is_reachable(&self) -> bool {
+        UnixStream::connect(&self.socket).is_ok()
+    }
+
+    fn url(endpoint: &str) -> String {
+        format!("http://docker/{endpoint}")
+    }
+
+    /// Turns the status of a response into an error, passing successful responses through
+    fn check(response: Response<Body>) -> Result<Response<Body>, ContainerError> {
+        match response.status() {
+            status if status.is_success() => Ok(response),
+            // 304 means that the container already is in the requested state
+            StatusCode::NOT_MODIFIED => Ok(response),
+            StatusCode::NOT_FOUND => Err(ContainerError::ResourceNotFound),
+
+            _ => Err(ContainerError::Unknown),
+        }
+    }
+
+    /// Sends a POST request without a body, for endpoints that only report success through the
+    /// status code
+    pub(crate) fn post(
+        &self,
+        endpoint: &str,
+        query: &[(&str, &str)],
+    ) -> Result<(), ContainerError> {
+        let response = self
+            .agent
+            .post(Self::url(endpoint))
+            .query_pairs(query.iter().copied())
+            .send_empty()?;
+
+        Self::check(response).map(|_| ())
+    }
+
+    /// Sends a GET request, returning the response if it was successful
+    pub(crate) fn get(
+        &self,
+        endpoint: &str,
+        query: &[(&str, &str)],
+    ) -> Result<Response<Body>, ContainerError> {
+        let response = self
+            .agent
+            .get(Self::url(endpoint))
+            .query_pairs(query.iter().copied())
+            .call()?;
+
+        Self::check(response)
+    }
+
+    /// Sends a GET request like [`DockerApi::get`], but over a connection of its own, which is
+    /// returned as well. Shutting that connection down ends a body that's still being streamed,
+    /// even from another thread
+    pub(crate) fn get_stream(
+        &self,
+        endpoint: &str,
+        query: &[(&str, &str)],
+    ) -> Result<(Response<Body>, UnixStream), ContainerError> {
+        let opened = Arc::new(Mutex::new(None));
+        let agent = Self::agent(UnixConnector {
+            path: self.socket.clone(),
+            opened: Some(opened.clone()),
+        });
+        let response = agent
+            .get(Self::url(endpoint))
+            .query_pairs(query.iter().copied())
+            .call()?;
+
+        let socket = opened
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .take()
+            .ok_or(ContainerError::Unknown)?;
+
+        Ok((Self::check(response)?, socket))
+    }
+
+    /// Sends a GET request and parses the JSON response
+    pub(crate) fn get_json(
+        &self,
+        endpoint: &str,
+        query: &[(&str, &str)],
+    ) -> Result<serde_json::Value, ContainerError> {
+        let mut response = self.get(endpoint, query)?;
+
+        serde_json::from_str(&response.body_mut().read_to_string()?)
+            .map_err(|_| ContainerError::Unknown)
+    }
+
+    /// Lists containers, passing `query` to the `/containers/json` endpoint
+    pub(crate) fn list(
+        &self,
+        query: &[(&str, &str)],
+    ) -> Result<Vec<ContainerInfo>, ContainerError> {
+        let containers = self.get_json("containers/json", query)?;
+        let containers = containers.as_array().ok_or(ContainerError::Unknown)?;
+
+        Ok(containers
+            .iter()
+            .filter_map(|container| {
+                // docker prefixes names with a slash
+                let name = container["Names"][0].as_str()?.trim_start_matches('/');
+
+                Some(ContainerInfo {
+                    name: name.to_owned(),
+                    labels: parse_labels(&container["Labels"]),
+                })
+            })
+            .collect())
+    }
+}
+
+/// Connects to the daemon's socket, whatever the URL says
+#[derive(Debug)]
+struct UnixConnector {
+    path: PathBuf,
+    /// Where a handle to the last socket opened is put, if anywhere
+    opened: Option<Arc<Mutex<Option<UnixStream>>>>,
+}
+
+impl Connector<()> for UnixConnector {
+    type Out = UnixTransport;
+
+    fn connect(
+        &self,
+        details: &ConnectionDetails,
+        _: Option<()>,
+    ) -> Result<Option<Self::Out>, ureq::Error> {
+        let stream = UnixStream::connect(&self.path)?;
+        if let Some(opened) = &self.opened {
+            *opened.lock().unwrap_or_else(PoisonError::into_inner) = Some(stream.try_clone()?);
+        }
+        let config = details.config;
+        let buffers = LazyBuffers::new(config.input_buffer_size(), config.output_buffer_size());
+
+        Ok(Some(UnixTransport { stream, buffers }))
+    }
+}
+
+/// There's nothing to resolve, as every request goes to the same socket. The connector doesn't
+/// look at the address, but `ureq` insists on having one
+#[derive(Debug)]
+struct UnixResolver;
+
+impl Resolver for UnixResolver {
+    fn resolve(
+        &self,
+        _: &Uri,
+        _: &Config,
+        _: NextTimeout,
+    ) -> Result<ResolvedSocketAddrs, ureq::Error> {
+        let mut addrs = self.empty();
+        addrs.push(SocketAddr::from((Ipv4Addr::LOCALHOST, 0)));
+
+        Ok(addrs)
+    }
+}
+
+struct UnixTransport {
+    stream: UnixStream,
+    buffers: LazyBuffers,
+}
+
+impl fmt::Debug for UnixTransport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("UnixTransport")
+            .field("stream", &self.stream)
+            .finish_non_exhaustive()
+    }
+}
+
+/// Converts a socket timeout into the error `ureq` expects
+fn map_timeout(why: io::Error, timeout: &NextTimeout) -> ureq::Error {
+    match why.kind() {
+        io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut => ureq::Error::Timeout(timeout.reason),
+
+        _ => why.into(),
+    }
+}
+
+impl Transport for UnixTransport {
+    fn buffers(&mut self) -> &mut dyn Buffers {
+        &mut self.buffers
+    }
+
+    fn transmit_output(&mut self, amount: usize, timeout: NextTimeout) -> Result<(), ureq::Error> {
+        self.stream
+            .set_write_timeout(timeout.not_zero().map(|timeout| *timeout))?;
+
+        let output = &self.buffers.output()[..amount];
+        self.stream
+            .write_all(output)
+            .map_err(|why| map_timeout(why, &timeout))
+    }
+
+    fn await_input(&mut self, timeout: NextTimeout) -> Result<bool, ureq::Error> {
+        self.stream
+            .set_read_timeout(timeout.not_zero().map(|timeout| *timeout))?;
+
+        let input = self.buffers.input_append_buf();
+        let read = self
+            .stream
+            .read(input)
+            .map_err(|why| map_timeout(why, &timeout))?;
+        self.buffers.input_appended(read);
+
+        Ok(read > 0)
+    }
+
+    fn is_open(&mut self) -> bool {
+        // connections are cheap, so they aren't reused
+        false
+    }
+}
+
+#[cfg(test)]
+mod docker_tests {
+    use std::{
+        io::{BufRead, BufReader, Read, Write},
+        os::unix::net::UnixListener,
+        path::PathBuf,
+        thread::{self, JoinHandle},
+    };
+
+    use super::DockerApi;
+    use crate::{ContainerEngine, ContainerError, discovery};
+
+    /// Serves every request to a socket with `respond`, returning the request lines
+    fn fake_daemon(
+        name: &str,
+        requests: usize,
+        respond: impl Fn(&str) -> Vec<u8> + Send + 'static,
+    ) -> (PathBuf, JoinHandle<Vec<String>>) {
+        let path = std::env::temp_dir().join(format!("cower-{name}-{}.sock", std::process::id()));
+        _ = std::fs::remove_file(&path);
+        let listener = UnixListener::bind(&path).expect("couldn't bind the fake socket");
+
+        let handle = thread::spawn(move || {
+            let mut lines = vec![];
+            for stream in listener.incoming().take(requests) {
+                let mut stream = stream.expect("couldn't accept");
+                let mut reader = BufReader::new(stream.try_clone().expect("couldn't clone"));
+
+                let mut line = String::new();
+                reader.read_line(&mut line).expect("couldn't read");
+                // the rest of the headers
+                let mut header = String::new();
+                while reader.read_line(&mut header).is_ok_and(|read| read > 2) {
+                    header.clear();
+                }
+
+                stream.write_all(&respond(&line)).expect("couldn't write");
+                lines.push(line.trim_end().to_owned());
+            }
+
+            lines
+        });
+
+        (path, handle)
+    }
+
+    fn response(status: &str, body: &[u8]) -> Vec<u8> {
+        let head = format!(
+            "HTTP/1.1 {status}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+            body.len()
+        );
+
+        [head.as_bytes(), body].concat()
+    }
+
+    #[test]
+    fn logs_over_socket() -> anyhow::Result<()> {
+        let (path, daemon) = fake_daemon("logs", 2, |line| {
+            if line.contains("/containers/web/") {
+                // a stdout frame, then a stderr frame
+                let mut body = vec![1, 0, 0, 0, 0, 0, 0, 6];
+                body.extend_from_slice(b"hello\n");
+                body.extend_from_slice(&[2, 0, 0, 0, 0, 0, 0, 4]);
+                body.extend_from_slice(b"oops");
+                response("200 OK", &body)
+            } else {
+                response("404 Not Found", br#"{"message":"No such container"}"#)
+            }
+        });
+        let engine = ContainerEngine::Docker(DockerApi::new(&path));
+
+        let mut logs = String::new();
+        engine
+            .logs("web", Some(10), false)?
+            .read_to_string(&mut logs)?;
+        assert_eq!(logs, "hello\noops");
+
+        assert!(matches!(
+            engine.logs("nonexistent", None, false),
+            Err(ContainerError::ResourceNotFound)
+        ));
+
+        let lines = daemon.join().expect("fake daemon panicked");
+        assert_eq!(
+            lines[0],
+            "GET /containers/web/logs?stdout=1&stderr=1&tail=10&follow=0 HTTP/1.1"
+        );
+        _ = std::fs::remove_file(path);
+
+        Ok(())
+    }
+
+    #[test]
+    fn close_followed_logs() -> anyhow::Result<()> {
+        let path = std::env::temp_dir().join(format!("cower-follow-{}.sock", std::process::id()));
+        _ = std::fs::remove_file(&path);
+        let listener = UnixListener::bind(&path)?;
+
+        // sends one frame and then keeps the connection open, like a container that went quiet
+        let daemon = thread::spawn(move || {
+            let (mut stream, _) = listener.accept().expect("couldn't accept");
+            let mut reader = BufReader::new(stream.try_clone().expect("couldn't clone"));
+            let mut header = String::new();
+            while reader.read_line(&mut header).is_ok_and(|read| read > 2) {
+                header.clear();
+            }
+
+            let head = "HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\n";
+            let chunk = b"e\r\n\x01\0\0\0\0\0\0\x06hello\n\r\n";
+            stream
+                .write_all(&[head.as_bytes(), chunk].concat())
+                .expect("couldn't write");
+
+            stream
+        });
+        let engine = ContainerEngine::Docker(DockerApi::new(&path));
+
+        let mut logs = engine.logs("web", None, true)?;
+        let mut hello = [0; 6];
+        logs.read_exact(&mut hello)?;
+        assert_eq!(&hello, b"hello\n");
+
+        let _stream = daemon.join().expect("fake daemon panicked");
+        logs.closer().close();
+        // blocks forever unless closing ended the stream
+        assert!(!matches!(logs.read(&mut hello), Ok(read) if read > 0));
+        _ = std::fs::remove_file(path);
+
+        Ok(())
+    }
+
+    #[test]
+    fn stop_and_restart_statuses() -> anyhow::Result<()> {
+        let (path, daemon) = fake_daemon("stop", 4, |line| {
+            if line.contains("/containers/web/stop") {
+                response("204 No Content", b"")
+            } else if line.contains("/containers/web/") {
+                // already in the requested state
+                response("304 Not Modified", b"")
+            } else {
+                response("404 Not Found", br#"{"message":"No such container"}"#)
+            }
+        });
+        let engine = ContainerEngine::Docker(DockerApi::new(&path));
+
+        engine.stop_container("web", Some(5), Some("SIGINT"))?;
+        // whatever the client sends stays within the value
+        engine.stop_container("web", None, Some("SIGTERM&t=0 #"))?;
+        engine.restart_container("web", None)?;
+        assert!(matches!(
+            engine.stop_container("nonexistent", None, None),
+            Err(ContainerError::ResourceNotFound)
+        ));
+
+        let lines = daemon.join().expect("fake daemon panicked");
+        assert_eq!(
+            lines[0],
+            "POST /containers/web/stop?t=5&signal=SIGINT HTTP/1.1"
+        );
+        assert_eq!(
+            lines[1],
+            "POST /containers/web/stop?signal=SIGTERM%26t%3D0%20%23 HTTP/1.1"
+        );
+        assert_eq!(lines[2], "POST /containers/web/restart HTTP/1.1");
+        _ = std::fs::remove_file(path);
+
+        Ok(())
+    }
+
+    #[test]
+    fn discover_over_socket() -> anyhow::Result<()> {
+        let (path, daemon) = fake_daemon("discover", 1, |_| {
+            let containers = br#"[{"Names":["/mc-server-1"],"Labels":{"cower.enable":"true","cower.alias":"minecraft"}}]"#;
+            response("200 OK", containers)
+        });
+        let engine = ContainerEngine::Docker(DockerApi::new(&path));
+
+        let resources = discovery::discover(&engine, &[])?;
+        let minecraft = resources
+            .get("minecraft")
+            .expect("minecraft wasn't discovered");
+        assert_eq!(minecraft.container.as_deref(), Some("mc-server-1"));
+
+        let lines = daemon.join().expect("fake daemon panicked");
+        assert!(lines[0].starts_with("GET /containers/json?all=true&filters="));
+        _ = std::fs::remove_file(path);
+
+        Ok(())
+    }
+}
@@ -0,0 +1,242 @@
+//! Groups of resources that are started and stopped together, in an order that respects the
+//! dependencies between them
+
+use std::{
+    collections::{BTreeMap, BTreeSet, HashMap},
+    thread,
+    time::{Duration, Instant},
+};
+
+use crate::{
+    ContainerEngine, ContainerError,
+    config::{Config, GroupConfig, GroupSource},
+};
+
+/// Label Docker Compose puts on containers to identify their project
+const COMPOSE_PROJECT_LABEL: &str = "com.docker.compose.project";
+/// Label Docker Compose puts on containers to identify their service
+const COMPOSE_SERVICE_LABEL: &str = "com.docker.compose.service";
+/// Label Docker Compose puts on containers to list the services they depend on, in the format
+/// `service:condition:restart,...`
+const COMPOSE_DEPENDS_ON_LABEL: &str = "com.docker.compose.depends_on";
+
+/// How often members are checked while waiting for them to become ready
+const READY_POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+/// Errors arising from group operations
+#[derive(thiserror::Error, Debug)]
+pub enum GroupError {
+    /// The dependencies of the group's members form a cycle
+    #[error("dependency cycle in group")]
+    DependencyCycle,
+
+    /// A member depends on something that isn't a member of the group
+    #[error("unknown dependency {0}")]
+    UnknownDependency(String),
+
+    /// A member didn't become ready in time
+    #[error("{0} didn't become ready in time")]
+    NotReady(String),
+
+    /// The container engine failed
+    #[error("container engine error")]
+    Container(#[from] ContainerError),
+}
+
+/// Members of a group, with container names/IDs mapped to the containers they depend on
+pub type Members = BTreeMap<String, BTreeSet<String>>;
+
+/// Collect the members of `group` from its source and its member list
+pub fn resolve_members(
+    engine: &ContainerEngine,
+    config: &Config,
+    group: &GroupConfig,
+) -> Result<Members, GroupError> {
+    let mut members = Members::new();
+
+    match &group.source {
+        Some(GroupSource::Compose(project)) => {
+            let label = format!("{COMPOSE_PROJECT_LABEL}={project}");
+            let containers = engine.list_containers(&label)?;
+
+            let services: HashMap<&str, &str> = containers
+                .iter()
+                .filter_map(|container| {
+                    let service = container.labels.get(COMPOSE_SERVICE_LABEL)?;
+                    Some((service.as_str(), container.name.as_str()))
+                })
+                .collect();
+
+            for container in &containers {
+                let depends_on = container
+                    .labels
+                    .get(COMPOSE_DEPENDS_ON_LABEL)
+                    .map(String::as_str)
+                    .unwrap_or_default()
+                    .split(',')
+                    .filter_map(|dependency| dependency.split(':').next())
+                    .filter(|service| !service.is_empty())
+                    .map(|service| {
+                        services
+                            .get(service)
+                            .map(|name| (*name).to_owned())
+                            .ok_or_else(|| GroupError::UnknownDependency(service.to_owned()))
+                    })
+                    .collect::<Result<_, _>>()?;
+
+                members.insert(container.name.clone(), depends_on);
+            }
+        }
+        Some(GroupSource::Pod(pod)) => {
+            for container in engine.list_pod_containers(pod)? {
+                members.insert(container.name, BTreeSet::new());
+            }
+        }
+        None => {}
+    }
+
+    for (member, depends_on) in &group.members {
+        let dependencies = members
+            .entry(config.container_id(member).to_owned())
+            .or_default();
+        dependencies.extend(
+            depends_on
+                .iter()
+                .map(|dependency| config.container_id(dependency).to_owned()),
+        );
+    }
+
+    Ok(members)
+}
+
+/// Order the members so that every member comes after all of its dependencies
+pub fn start_order(members: &Members) -> Result<Vec<String>, GroupError> {
+    let mut remaining: BTreeMap<&str, BTreeSet<&str>> = BTreeMap::new();
+    for (member, depends_on) in members {
+        let mut dependencies = BTreeSet::new();
+        for dependency in depends_on {
+            if !members.contains_key(dependency) {
+                return Err(GroupError::UnknownDependency(dependency.clone()));
+            }
+
+            dependencies.insert(dependency.as_str());
+        }
+
+        remaining.insert(member, dependencies);
+    }
+
+    let mut order = Vec::with_capacity(members.len());
+    while !remaining.is_empty() {
+        let ready: Vec<&str> = remaining
+            .iter()
+            .filter(|(_, dependencies)| dependencies.is_empty())
+            .map(|(member, _)| *member)
+            .collect();
+
+        if ready.is_empty() {
+            return Err(GroupError::DependencyCycle);
+        }
+
+        for member in ready {
+            remaining.remove(member);
+            for dependencies in remaining.values_mut() {
+                dependencies.remove(member);
+            }
+
+            order.push(member.to_owned());
+        }
+    }
+
+    Ok(order)
+}
+
+/// Start the members of a group, waiting up to `ready_timeout` for each of them to become ready
+/// before starting the members that depend on it. Returns the name of the last started member
+pub fn start_group(
+    engine: &ContainerEngine,
+    members: &Members,
+    ready_timeout: Duration,
+) -> Result<Option<String>, GroupError> {
+    let order = start_order(members)?;
+
+    for member in &order {
+        engine.wake_container(member)?;
+
+        let deadline = Instant::now() + ready_timeout;
+        while !engine.is_ready(member)? {
+            if Instant::now() >= deadline {
+                return Err(GroupError::NotReady(member.clone()));
+            }
+
+            thread::sleep(READY_POLL_INTERVAL);
+        }
+    }
+
+    Ok(order.last().cloned())
+}
+
+/// Stop the members of a group in the reverse order they are started in. Returns the name of the
+/// last stopped member
+pub fn stop_group(
+    engine: &ContainerEngine,
+    members: &Members,
+) -> Result<Option<String>, GroupError> {
+    let order = start_order(members)?;
+
+    for member in order.iter().rev() {
+        engine.stop_container(member, None, None)?;
+    }
+
+    Ok(order.first().cloned())
+}
+
+#[cfg(test)]
+mod group_tests {
+    use super::{GroupError, Members, start_order};
+
+    fn members(edges: &[(&str, &[&str])]) -> Members {
+        edges
+            .iter()
+            .map(|(member, depends_on)| {
+                (
+                    (*member).to_owned(),
+                    depends_on.iter().map(|dep| (*dep).to_owned()).collect(),
+                )
+            })
+            .collect()
+    }
+
+    #[test]
+    fn dependencies_come_first() -> Result<(), GroupError> {
+        let members = members(&[
+            ("backup", &["server"]),
+            ("dynmap", &["server"]),
+            ("server", &[]),
+        ]);
+
+        let order = start_order(&members)?;
+        assert_eq!(order, ["server", "backup", "dynmap"]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn cycle_is_rejected() {
+        let members = members(&[("a", &["b"]), ("b", &["c"]), ("c", &["a"])]);
+
+        assert!(matches!(
+            start_order(&members),
+            Err(GroupError::DependencyCycle)
+        ));
+    }
+
+    #[test]
+    fn unknown_dependency_is_rejected() {
+        let members = members(&[("a", &["nonexistent"])]);
+
+        assert!(matches!(
+            start_order(&members),
+            Err(GroupError::UnknownDependency(_))
+        ));
+    }
+}
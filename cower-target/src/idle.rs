@@ -0,0 +1,116 @@
+//! Suspending resources nobody has asked for anything in a while, so that they don't use up the
+//! host when nobody's playing
+//!
+//! Resources are suspended according to their `idle_action` once they've gone without requests
+//! for their `idle_timeout`. Resources without an `idle_timeout` are left alone.
+
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use crate::config::{Config, IdleAction};
+
+/// Idle tracker shared between the connection handlers, which record requests, and the thread
+/// suspending idle resources
+pub type SharedTracker = Arc<Mutex<IdleTracker>>;
+
+/// Keeps track of when each resource was last asked for something
+#[derive(Default, Debug)]
+pub struct IdleTracker {
+    last_request: HashMap<String, Instant>,
+}
+
+impl IdleTracker {
+    /// Records a request for `resource` made at `now`
+    pub fn touch(&mut self, resource: &str, now: Instant) {
+        self.last_request.insert(resource.to_owned(), now);
+    }
+
+    /// Resources in `config` that haven't been asked for anything within their idle timeout at
+    /// `now`, together with what to do with them, sorted by name
+    ///
+    /// Resources are tracked from the first time they're seen here, so that ones that were
+    /// running before the target started are suspended as well. Returned resources start over,
+    /// so that they aren't returned again until another idle timeout passes
+    pub fn idle(&mut self, config: &Config, now: Instant) -> Vec<(String, IdleAction)> {
+        let mut idle: Vec<_> = config
+            .resources
+            .iter()
+            .filter_map(|(name, resource)| {
+                let timeout = Duration::from_secs(resource.idle_timeout?);
+                let last = self.last_request.entry(name.clone()).or_insert(now);
+                if now.saturating_duration_since(*last) < timeout {
+                    return None;
+                }
+
+                *last = now;
+                Some((name.clone(), config.idle_action(name)))
+            })
+            .collect();
+        idle.sort_unstable_by(|(a, _), (b, _)| a.cmp(b));
+
+        idle
+    }
+}
+
+#[cfg(test)]
+mod idle_tests {
+    use std::time::{Duration, Instant};
+
+    use super::IdleTracker;
+    use crate::config::{Config, IdleAction};
+
+    #[test]
+    fn resources_go_idle() -> anyhow::Result<()> {
+        let config: Config = toml::from_str(
+            r#"
+            [resources.minecraft]
+            idle_timeout = 600
+            idle_action = "pause"
+
+            [resources.web]
+            idle_timeout = 60
+
+            [resources.db]
+            "#,
+        )?;
+        let start = Instant::now();
+        let at = |secs| start + Duration::from_secs(secs);
+
+        let mut tracker = IdleTracker::default();
+        // tracking starts when the resources are first seen
+        assert!(tracker.idle(&config, at(0)).is_empty());
+        assert_eq!(
+            tracker.idle(&config, at(60)),
+            [("web".into(), IdleAction::Stop)]
+        );
+        // a suspended resource isn't suspended again right away
+        assert!(tracker.idle(&config, at(61)).is_empty());
+
+        // requests keep a resource awake
+        tracker.touch("minecraft", at(500));
+        assert_eq!(
+            tracker.idle(&config, at(600)),
+            [("web".into(), IdleAction::Stop)]
+        );
+        assert_eq!(
+            tracker.idle(&config, at(1100)),
+            [
+                ("minecraft".into(), IdleAction::Pause),
+                ("web".into(), IdleAction::Stop)
+            ]
+        );
+
+        // resources without an idle timeout are never suspended
+        assert!(
+            tracker
+                .idle(&config, at(100_000))
+                .iter()
+                .all(|(name, _)| name != "db")
+        );
+
+        Ok(())
+    }
+}
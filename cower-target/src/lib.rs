@@ -2,25 +2,48 @@
 
 //! The target is the thing that manages containers
 
-use std::{fs, process::Command};
+pub mod config;
+pub mod discovery;
+#[cfg(feature = "docker")]
+pub mod docker;
+pub mod group;
+pub mod idle;
+
+use std::{
+    collections::HashMap,
+    io::{self, Read},
+    process::Command,
+    sync::Arc,
+    thread,
+    time::{Duration, Instant},
+};
+#[cfg(feature = "podman")]
+use std::sync::{Mutex, PoisonError};
 
 use anyhow::Result;
+use config::IdleAction;
+use cower_common::message::ContainerState;
 
 /// The container engine to use
 #[allow(missing_docs)]
 pub enum ContainerEngine {
     #[cfg(feature = "docker")]
-    Docker,
+    Docker(docker::DockerApi),
     #[cfg(feature = "podman")]
     Podman,
 }
 
-#[cfg(feature = "docker")]
-const DOCKER_SOCKET_PATH: &str = "/var/run/docker.sock";
 #[cfg(feature = "podman")]
 const PODMAN_BIN_PATH: &str = "/usr/bin/podman";
 
 const CMD_NOT_FOUND_STATUS: i32 = 127;
+/// How long containers get to stop before they're killed, unless the client says otherwise. The
+/// same as the default of both engines
+#[cfg(feature = "podman")]
+const DEFAULT_STOP_TIMEOUT: Duration = Duration::from_secs(10);
+/// How often a container that was sent a signal is checked on
+#[cfg(feature = "podman")]
+const STOP_POLL_INTERVAL: Duration = Duration::from_millis(200);
 
 /// Errors arising from container engine communication
 #[derive(thiserror::Error, Debug)]
@@ -38,19 +61,35 @@ pub enum ContainerError {
     #[error("requested resource was not found")]
     ResourceNotFound,
 
+    /// The engine doesn't support the requested operation
+    #[error("operation not supported by the engine")]
+    Unsupported,
+
     /// Some other error
     #[error("unknown engine error")]
     Unknown,
 }
 
+/// Basic information about a container, as returned by [`ContainerEngine::list_containers`]
+#[derive(Debug)]
+pub struct ContainerInfo {
+    /// Name of the container
+    pub name: String,
+    /// Labels the container has
+    pub labels: HashMap<String, String>,
+}
+
 impl ContainerEngine {
     /// Try to detect the container engine available on the target
     // TODO: handle multiple runtimes (I know, niche)
     pub fn try_detect() -> Option<Self> {
         // docker
         #[cfg(feature = "docker")]
-        if fs::File::open(DOCKER_SOCKET_PATH).is_ok() {
-            return Some(Self::Docker);
+        {
+            let api = docker::DockerApi::new(docker::DEFAULT_SOCKET_PATH);
+            if api.is_reachable() {
+                return Some(Self::Docker(api));
+            }
         }
 
         // podman
@@ -73,87 +112,639 @@ impl ContainerEngine {
     pub fn start_container(&self, resource_id: &str) -> Result<(), ContainerError> {
         match self {
             #[cfg(feature = "docker")]
-            ContainerEngine::Docker => {
-                use ureq::{Agent, http::StatusCode};
+            ContainerEngine::Docker(api) => {
+                api.post(&format!("containers/{resource_id}/start"), &[])
+            }
+            #[cfg(feature = "podman")]
+            ContainerEngine::Podman => run_podman(&["start", resource_id]),
+        }
+    }
 
-                let uri = format!("{DOCKER_SOCKET_PATH}/containers/{resource_id}/start");
-                let res = Agent::new_with_defaults().post(uri).send(&[])?;
+    /// Stops the resource specified by `resource_id`. The container is killed if it doesn't stop
+    /// within `timeout` seconds; the engine's default timeout is used if it's `None`. If `signal`
+    /// is set, it's sent instead of the container's stop signal
+    ///
+    /// Podman can't stop containers with a custom signal, so `podman kill --signal` sends it
+    /// instead, and the container is killed with `SIGKILL` if it's still up after the timeout.
+    /// This is what Docker does as well
+    pub fn stop_container(
+        &self,
+        resource_id: &str,
+        timeout: Option<u32>,
+        signal: Option<&str>,
+    ) -> Result<(), ContainerError> {
+        match self {
+            #[cfg(feature = "docker")]
+            ContainerEngine::Docker(api) => {
+                let timeout = timeout.map(|timeout| timeout.to_string());
+                let mut query = vec![];
+                if let Some(timeout) = &timeout {
+                    query.push(("t", timeout.as_str()));
+                }
+                if let Some(signal) = signal {
+                    query.push(("signal", signal));
+                }
 
-                // this match looks weird, but 404 and 500 are the only documented status codes
-                match res.status() {
-                    StatusCode::NOT_FOUND => return Err(ContainerError::ResourceNotFound),
-                    StatusCode::INTERNAL_SERVER_ERROR => return Err(ContainerError::Unknown),
+                api.post(&format!("containers/{resource_id}/stop"), &query)
+            }
+            #[cfg(feature = "podman")]
+            ContainerEngine::Podman => {
+                if let Some(signal) = signal {
+                    // `podman stop` always sends the stop signal from the container config, so
+                    // the grace period is kept track of here instead
+                    let timeout = timeout.map_or(DEFAULT_STOP_TIMEOUT, |timeout| {
+                        Duration::from_secs(timeout.into())
+                    });
+                    let is_stopped = || Ok(is_stopped(self.container_state(resource_id)?));
+
+                    return stop_with_signal(run_podman, is_stopped, resource_id, signal, timeout);
+                }
 
-                    _ => return Err(ContainerError::Unknown),
+                let timeout = timeout.map(|timeout| timeout.to_string());
+                match &timeout {
+                    Some(timeout) => run_podman(&["stop", "--time", timeout, resource_id]),
+                    None => run_podman(&["stop", resource_id]),
                 }
             }
+        }
+    }
+
+    /// Restarts the resource specified by `resource_id`. `timeout` has the same meaning as in
+    /// [`ContainerEngine::stop_container`]
+    pub fn restart_container(
+        &self,
+        resource_id: &str,
+        timeout: Option<u32>,
+    ) -> Result<(), ContainerError> {
+        match self {
+            #[cfg(feature = "docker")]
+            ContainerEngine::Docker(api) => {
+                let timeout = timeout.map(|timeout| timeout.to_string());
+                let query: Vec<_> = timeout
+                    .iter()
+                    .map(|timeout| ("t", timeout.as_str()))
+                    .collect();
+
+                api.post(&format!("containers/{resource_id}/restart"), &query)
+            }
             #[cfg(feature = "podman")]
             ContainerEngine::Podman => {
-                use std::process::Stdio;
+                let timeout = timeout.map(|timeout| timeout.to_string());
+                match &timeout {
+                    Some(timeout) => run_podman(&["restart", "--time", timeout, resource_id]),
+                    None => run_podman(&["restart", resource_id]),
+                }
+            }
+        }
+    }
 
-                let status = Command::new(PODMAN_BIN_PATH)
-                    .args(["start", resource_id])
-                    .stdout(Stdio::null())
-                    .stderr(Stdio::null())
-                    .status()
-                    .map_err(|_| ContainerError::EngineUnreachable)?;
+    /// Pauses the resource specified by `resource_id`, freezing its processes without losing its
+    /// memory
+    pub fn pause_container(&self, resource_id: &str) -> Result<(), ContainerError> {
+        match self {
+            #[cfg(feature = "docker")]
+            ContainerEngine::Docker(api) => {
+                api.post(&format!("containers/{resource_id}/pause"), &[])
+            }
+            #[cfg(feature = "podman")]
+            ContainerEngine::Podman => run_podman(&["pause", resource_id]),
+        }
+    }
+
+    /// Resumes the paused resource specified by `resource_id`
+    pub fn unpause_container(&self, resource_id: &str) -> Result<(), ContainerError> {
+        match self {
+            #[cfg(feature = "docker")]
+            ContainerEngine::Docker(api) => {
+                api.post(&format!("containers/{resource_id}/unpause"), &[])
+            }
+            #[cfg(feature = "podman")]
+            ContainerEngine::Podman => run_podman(&["unpause", resource_id]),
+        }
+    }
 
-                if !status.success() {
-                    let status_code = status.code().ok_or(ContainerError::EngineUnreachable)?;
+    /// Wakes up the resource specified by `resource_id`, unpausing it if it's paused and starting
+    /// it otherwise
+    pub fn wake_container(&self, resource_id: &str) -> Result<(), ContainerError> {
+        match self.container_state(resource_id)? {
+            ContainerState::Paused => self.unpause_container(resource_id),
 
-                    match status_code {
-                        CMD_NOT_FOUND_STATUS => return Err(ContainerError::EngineUnreachable),
+            _ => self.start_container(resource_id),
+        }
+    }
 
-                        _ => return Err(ContainerError::Unknown),
-                    }
-                }
-            }
+    /// Puts the idle resource specified by `resource_id` to sleep according to `action`
+    pub fn suspend_container(
+        &self,
+        resource_id: &str,
+        action: IdleAction,
+    ) -> Result<(), ContainerError> {
+        match action {
+            IdleAction::Stop => self.stop_container(resource_id, None, None),
+            IdleAction::Pause => self.pause_container(resource_id),
         }
+    }
+
+    /// Gets the current state of the resource specified by `resource_id`
+    pub fn container_state(&self, resource_id: &str) -> Result<ContainerState, ContainerError> {
+        let inspect = self.inspect(resource_id)?;
+        let status = inspect["State"]["Status"]
+            .as_str()
+            .ok_or(ContainerError::Unknown)?;
 
-        Err(ContainerError::EngineUnreachable)
+        Ok(parse_state(status))
     }
 
-    /// Stops the resource specified by `resource_id`
-    pub fn stop_container(&self, resource_id: &str) -> Result<(), ContainerError> {
+    /// Checks whether the resource specified by `resource_id` is ready - it must be running, and
+    /// if it has a healthcheck, the check must be passing
+    pub fn is_ready(&self, resource_id: &str) -> Result<bool, ContainerError> {
+        let inspect = self.inspect(resource_id)?;
+        let state = &inspect["State"];
+
+        let running = state["Status"].as_str().map(parse_state) == Some(ContainerState::Running);
+        // containers without a healthcheck either don't have the field or have it empty
+        let healthy = match state["Health"]["Status"].as_str() {
+            None | Some("") => true,
+            Some(status) => status == "healthy",
+        };
+
+        Ok(running && healthy)
+    }
+
+    /// Lists all containers (including stopped ones) that have the label `label`. `label` can be
+    /// either just a key, or a `key=value` pair
+    pub fn list_containers(&self, label: &str) -> Result<Vec<ContainerInfo>, ContainerError> {
         match self {
             #[cfg(feature = "docker")]
-            ContainerEngine::Docker => {
-                use ureq::{Agent, http::StatusCode};
+            ContainerEngine::Docker(api) => {
+                let filters = serde_json::json!({ "label": [label] }).to_string();
 
-                let uri = format!("{DOCKER_SOCKET_PATH}/containers/{resource_id}/stop");
-                let res = Agent::new_with_defaults().post(uri).send(&[])?;
+                api.list(&[("all", "true"), ("filters", &filters)])
+            }
+            #[cfg(feature = "podman")]
+            ContainerEngine::Podman => {
+                let filter = format!("label={label}");
 
-                // this match looks weird, but 404 and 500 are the only documented status codes
-                match res.status() {
-                    StatusCode::NOT_FOUND => return Err(ContainerError::ResourceNotFound),
-                    StatusCode::INTERNAL_SERVER_ERROR => return Err(ContainerError::Unknown),
+                podman_list(&["--filter", &filter])
+            }
+        }
+    }
 
-                    _ => return Err(ContainerError::Unknown),
+    /// Lists the containers in the Podman pod `pod`, excluding the infra container
+    pub fn list_pod_containers(&self, pod: &str) -> Result<Vec<ContainerInfo>, ContainerError> {
+        match self {
+            #[cfg(feature = "docker")]
+            ContainerEngine::Docker(_) => {
+                _ = pod;
+                Err(ContainerError::Unsupported)
+            }
+            #[cfg(feature = "podman")]
+            ContainerEngine::Podman => {
+                let filter = format!("pod={pod}");
+
+                podman_list(&["--filter", &filter])
+            }
+        }
+    }
+
+    /// Gets the low-level information about a container that `docker inspect`/`podman inspect`
+    /// returns
+    fn inspect(&self, resource_id: &str) -> Result<serde_json::Value, ContainerError> {
+        match self {
+            #[cfg(feature = "docker")]
+            ContainerEngine::Docker(api) => {
+                api.get_json(&format!("containers/{resource_id}/json"), &[])
+            }
+            #[cfg(feature = "podman")]
+            ContainerEngine::Podman => {
+                let output = podman_output(&["inspect", "--type", "container", resource_id])?;
+                let inspect: serde_json::Value =
+                    serde_json::from_str(&output).map_err(|_| ContainerError::Unknown)?;
+
+                // podman always returns an array, even for a single container
+                match inspect {
+                    serde_json::Value::Array(mut containers) if !containers.is_empty() => {
+                        Ok(containers.swap_remove(0))
+                    }
+
+                    _ => Err(ContainerError::ResourceNotFound),
                 }
             }
+        }
+    }
+
+    /// Streams the logs of the resource specified by `resource_id`. Only the last `tail` lines are
+    /// returned if it is set, and the returned stream keeps producing new output if `follow` is set
+    pub fn logs(
+        &self,
+        resource_id: &str,
+        tail: Option<u32>,
+        follow: bool,
+    ) -> Result<LogStream, ContainerError> {
+        match self {
+            #[cfg(feature = "docker")]
+            ContainerEngine::Docker(api) => {
+                let tail = tail.map_or_else(|| "all".to_owned(), |tail| tail.to_string());
+                let query = [
+                    ("stdout", "1"),
+                    ("stderr", "1"),
+                    ("tail", tail.as_str()),
+                    ("follow", if follow { "1" } else { "0" }),
+                ];
+                let (response, socket) =
+                    api.get_stream(&format!("containers/{resource_id}/logs"), &query)?;
+
+                Ok(LogStream {
+                    reader: Box::new(DockerLogReader::new(response.into_body().into_reader())),
+                    closer: LogCloser(Arc::new(move || {
+                        _ = socket.shutdown(std::net::Shutdown::Both);
+                    })),
+                })
+            }
             #[cfg(feature = "podman")]
             ContainerEngine::Podman => {
                 use std::process::Stdio;
 
-                let status = Command::new(PODMAN_BIN_PATH)
-                    .args(["stop", resource_id])
-                    .stdout(Stdio::null())
-                    .stderr(Stdio::null())
-                    .status()
+                let mut command = Command::new(PODMAN_BIN_PATH);
+                command.arg("logs");
+                if let Some(tail) = tail {
+                    command.args(["--tail", &tail.to_string()]);
+                }
+                if follow {
+                    command.arg("--follow");
+                }
+
+                // `podman logs` doesn't fail for unknown containers until after it's spawned
+                podman_container_exists(resource_id)?;
+
+                // podman passes what the container wrote to stderr on to its own stderr, so both
+                // are merged into one stream, like Docker does
+                let (output, writer) = io::pipe().map_err(|_| ContainerError::Unknown)?;
+                let stderr = writer.try_clone().map_err(|_| ContainerError::Unknown)?;
+                let child = command
+                    .arg(resource_id)
+                    .stdin(Stdio::null())
+                    .stdout(writer)
+                    .stderr(stderr)
+                    .spawn()
                     .map_err(|_| ContainerError::EngineUnreachable)?;
+                // the write ends have to be gone for the output to end when podman exits
+                drop(command);
+
+                let child = Arc::new(Mutex::new(child));
+                let closer = LogCloser({
+                    let child = child.clone();
+                    // once podman is gone, the output ends
+                    Arc::new(move || {
+                        _ = child.lock().unwrap_or_else(PoisonError::into_inner).kill();
+                    })
+                });
+
+                Ok(LogStream {
+                    reader: Box::new(PodmanLogReader { child, output }),
+                    closer,
+                })
+            }
+        }
+    }
+}
+
+/// Lists containers with `podman ps`, passing it `args`. Infra containers of pods are skipped
+#[cfg(feature = "podman")]
+fn podman_list(args: &[&str]) -> Result<Vec<ContainerInfo>, ContainerError> {
+    let output = podman_output(&[&["ps", "--all", "--format", "json"], args].concat())?;
+    let containers: serde_json::Value =
+        serde_json::from_str(&output).map_err(|_| ContainerError::Unknown)?;
+    let containers = containers.as_array().ok_or(ContainerError::Unknown)?;
+
+    Ok(containers
+        .iter()
+        .filter(|container| container["IsInfra"].as_bool() != Some(true))
+        .filter_map(|container| {
+            Some(ContainerInfo {
+                name: container["Names"][0].as_str()?.to_owned(),
+                labels: parse_labels(&container["Labels"]),
+            })
+        })
+        .collect())
+}
 
-                if !status.success() {
-                    let status_code = status.code().ok_or(ContainerError::EngineUnreachable)?;
+/// Converts a JSON object of labels into a map. Anything that isn't a string is skipped
+fn parse_labels(labels: &serde_json::Value) -> HashMap<String, String> {
+    let Some(labels) = labels.as_object() else {
+        return HashMap::new();
+    };
 
-                    match status_code {
-                        CMD_NOT_FOUND_STATUS => return Err(ContainerError::EngineUnreachable),
+    labels
+        .iter()
+        .filter_map(|(key, value)| Some((key.clone(), value.as_str()?.to_owned())))
+        .collect()
+}
 
-                        _ => return Err(ContainerError::Unknown),
-                    }
+/// Sends `signal` to a container with `podman`, then waits for up to `timeout` until
+/// `is_stopped` says it's stopped. If it doesn't stop in time, it's killed
+#[cfg(feature = "podman")]
+fn stop_with_signal(
+    podman: impl Fn(&[&str]) -> Result<(), ContainerError>,
+    mut is_stopped: impl FnMut() -> Result<bool, ContainerError>,
+    resource_id: &str,
+    signal: &str,
+    timeout: Duration,
+) -> Result<(), ContainerError> {
+    podman(&["kill", "--signal", signal, resource_id])?;
+
+    let deadline = Instant::now() + timeout;
+    while !is_stopped()? {
+        if Instant::now() >= deadline {
+            // `podman kill` sends SIGKILL by default. It fails if the container has stopped in
+            // the meantime, which is fine
+            return podman(&["kill", resource_id]).or_else(|why| match is_stopped()? {
+                true => Ok(()),
+                false => Err(why),
+            });
+        }
+
+        thread::sleep(STOP_POLL_INTERVAL);
+    }
+
+    Ok(())
+}
+
+/// Whether a container in `state` has no processes that need stopping
+#[cfg(feature = "podman")]
+fn is_stopped(state: ContainerState) -> bool {
+    !matches!(
+        state,
+        ContainerState::Running
+            | ContainerState::Paused
+            | ContainerState::Restarting
+            | ContainerState::Stopping
+    )
+}
+
+/// Fails with [`ContainerError::ResourceNotFound`] unless Podman knows the container
+#[cfg(feature = "podman")]
+fn podman_container_exists(resource_id: &str) -> Result<(), ContainerError> {
+    use std::process::Stdio;
+
+    let status = Command::new(PODMAN_BIN_PATH)
+        .args(["container", "exists", resource_id])
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .status()
+        .map_err(|_| ContainerError::EngineUnreachable)?;
+
+    match status.code() {
+        Some(0) => Ok(()),
+        Some(1) => Err(ContainerError::ResourceNotFound),
+        Some(CMD_NOT_FOUND_STATUS) | None => Err(ContainerError::EngineUnreachable),
+
+        _ => Err(ContainerError::Unknown),
+    }
+}
+
+/// Runs a Podman command, discarding its output
+#[cfg(feature = "podman")]
+fn run_podman(args: &[&str]) -> Result<(), ContainerError> {
+    podman_output(args).map(|_| ())
+}
+
+/// Runs a Podman command and returns its standard output
+#[cfg(feature = "podman")]
+fn podman_output(args: &[&str]) -> Result<String, ContainerError> {
+    use std::process::Stdio;
+
+    let output = Command::new(PODMAN_BIN_PATH)
+        .args(args)
+        .stdin(Stdio::null())
+        .stderr(Stdio::null())
+        .output()
+        .map_err(|_| ContainerError::EngineUnreachable)?;
+
+    if !output.status.success() {
+        let status_code = output
+            .status
+            .code()
+            .ok_or(ContainerError::EngineUnreachable)?;
+
+        return match status_code {
+            CMD_NOT_FOUND_STATUS => Err(ContainerError::EngineUnreachable),
+
+            _ => Err(ContainerError::Unknown),
+        };
+    }
+
+    String::from_utf8(output.stdout).map_err(|_| ContainerError::Unknown)
+}
+
+/// Converts a container status reported by the engine into a [`ContainerState`]
+fn parse_state(status: &str) -> ContainerState {
+    match status {
+        // podman uses "configured" and "initialized" for containers that haven't been started yet
+        "created" | "configured" | "initialized" => ContainerState::Created,
+        "running" => ContainerState::Running,
+        "paused" => ContainerState::Paused,
+        "restarting" => ContainerState::Restarting,
+        "removing" => ContainerState::Removing,
+        "stopping" => ContainerState::Stopping,
+        // "stopped" is podman's name for exited
+        "exited" | "stopped" => ContainerState::Exited,
+        "dead" => ContainerState::Dead,
+
+        _ => ContainerState::Unknown,
+    }
+}
+
+/// Size of the header Docker puts in front of every frame of a multiplexed log stream
+#[cfg(feature = "docker")]
+const DOCKER_FRAME_HEADER_SIZE: usize = 8;
+
+/// Strips the stream multiplexing headers from Docker log output
+///
+/// Containers without a TTY have their stdout and stderr multiplexed into a single stream, where
+/// every frame starts with an 8-byte header (stream type, 3 bytes of padding, big endian `u32`
+/// frame size). Containers with a TTY send raw output, which is detected from the first header and
+/// passed through untouched.
+#[cfg(feature = "docker")]
+struct DockerLogReader<R> {
+    inner: R,
+    /// `None` until the first read, when it's determined whether the stream is multiplexed
+    multiplexed: Option<bool>,
+    /// Bytes of raw output that were read while detecting the stream type
+    pending: Vec<u8>,
+    /// Bytes left in the current frame
+    frame_remaining: usize,
+}
+
+#[cfg(feature = "docker")]
+impl<R: Read> DockerLogReader<R> {
+    fn new(inner: R) -> Self {
+        Self {
+            inner,
+            multiplexed: None,
+            pending: vec![],
+            frame_remaining: 0,
+        }
+    }
+
+    /// Reads the next frame header. Returns `None` on a clean EOF
+    fn read_header(&mut self) -> io::Result<Option<[u8; DOCKER_FRAME_HEADER_SIZE]>> {
+        let mut header = [0; DOCKER_FRAME_HEADER_SIZE];
+        let mut read = 0;
+        while read < header.len() {
+            match self.inner.read(&mut header[read..])? {
+                0 if read == 0 => return Ok(None),
+                0 => return Err(io::ErrorKind::UnexpectedEof.into()),
+                n => read += n,
+            }
+        }
+
+        Ok(Some(header))
+    }
+
+    fn is_frame_header(header: &[u8; DOCKER_FRAME_HEADER_SIZE]) -> bool {
+        // stdin, stdout or stderr, followed by padding
+        header[0] <= 2 && header[1..4] == [0, 0, 0]
+    }
+}
+
+#[cfg(feature = "docker")]
+impl<R: Read> Read for DockerLogReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if !self.pending.is_empty() {
+            let n = buf.len().min(self.pending.len());
+            buf[..n].copy_from_slice(&self.pending[..n]);
+            self.pending.drain(..n);
+
+            return Ok(n);
+        }
+
+        if self.multiplexed == Some(false) {
+            return self.inner.read(buf);
+        }
+
+        while self.frame_remaining == 0 {
+            let Some(header) = self.read_header()? else {
+                return Ok(0);
+            };
+
+            if self.multiplexed.is_none() {
+                let multiplexed = Self::is_frame_header(&header);
+                self.multiplexed = Some(multiplexed);
+
+                if !multiplexed {
+                    self.pending = header.to_vec();
+                    return self.read(buf);
                 }
             }
+
+            let mut size = [0; size_of::<u32>()];
+            size.copy_from_slice(&header[4..]);
+            self.frame_remaining = u32::from_be_bytes(size) as usize;
+        }
+
+        let len = buf.len().min(self.frame_remaining);
+        let n = self.inner.read(&mut buf[..len])?;
+        if n == 0 {
+            return Err(io::ErrorKind::UnexpectedEof.into());
         }
+        self.frame_remaining -= n;
+
+        Ok(n)
+    }
+}
+
+/// The logs of a resource, returned by [`ContainerEngine::logs`]
+pub struct LogStream {
+    reader: Box<dyn Read + Send>,
+    closer: LogCloser,
+}
+
+impl LogStream {
+    /// Returns a handle that ends the stream from another thread
+    pub fn closer(&self) -> LogCloser {
+        self.closer.clone()
+    }
+}
+
+impl Read for LogStream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.reader.read(buf)
+    }
+}
+
+/// Ends a [`LogStream`] from another thread, e.g. once nobody is waiting for the logs anymore. A
+/// read that's waiting for the container to write something returns right away
+#[derive(Clone)]
+pub struct LogCloser(Arc<dyn Fn() + Send + Sync>);
+
+impl LogCloser {
+    /// Ends the stream. Closing a stream that already ended does nothing
+    pub fn close(&self) {
+        (self.0)();
+    }
+}
+
+/// Reads the output of a `podman logs` process, killing the process when dropped
+#[cfg(feature = "podman")]
+struct PodmanLogReader {
+    child: Arc<Mutex<std::process::Child>>,
+    /// Standard output and standard error of the process
+    output: io::PipeReader,
+}
+
+#[cfg(feature = "podman")]
+impl Read for PodmanLogReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.output.read(buf)
+    }
+}
+
+#[cfg(feature = "podman")]
+impl Drop for PodmanLogReader {
+    fn drop(&mut self) {
+        let mut child = self.child.lock().unwrap_or_else(PoisonError::into_inner);
+        // the process has most likely exited already if this fails
+        _ = child.kill();
+        _ = child.wait();
+    }
+}
+
+#[cfg(all(test, feature = "podman"))]
+mod engine_tests {
+    use std::{cell::RefCell, time::Duration};
+
+    use super::stop_with_signal;
+
+    /// Runs `stop_with_signal` against a container that stops after being checked on `checks`
+    /// times, returning the podman commands it ran
+    fn stop(checks: usize, timeout: Duration) -> Vec<String> {
+        let commands = RefCell::new(vec![]);
+        let mut checked = 0;
+
+        let podman = |args: &[&str]| {
+            commands.borrow_mut().push(args.join(" "));
+            Ok(())
+        };
+        let is_stopped = || {
+            checked += 1;
+            Ok(checked > checks)
+        };
+        stop_with_signal(podman, is_stopped, "mc", "SIGINT", timeout).expect("stopping failed");
+
+        commands.into_inner()
+    }
+
+    #[test]
+    fn signal_then_kill() {
+        // a container that handles the signal is only sent the signal
+        assert_eq!(stop(1, Duration::from_secs(5)), ["kill --signal SIGINT mc"]);
 
-        Ok(())
+        // one that ignores it is killed once the timeout runs out
+        assert_eq!(
+            stop(usize::MAX, Duration::ZERO),
+            ["kill --signal SIGINT mc", "kill mc"]
+        );
     }
 }
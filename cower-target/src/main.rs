@@ -1,20 +1,60 @@
 use anyhow::anyhow;
-use cower_target::ContainerEngine;
-use native_tls::Identity;
+use cower_target::{
+    ContainerEngine, ContainerError,
+    config::{Config, GroupConfig, Operation},
+    discovery::{self, SharedConfig},
+    group::{self, GroupError},
+    idle::{IdleTracker, SharedTracker},
+};
 use std::{
     env, fs,
-    io::Read,
-    net::{TcpListener, TcpStream},
+    future::Future,
+    io::{self, Read},
     path::PathBuf,
-    sync::Arc,
+    sync::{Arc, Mutex, PoisonError, RwLock},
     thread::{self, JoinHandle},
+    time::{Duration, Instant},
 };
 
 use clap::Parser;
+use tokio::{
+    net::TcpListener,
+    runtime,
+    sync::{
+        Semaphore,
+        mpsc::{self, UnboundedSender},
+    },
+    task,
+};
 
-use cower_common::{Acceptor, prelude::*};
+use cower_common::{
+    Timeouts,
+    asynchronous::{AsyncAcceptor, AsyncConnection},
+    message::{CloseReason, ContainerState, ErrorCode, RequestId},
+    prelude::*,
+    tls::Identity,
+};
 
 const DEFAULT_BIND_ADDR: &str = "0.0.0.0:9989";
+#[cfg(feature = "websocket")]
+const DEFAULT_WEBSOCKET_PATH: &str = "/cower";
+/// Maximum amount of log output sent in a single message
+const LOG_CHUNK_SIZE: usize = 64 * 1024;
+/// How many requests a single client can have in flight at once. Requests past that are answered
+/// with an error, so that a client can't make the target spawn threads without end
+const MAX_REQUESTS_IN_FLIGHT: usize = 32;
+/// Clients that don't finish the TLS handshake in time are disconnected
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+/// Clients that don't answer pings or read replies in time are disconnected
+const IDLE_TIMEOUT: Duration = Duration::from_secs(60);
+/// How often resources are checked for having gone idle
+const IDLE_CHECK_INTERVAL: Duration = Duration::from_secs(10);
+const TIMEOUTS: Timeouts = Timeouts {
+    handshake: Some(HANDSHAKE_TIMEOUT),
+    read: None,
+    write: Some(IDLE_TIMEOUT),
+    idle: Some(IDLE_TIMEOUT),
+};
 
 #[derive(Parser)]
 #[command(about, long_about)]
@@ -30,61 +70,606 @@ struct Args {
     /// Password to identity file
     #[arg(long)]
     ident_pass: Option<String>,
+
+    /// Path to a PEM certificate chain. Used together with `--key-path` instead of an identity
+    /// file
+    #[arg(long, conflicts_with = "ident_path")]
+    cert_path: Option<PathBuf>,
+
+    /// Path to a PEM private key belonging to the certificate chain
+    #[arg(long, requires = "cert_path")]
+    key_path: Option<PathBuf>,
+
+    /// Path to the configuration file
+    #[arg(short, long)]
+    config: Option<PathBuf>,
+
+    /// Path to a Unix socket local clients can connect to without certificates. Who may connect
+    /// is set in the `[local]` table of the configuration
+    #[cfg(unix)]
+    #[arg(long)]
+    socket: Option<PathBuf>,
+
+    /// Socket address to accept WebSocket connections on, e.g. `0.0.0.0:443`. Useful when only
+    /// HTTPS gets through to the target
+    #[cfg(feature = "websocket")]
+    #[arg(long)]
+    websocket_addr: Option<String>,
+
+    /// Path WebSocket clients have to connect to, `/cower` by default. Requests for other paths
+    /// are refused
+    #[cfg(feature = "websocket")]
+    #[arg(long)]
+    websocket_path: Option<String>,
+}
+
+/// Where a connection comes from, which decides what the client may do
+#[derive(Clone, Copy)]
+enum Origin {
+    /// A client that connected over TLS. What it may do is decided by the resources
+    Network,
+    /// An authorized client that connected over the Unix socket. What it may do is decided by
+    /// the `[local]` table of the configuration
+    #[cfg(unix)]
+    Local,
+}
+
+fn error_code(error: &ContainerError) -> ErrorCode {
+    match error {
+        ContainerError::ResourceNotFound => ErrorCode::ResourceNotFound,
+        ContainerError::EngineUnreachable => ErrorCode::EngineUnreachable,
+        ContainerError::Unsupported => ErrorCode::Unsupported,
+
+        _ => ErrorCode::Unknown,
+    }
+}
+
+/// Sends the replies to one request back over the connection the request came from
+struct Replier {
+    request_id: RequestId,
+    replies: UnboundedSender<(RequestId, Message)>,
+    /// The runtime serving the connection
+    runtime: runtime::Handle,
+}
+
+impl Replier {
+    fn send(&self, message: Message) -> anyhow::Result<()> {
+        self.replies
+            .send((self.request_id, message))
+            .map_err(|_| anyhow!("Connection closed before the reply was sent"))
+    }
+
+    /// Whether the connection is gone, so that there's no one left to reply to
+    fn is_closed(&self) -> bool {
+        self.replies.is_closed()
+    }
+
+    /// Runs `on_close` once the connection is gone, unless the returned handle is aborted first
+    fn on_close(&self, on_close: impl FnOnce() + Send + 'static) -> task::AbortHandle {
+        let replies = self.replies.clone();
+        let task = self.runtime.spawn(async move {
+            replies.closed().await;
+            on_close();
+        });
+
+        task.abort_handle()
+    }
+}
+
+fn handle_group(
+    reply: &Replier,
+    engine: &ContainerEngine,
+    config: &Config,
+    group: &GroupConfig,
+    msg: &Message,
+) -> anyhow::Result<()> {
+    let result = group::resolve_members(engine, config, group).and_then(|members| match msg {
+        Message::StartMessage { .. } => {
+            group::start_group(engine, &members, Duration::from_secs(group.ready_timeout))
+        }
+        Message::StopMessage { .. } => group::stop_group(engine, &members),
+
+        _ => Err(ContainerError::Unsupported.into()),
+    });
+
+    // the state of the whole group is reported as the state of the member that was handled last
+    let state = result.and_then(|last| match last {
+        Some(member) => Ok(Some(engine.container_state(&member)?)),
+        None => Ok(None),
+    });
+
+    match state {
+        Ok(Some(state)) => reply.send(Message::StateMessage { state, ready: None })?,
+        Ok(None) => reply.send(Message::ErrorMessage {
+            code: ErrorCode::ResourceNotFound,
+        })?,
+        Err(why) => {
+            let code = match &why {
+                GroupError::Container(why) => error_code(why),
+
+                _ => ErrorCode::Unknown,
+            };
+            reply.send(Message::ErrorMessage { code })?;
+
+            return Err(why.into());
+        }
+    }
+
+    Ok(())
+}
+
+fn send_logs(
+    reply: &Replier,
+    engine: &ContainerEngine,
+    resource_id: &str,
+    tail: Option<u32>,
+    follow: bool,
+) -> anyhow::Result<()> {
+    let mut logs = match engine.logs(resource_id, tail, follow) {
+        Ok(logs) => logs,
+        Err(why) => {
+            reply.send(Message::ErrorMessage {
+                code: error_code(&why),
+            })?;
+            return Err(why.into());
+        }
+    };
+
+    // following waits for the container to write something, so the logs are closed as soon as the
+    // client disconnects instead of once the next line arrives
+    let closer = logs.closer();
+    let watcher = reply.on_close(move || closer.close());
+
+    let mut buf = vec![0; LOG_CHUNK_SIZE];
+    let result = loop {
+        let read = logs.read(&mut buf);
+        // the watcher closed the logs, which may look like the end of the logs or like an error
+        if reply.is_closed() {
+            break Ok(());
+        }
+
+        let sent = match read {
+            Ok(0) => break reply.send(Message::LogsEndMessage),
+            Ok(n) => reply.send(Message::LogDataMessage {
+                data: buf[..n].to_vec(),
+            }),
+            Err(why) => Err(why.into()),
+        };
+        if let Err(why) = sent {
+            break Err(why);
+        }
+    };
+    watcher.abort();
+
+    result
+}
+
+fn is_allowed(config: &Config, origin: Origin, resource: &str, operation: Operation) -> bool {
+    match origin {
+        Origin::Network => config.is_allowed(resource, operation),
+        #[cfg(unix)]
+        Origin::Local => config.local.allow.contains(&operation),
+    }
+}
+
+/// Lists the configured resources and groups whose status the client may see
+fn send_list(
+    reply: &Replier,
+    engine: &ContainerEngine,
+    config: &Config,
+    origin: Origin,
+) -> anyhow::Result<()> {
+    let resources = config
+        .resources
+        .iter()
+        .map(|(name, resource)| (name, resource.description.as_deref(), false));
+    let groups = config.groups.keys().map(|name| (name, None, true));
+    let mut entries: Vec<_> = resources
+        .chain(groups)
+        .filter(|(name, _, _)| is_allowed(config, origin, name, Operation::Status))
+        .collect();
+    entries.sort_unstable_by_key(|(name, _, _)| *name);
+
+    for (name, description, is_group) in entries {
+        // a container that's gone is still listed, just without a state
+        let state = (!is_group)
+            .then(|| engine.container_state(config.container_id(name)).ok())
+            .flatten();
+
+        reply.send(Message::ResourceMessage {
+            resource_name: name.clone(),
+            state,
+            description: description.map(str::to_owned),
+        })?;
+    }
+
+    reply.send(Message::ListEndMessage)?;
+
+    Ok(())
+}
+
+fn handle_request(
+    reply: &Replier,
+    engine: &ContainerEngine,
+    config: &Config,
+    idle: &Mutex<IdleTracker>,
+    origin: Origin,
+    msg: Message,
+) -> anyhow::Result<()> {
+    let (resource_name, operation) = match &msg {
+        Message::StartMessage { resource_name } => (resource_name, Operation::Start),
+        Message::StopMessage { resource_name, .. } => (resource_name, Operation::Stop),
+        Message::RestartMessage { resource_name, .. } => (resource_name, Operation::Restart),
+        Message::PauseMessage { resource_name } => (resource_name, Operation::Pause),
+        Message::UnpauseMessage { resource_name } => (resource_name, Operation::Unpause),
+        Message::LogsMessage { resource_name, .. } => (resource_name, Operation::Logs),
+        Message::StatusMessage { resource_name } => (resource_name, Operation::Status),
+        Message::ListMessage => return send_list(reply, engine, config, origin),
+
+        _ => return Err(anyhow!("Received unexpected message: {msg:?}")),
+    };
+
+    if !is_allowed(config, origin, resource_name, operation) {
+        reply.send(Message::ErrorMessage {
+            code: ErrorCode::PermissionDenied,
+        })?;
+        return Err(anyhow!("{operation:?} not allowed on {resource_name}"));
+    }
+
+    // status is polled by clients waiting for a resource, which doesn't mean anyone's using it
+    if operation != Operation::Status {
+        idle.lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .touch(resource_name, Instant::now());
+    }
+
+    if let Some(group) = config.groups.get(resource_name) {
+        return handle_group(reply, engine, config, group, &msg);
+    }
+    let resource_id = config.container_id(resource_name);
+
+    let result = match &msg {
+        Message::StartMessage { .. } => engine.wake_container(resource_id),
+        Message::StopMessage {
+            timeout, signal, ..
+        } => engine.stop_container(resource_id, *timeout, signal.as_deref()),
+        Message::RestartMessage { timeout, .. } => engine.restart_container(resource_id, *timeout),
+        Message::PauseMessage { .. } => engine.pause_container(resource_id),
+        Message::UnpauseMessage { .. } => engine.unpause_container(resource_id),
+        Message::StatusMessage { .. } => Ok(()),
+        Message::LogsMessage { tail, follow, .. } => {
+            return send_logs(reply, engine, resource_id, *tail, *follow);
+        }
+
+        _ => unreachable!("other messages are rejected above"),
+    };
+
+    let status = result.and_then(|_| {
+        let state = engine.container_state(resource_id)?;
+        // clients waiting for a resource poll its status, so that's where readiness is reported
+        let ready = match &msg {
+            Message::StatusMessage { .. } => Some(engine.is_ready(resource_id)?),
+            _ => None,
+        };
+
+        Ok((state, ready))
+    });
+
+    match status {
+        Ok((state, ready)) => reply.send(Message::StateMessage { state, ready })?,
+        Err(why) => {
+            reply.send(Message::ErrorMessage {
+                code: error_code(&why),
+            })?;
+            return Err(why.into());
+        }
+    }
+
+    Ok(())
 }
 
-fn spawn_handler_thread(
-    acceptor: Acceptor,
-    stream: TcpStream,
+/// Why the connection is closed after receiving fails with `error`. `None` if the connection is
+/// broken and there's no point in telling the client
+fn close_reason(error: &cower_common::Error) -> Option<CloseReason> {
+    use cower_common::Error;
+
+    match error {
+        Error::MesssageTooBig => Some(CloseReason::MessageTooBig),
+        Error::UnknownMessage
+        | Error::LengthMismatch
+        | Error::MalformedPayload
+        | Error::UnsupportedVersion(_)
+        | Error::MissingField(_)
+        | Error::InvalidUtf8(_) => Some(CloseReason::ProtocolError),
+
+        _ => None,
+    }
+}
+
+/// Handles every request coming over a connection on its own thread, so that a client can have
+/// several requests in flight at once. Replies are sent in the order they are produced, tagged
+/// with the ID of the request they belong to
+///
+/// The connection is established by `accept` on the new task, so that slow handshakes don't hold
+/// up other clients
+fn spawn_handler(
+    accept: impl Future<Output = anyhow::Result<AsyncConnection<Server>>> + Send + 'static,
+    origin: Origin,
     engine: Arc<ContainerEngine>,
-) -> JoinHandle<anyhow::Result<()>> {
-    thread::spawn(move || {
-        let mut stream = acceptor.accept(stream)?;
-        let msg = stream.receive()?;
+    config: SharedConfig,
+    idle: SharedTracker,
+) -> task::JoinHandle<anyhow::Result<()>> {
+    tokio::spawn(async move {
+        let mut conn = accept.await?;
+        let runtime = runtime::Handle::current();
+        let (replies, mut pending) = mpsc::unbounded_channel();
+        let in_flight = Arc::new(Semaphore::new(MAX_REQUESTS_IN_FLIGHT));
 
-        match msg {
-            Message::StartMessage { resource_name } => engine.start_container(&resource_name)?,
+        loop {
+            // receiving is cancel-safe, so a reply that's ready doesn't wait for the next request
+            let received = tokio::select! {
+                Some((request_id, message)) = pending.recv() => {
+                    conn.send_request(request_id, &message).await?;
+                    continue;
+                }
+                received = conn.receive_request() => received,
+            };
+
+            let (request_id, msg) = match received {
+                Ok(request) => request,
+                Err(cower_common::Error::IOFailure(why))
+                    if why.kind() == io::ErrorKind::UnexpectedEof =>
+                {
+                    // the client hung up, replies that are still being produced are dropped
+                    return Ok(());
+                }
+                Err(cower_common::Error::PeerTimedOut) => {
+                    println!("Client stopped responding, closing the connection");
+                    return Ok(());
+                }
+                Err(cower_common::Error::Closed { .. }) => return Ok(()),
+                Err(why) => {
+                    if let Some(reason) = close_reason(&why) {
+                        _ = conn.close(reason, Some(&why.to_string())).await;
+                    }
+                    return Err(why.into());
+                }
+            };
+
+            let Ok(permit) = in_flight.clone().try_acquire_owned() else {
+                println!("Client has too many requests in flight, refused {msg:?}");
+                let refusal = Message::ErrorMessage {
+                    code: ErrorCode::Unknown,
+                };
+                conn.send_request(request_id, &refusal).await?;
+                continue;
+            };
+
+            let reply = Replier {
+                request_id,
+                replies: replies.clone(),
+                runtime: runtime.clone(),
+            };
+            let engine = engine.clone();
+            let config = Arc::clone(&config.read().unwrap_or_else(PoisonError::into_inner));
+            let idle = idle.clone();
+            thread::spawn(move || {
+                if let Err(why) = handle_request(&reply, &engine, &config, &idle, origin, msg) {
+                    println!("Failed to handle request: {why}");
+                }
+                drop(permit);
+            });
         }
+    })
+}
+
+/// Suspends resources that went idle according to their idle action
+fn spawn_idle_thread(
+    engine: Arc<ContainerEngine>,
+    config: SharedConfig,
+    idle: SharedTracker,
+) -> JoinHandle<()> {
+    thread::spawn(move || {
+        loop {
+            thread::sleep(IDLE_CHECK_INTERVAL);
 
-        Ok(())
+            let config = Arc::clone(&config.read().unwrap_or_else(PoisonError::into_inner));
+            let resources = idle
+                .lock()
+                .unwrap_or_else(PoisonError::into_inner)
+                .idle(&config, Instant::now());
+
+            for (resource, action) in resources {
+                let container = config.container_id(&resource);
+                // resources that are already stopped or paused are left as they are
+                match engine.container_state(container) {
+                    Ok(ContainerState::Running) => {}
+                    Ok(_) => continue,
+                    Err(why) => {
+                        println!("Failed to check whether {resource} is idle: {why}");
+                        continue;
+                    }
+                }
+
+                match engine.suspend_container(container, action) {
+                    Ok(()) => println!("{resource} went idle, suspended it ({action:?})"),
+                    Err(why) => println!("Failed to suspend idle {resource}: {why}"),
+                }
+            }
+        }
     })
 }
 
-fn main() -> anyhow::Result<()> {
+/// Accepts a client connected over the Unix socket, refusing the ones that aren't authorized
+#[cfg(unix)]
+async fn accept_local(
+    stream: tokio::net::UnixStream,
+    config: SharedConfig,
+) -> anyhow::Result<AsyncConnection<Server>> {
+    let mut conn = AsyncConnection::accept_unix(stream)?;
+    conn.set_timeouts(TIMEOUTS);
+
+    let credentials = *conn
+        .peer_credentials()
+        .ok_or(anyhow!("Local client has no credentials"))?;
+    let config = Arc::clone(&config.read().unwrap_or_else(PoisonError::into_inner));
+    if !config.local.is_authorized(credentials.uid, credentials.gid) {
+        println!(
+            "Refused local connection from uid {} gid {}",
+            credentials.uid, credentials.gid
+        );
+        _ = conn.close(CloseReason::Unauthorized, None).await;
+
+        return Err(anyhow!("Local client isn't authorized"));
+    }
+
+    Ok(conn)
+}
+
+/// Serves local clients on the Unix socket at `path`
+#[cfg(unix)]
+fn spawn_local_listener(
+    path: PathBuf,
+    engine: Arc<ContainerEngine>,
+    config: SharedConfig,
+    idle: SharedTracker,
+) -> anyhow::Result<task::JoinHandle<()>> {
+    use std::os::unix::fs::FileTypeExt;
+
+    // a socket left behind by a target that didn't shut down cleanly prevents binding
+    if fs::symlink_metadata(&path).is_ok_and(|metadata| metadata.file_type().is_socket()) {
+        fs::remove_file(&path)?;
+    }
+    let listener = tokio::net::UnixListener::bind(&path)?;
+
+    Ok(tokio::spawn(async move {
+        loop {
+            match listener.accept().await {
+                Ok((stream, _)) => {
+                    let accept = accept_local(stream, config.clone());
+                    let (engine, config, idle) = (engine.clone(), config.clone(), idle.clone());
+                    _ = spawn_handler(accept, Origin::Local, engine, config, idle);
+                }
+                Err(why) => println!("Failed to accept local connection: {why}"),
+            }
+        }
+    }))
+}
+
+/// Serves clients connecting over WebSockets on `addr`
+#[cfg(feature = "websocket")]
+async fn spawn_websocket_listener(
+    addr: String,
+    path: String,
+    acceptor: AsyncAcceptor,
+    engine: Arc<ContainerEngine>,
+    config: SharedConfig,
+    idle: SharedTracker,
+) -> anyhow::Result<task::JoinHandle<()>> {
+    let listener = TcpListener::bind(addr).await?;
+    let path = Arc::new(path);
+
+    Ok(tokio::spawn(async move {
+        loop {
+            match listener.accept().await {
+                Ok((stream, _)) => {
+                    let acceptor = acceptor.clone();
+                    let path = path.clone();
+                    let accept = async move { Ok(acceptor.accept_websocket(stream, &path).await?) };
+                    let (engine, config, idle) = (engine.clone(), config.clone(), idle.clone());
+                    _ = spawn_handler(accept, Origin::Network, engine, config, idle);
+                }
+                Err(why) => println!("Failed to accept WebSocket connection: {why}"),
+            }
+        }
+    }))
+}
+
+#[tokio::main]
+async fn main() -> anyhow::Result<()> {
     let args = Args::parse();
     _ = args;
 
-    let ident_path = args
-        .ident_path
-        .or_else(|| env::var("COWER_IDENT_PATH").ok().map(PathBuf::from))
-        .ok_or(anyhow!("Missing path to identity file"))?;
+    let cert_path = args
+        .cert_path
+        .or_else(|| env::var("COWER_CERT_PATH").ok().map(PathBuf::from));
+    let key_path = args
+        .key_path
+        .or_else(|| env::var("COWER_KEY_PATH").ok().map(PathBuf::from));
+
+    let identity = if let (Some(cert_path), Some(key_path)) = (cert_path, key_path) {
+        Identity::from_pem(&fs::read(cert_path)?, &fs::read(key_path)?)?
+    } else {
+        let ident_path = args
+            .ident_path
+            .or_else(|| env::var("COWER_IDENT_PATH").ok().map(PathBuf::from))
+            .ok_or(anyhow!("Missing path to identity file"))?;
 
-    let ident_pass = args
-        .ident_pass
-        .or_else(|| env::var("COWER_IDENT_PASS").ok())
-        .ok_or(anyhow!("Missing password to identity file"))?;
+        let ident_pass = args
+            .ident_pass
+            .or_else(|| env::var("COWER_IDENT_PASS").ok())
+            .ok_or(anyhow!("Missing password to identity file"))?;
 
-    let mut ident_buf = vec![];
-    let mut identity = fs::File::open(ident_path)?;
-    identity.read_to_end(&mut ident_buf)?;
+        let mut ident_buf = vec![];
+        let mut identity = fs::File::open(ident_path)?;
+        identity.read_to_end(&mut ident_buf)?;
 
-    let identity = Identity::from_pkcs12(&ident_buf, &ident_pass)?;
+        Identity::from_pkcs12(&ident_buf, &ident_pass)?
+    };
 
-    let acceptor = Acceptor::new(identity)?;
-    let listener = TcpListener::bind(args.addr)?;
+    let mut acceptor = AsyncAcceptor::new(identity)?;
+    acceptor.set_timeouts(TIMEOUTS);
+    let listener = TcpListener::bind(args.addr).await?;
 
     let engine = ContainerEngine::try_detect().ok_or(anyhow!("No container engine found"))?;
     let engine = Arc::new(engine);
 
-    for stream in listener.incoming() {
-        match stream {
-            Ok(stream) => {
+    let config = args
+        .config
+        .or_else(|| env::var("COWER_CONFIG").ok().map(PathBuf::from))
+        .map(|path| Config::load(&path))
+        .transpose()?
+        .unwrap_or_default();
+
+    let shared_config: SharedConfig = Arc::new(RwLock::new(Arc::new(config.clone())));
+    if config.discovery.enabled {
+        _ = discovery::spawn_discovery_thread(engine.clone(), config, shared_config.clone());
+    }
+
+    let idle = SharedTracker::default();
+    _ = spawn_idle_thread(engine.clone(), shared_config.clone(), idle.clone());
+
+    #[cfg(unix)]
+    if let Some(path) = args
+        .socket
+        .or_else(|| env::var("COWER_SOCKET").ok().map(PathBuf::from))
+    {
+        _ = spawn_local_listener(path, engine.clone(), shared_config.clone(), idle.clone())?;
+    }
+
+    #[cfg(feature = "websocket")]
+    if let Some(addr) = args
+        .websocket_addr
+        .or_else(|| env::var("COWER_WEBSOCKET_ADDR").ok())
+    {
+        let path = args
+            .websocket_path
+            .or_else(|| env::var("COWER_WEBSOCKET_PATH").ok())
+            .unwrap_or_else(|| DEFAULT_WEBSOCKET_PATH.to_owned());
+        let (engine, config, idle) = (engine.clone(), shared_config.clone(), idle.clone());
+        _ = spawn_websocket_listener(addr, path, acceptor.clone(), engine, config, idle).await?;
+    }
+
+    loop {
+        match listener.accept().await {
+            Ok((stream, _)) => {
                 let acceptor = acceptor.clone();
+                let accept = async move { Ok(acceptor.accept(stream).await?) };
                 let engine = engine.clone();
-                _ = spawn_handler_thread(acceptor, stream, engine);
+                let config = shared_config.clone();
+                _ = spawn_handler(accept, Origin::Network, engine, config, idle.clone());
             }
             Err(why) => println!("Failed to accept connection: {why}"),
         }
     }
-
-    Ok(())
 }